@@ -0,0 +1,28 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * examples/snapshot.rs
+ * Print snapshots as JSON lines, which is also the replay file format.
+ *
+ *   cargo run --example snapshot -- [count] [interval_ms] > recording.ndjson
+ *   APPLE_SMI_REPLAY=recording.ndjson cargo run --example snapshot
+ */
+use anyhow::Result;
+use std::thread;
+use std::time::Duration;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let count: usize = args.next().map(|s| s.parse()).transpose()?.unwrap_or(1);
+    let interval_ms: u64 = args.next().map(|s| s.parse()).transpose()?.unwrap_or(1000);
+
+    let mut collector = apple_smi::Collector::new()?;
+    for i in 0..count {
+        if i > 0 {
+            thread::sleep(Duration::from_millis(interval_ms));
+        }
+        println!("{}", serde_json::to_string(&collector.snapshot()?)?);
+    }
+    Ok(())
+}
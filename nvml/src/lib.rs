@@ -0,0 +1,512 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * nvml/src/lib.rs
+ * NVML-shaped C API over the apple-smi collector, see include/nvml.h.
+ */
+// NVML's names and pointer contracts, documented in nvml.h
+#![allow(non_camel_case_types, non_snake_case, clippy::missing_safety_doc)]
+
+use apple_smi::error::{
+    CmdError, EXIT_DRIVER_NOT_LOADED, EXIT_INVALID_ARGUMENT, EXIT_NO_PERMISSION,
+    EXIT_NOT_SUPPORTED, EXIT_QUERY_FAILED,
+};
+use apple_smi::{Collector, Error, MetricSource, Snapshot, StatusKind};
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_uint, c_ulonglong};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub type nvmlReturn_t = c_uint;
+
+pub const NVML_SUCCESS: nvmlReturn_t = 0;
+pub const NVML_ERROR_UNINITIALIZED: nvmlReturn_t = 1;
+pub const NVML_ERROR_INVALID_ARGUMENT: nvmlReturn_t = 2;
+pub const NVML_ERROR_NOT_SUPPORTED: nvmlReturn_t = 3;
+pub const NVML_ERROR_NO_PERMISSION: nvmlReturn_t = 4;
+pub const NVML_ERROR_ALREADY_INITIALIZED: nvmlReturn_t = 5;
+pub const NVML_ERROR_NOT_FOUND: nvmlReturn_t = 6;
+pub const NVML_ERROR_INSUFFICIENT_SIZE: nvmlReturn_t = 7;
+pub const NVML_ERROR_DRIVER_NOT_LOADED: nvmlReturn_t = 9;
+pub const NVML_ERROR_TIMEOUT: nvmlReturn_t = 10;
+pub const NVML_ERROR_UNKNOWN: nvmlReturn_t = 999;
+
+pub const NVML_TEMPERATURE_GPU: c_uint = 0;
+pub const NVML_CLOCK_GRAPHICS: c_uint = 0;
+pub const NVML_CLOCK_SM: c_uint = 1;
+pub const NVML_VALUE_NOT_AVAILABLE: c_ulonglong = c_ulonglong::MAX;
+
+#[repr(C)]
+pub struct nvmlDevice_st {
+    _private: [u8; 0],
+}
+
+pub type nvmlDevice_t = *mut nvmlDevice_st;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct nvmlMemory_t {
+    pub total: c_ulonglong,
+    pub free: c_ulonglong,
+    pub used: c_ulonglong,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct nvmlUtilization_t {
+    pub gpu: c_uint,
+    pub memory: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct nvmlProcessInfo_t {
+    pub pid: c_uint,
+    pub usedGpuMemory: c_ulonglong,
+    pub gpuInstanceId: c_uint,
+    pub computeInstanceId: c_uint,
+}
+
+// Tools ask for one field at a time, don't collect again for each of them
+const MAX_AGE: Duration = Duration::from_secs(1);
+const MIB: u64 = 1024 * 1024;
+
+struct State {
+    collector: Collector,
+    snap: Snapshot,
+    taken: Instant,
+    // nvmlInit calls not yet matched by nvmlShutdown
+    refs: u32,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn state() -> MutexGuard<'static, Option<State>> {
+    // A panic can't cross the C boundary anyway, the data is still usable
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// exit_code already follows nvidia-smi, whose codes are NVML's, except for timeouts
+fn return_of(e: &Error) -> nvmlReturn_t {
+    if let Error::Cmd(CmdError::Timeout { .. }) = e {
+        return NVML_ERROR_TIMEOUT;
+    }
+    match e.exit_code() {
+        EXIT_INVALID_ARGUMENT => NVML_ERROR_INVALID_ARGUMENT,
+        EXIT_NOT_SUPPORTED => NVML_ERROR_NOT_SUPPORTED,
+        EXIT_NO_PERMISSION => NVML_ERROR_NO_PERMISSION,
+        EXIT_QUERY_FAILED => NVML_ERROR_NOT_FOUND,
+        EXIT_DRIVER_NOT_LOADED => NVML_ERROR_DRIVER_NOT_LOADED,
+        _ => NVML_ERROR_UNKNOWN,
+    }
+}
+
+// Why a metric is None, from its provenance: no root, a slow source or a failed one
+fn missing(provenance: &BTreeMap<String, MetricSource>, field: &str) -> nvmlReturn_t {
+    let Some(p) = provenance.get(field) else {
+        return NVML_ERROR_NOT_SUPPORTED;
+    };
+    match p.status {
+        StatusKind::Skipped if p.error.as_deref() == Some("needs root") => NVML_ERROR_NO_PERMISSION,
+        StatusKind::TimedOut => NVML_ERROR_TIMEOUT,
+        StatusKind::Failed => NVML_ERROR_UNKNOWN,
+        _ => NVML_ERROR_NOT_SUPPORTED,
+    }
+}
+
+// Handles are index + 1, so a null handle is never valid
+fn handle_of(index: usize) -> nvmlDevice_t {
+    (index + 1) as nvmlDevice_t
+}
+
+fn index_of(device: nvmlDevice_t) -> Option<usize> {
+    (device as usize).checked_sub(1)
+}
+
+/*
+ * Look up the device in a fresh enough snapshot and hand `f` its index. What `f`
+ * returns is written to `out`, which must not be null.
+ */
+unsafe fn query<T>(
+    device: nvmlDevice_t,
+    out: *mut T,
+    f: impl FnOnce(&Snapshot, usize) -> Result<T, nvmlReturn_t>,
+) -> nvmlReturn_t {
+    if out.is_null() {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    }
+    let mut guard = state();
+    let Some(st) = guard.as_mut() else {
+        return NVML_ERROR_UNINITIALIZED;
+    };
+    if st.taken.elapsed() >= MAX_AGE {
+        match st.collector.snapshot() {
+            Ok(snap) => {
+                st.snap = snap;
+                st.taken = Instant::now();
+            }
+            Err(e) => return return_of(&e),
+        }
+    }
+    let Some(index) = index_of(device).filter(|i| *i < st.snap.gpus.len()) else {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    };
+    match f(&st.snap, index) {
+        Ok(v) => {
+            unsafe { out.write(v) };
+            NVML_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+// The SMC's GPU sensors belong to the built-in GPU
+fn is_apple(snap: &Snapshot, index: usize) -> bool {
+    snap.gpus[index].unified_memory != Some(false)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nvmlInit_v2() -> nvmlReturn_t {
+    let mut guard = state();
+    if let Some(st) = guard.as_mut() {
+        st.refs += 1;
+        return NVML_SUCCESS;
+    }
+    let taken = Instant::now();
+    let result = Collector::new().and_then(|mut c| {
+        let snap = c.snapshot()?;
+        Ok((c, snap))
+    });
+    match result {
+        Ok((collector, snap)) => {
+            *guard = Some(State {
+                collector,
+                snap,
+                taken,
+                refs: 1,
+            });
+            NVML_SUCCESS
+        }
+        Err(e) => return_of(&e),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nvmlShutdown() -> nvmlReturn_t {
+    let mut guard = state();
+    match guard.as_mut() {
+        None => NVML_ERROR_UNINITIALIZED,
+        Some(st) if st.refs > 1 => {
+            st.refs -= 1;
+            NVML_SUCCESS
+        }
+        Some(_) => {
+            *guard = None;
+            NVML_SUCCESS
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn nvmlErrorString(result: nvmlReturn_t) -> *const c_char {
+    let s: &'static std::ffi::CStr = match result {
+        NVML_SUCCESS => c"Success",
+        NVML_ERROR_UNINITIALIZED => c"Uninitialized",
+        NVML_ERROR_INVALID_ARGUMENT => c"Invalid Argument",
+        NVML_ERROR_NOT_SUPPORTED => c"Not Supported",
+        NVML_ERROR_NO_PERMISSION => c"Insufficient Permissions",
+        NVML_ERROR_ALREADY_INITIALIZED => c"Already Initialized",
+        NVML_ERROR_NOT_FOUND => c"Not Found",
+        NVML_ERROR_INSUFFICIENT_SIZE => c"Insufficient Size",
+        NVML_ERROR_DRIVER_NOT_LOADED => c"Driver Not Loaded",
+        NVML_ERROR_TIMEOUT => c"Timeout",
+        _ => c"Unknown Error",
+    };
+    s.as_ptr()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetCount_v2(deviceCount: *mut c_uint) -> nvmlReturn_t {
+    if deviceCount.is_null() {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    }
+    match state().as_ref() {
+        Some(st) => {
+            unsafe { deviceCount.write(st.snap.gpus.len() as c_uint) };
+            NVML_SUCCESS
+        }
+        None => NVML_ERROR_UNINITIALIZED,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetHandleByIndex_v2(
+    index: c_uint,
+    device: *mut nvmlDevice_t,
+) -> nvmlReturn_t {
+    if device.is_null() {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    }
+    match state().as_ref() {
+        Some(st) if (index as usize) < st.snap.gpus.len() => {
+            unsafe { device.write(handle_of(index as usize)) };
+            NVML_SUCCESS
+        }
+        Some(_) => NVML_ERROR_INVALID_ARGUMENT,
+        None => NVML_ERROR_UNINITIALIZED,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetName(
+    device: nvmlDevice_t,
+    name: *mut c_char,
+    length: c_uint,
+) -> nvmlReturn_t {
+    let mut gpu_name = String::new();
+    let res = unsafe {
+        query(device, &mut gpu_name, |snap, i| {
+            Ok(snap.gpus[i].name.clone())
+        })
+    };
+    if res != NVML_SUCCESS {
+        return res;
+    }
+    if name.is_null() {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    }
+    // Room for the terminating NUL
+    let bytes = gpu_name.as_bytes();
+    if bytes.len() >= length as usize {
+        return NVML_ERROR_INSUFFICIENT_SIZE;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), name as *mut u8, bytes.len());
+        name.add(bytes.len()).write(0);
+    }
+    NVML_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetTemperature(
+    device: nvmlDevice_t,
+    sensorType: c_uint,
+    temp: *mut c_uint,
+) -> nvmlReturn_t {
+    if sensorType != NVML_TEMPERATURE_GPU {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    }
+    unsafe {
+        query(device, temp, |snap, i| {
+            if !is_apple(snap, i) {
+                return Err(NVML_ERROR_NOT_SUPPORTED);
+            }
+            let t = snap.sensors.gpu_temp_c;
+            t.map(|t| t.round() as c_uint)
+                .ok_or_else(|| missing(&snap.sensors.provenance, "gpu_temp_c"))
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetPowerUsage(
+    device: nvmlDevice_t,
+    power: *mut c_uint,
+) -> nvmlReturn_t {
+    // Milliwatts
+    unsafe {
+        query(device, power, |snap, i| {
+            let m = &snap.metrics[i];
+            m.power_w
+                .map(|w| (w * 1000.0).round() as c_uint)
+                .ok_or_else(|| missing(&m.provenance, "power_w"))
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetClockInfo(
+    device: nvmlDevice_t,
+    clockType: c_uint,
+    clock: *mut c_uint,
+) -> nvmlReturn_t {
+    // Apple GPUs have one clock domain, there is no memory or video clock to report
+    if clockType != NVML_CLOCK_GRAPHICS && clockType != NVML_CLOCK_SM {
+        return NVML_ERROR_NOT_SUPPORTED;
+    }
+    unsafe {
+        query(device, clock, |snap, i| {
+            let m = &snap.metrics[i];
+            m.frequency_mhz
+                .ok_or_else(|| missing(&m.provenance, "frequency_mhz"))
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetMemoryInfo(
+    device: nvmlDevice_t,
+    memory: *mut nvmlMemory_t,
+) -> nvmlReturn_t {
+    unsafe {
+        query(device, memory, |snap, i| {
+            let total = snap.gpus[i]
+                .memory_total_mib
+                .ok_or(NVML_ERROR_NOT_SUPPORTED)?
+                * MIB;
+            let m = &snap.metrics[i];
+            let used = m
+                .memory_used_mib
+                .ok_or_else(|| missing(&m.provenance, "memory_used_mib"))?
+                * MIB;
+            Ok(nvmlMemory_t {
+                total,
+                free: total.saturating_sub(used),
+                used,
+            })
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetUtilizationRates(
+    device: nvmlDevice_t,
+    utilization: *mut nvmlUtilization_t,
+) -> nvmlReturn_t {
+    unsafe {
+        query(device, utilization, |snap, i| {
+            let m = &snap.metrics[i];
+            let pct = m
+                .utilization_pct
+                .ok_or_else(|| missing(&m.provenance, "utilization_pct"))?;
+            Ok(nvmlUtilization_t {
+                gpu: pct.round().clamp(0.0, 100.0) as c_uint,
+                memory: 0,
+            })
+        })
+    }
+}
+
+/*
+ * NVML's two-call protocol: with *infoCount too small (0 and a null infos to ask),
+ * set it to the number of processes and fail with INSUFFICIENT_SIZE unless there
+ * are none.
+ */
+unsafe fn running_processes(
+    device: nvmlDevice_t,
+    infoCount: *mut c_uint,
+    infos: *mut nvmlProcessInfo_t,
+) -> nvmlReturn_t {
+    if infoCount.is_null() {
+        return NVML_ERROR_INVALID_ARGUMENT;
+    }
+    let mut pids = Vec::new();
+    let res = unsafe {
+        query(device, &mut pids, |snap, i| {
+            Ok(snap
+                .processes
+                .iter()
+                .filter(|p| p.gpu == i)
+                .map(|p| p.pid)
+                .collect::<Vec<_>>())
+        })
+    };
+    if res != NVML_SUCCESS {
+        return res;
+    }
+    let room = unsafe { infoCount.read() } as usize;
+    unsafe { infoCount.write(pids.len() as c_uint) };
+    if pids.is_empty() {
+        return NVML_SUCCESS;
+    }
+    if room < pids.len() || infos.is_null() {
+        return NVML_ERROR_INSUFFICIENT_SIZE;
+    }
+    for (n, pid) in pids.iter().enumerate() {
+        let info = nvmlProcessInfo_t {
+            pid: *pid,
+            usedGpuMemory: NVML_VALUE_NOT_AVAILABLE,
+            gpuInstanceId: c_uint::MAX,
+            computeInstanceId: c_uint::MAX,
+        };
+        unsafe { infos.add(n).write(info) };
+    }
+    NVML_SUCCESS
+}
+
+// powermetrics doesn't tell compute from graphics work, both lists are the same
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetComputeRunningProcesses_v3(
+    device: nvmlDevice_t,
+    infoCount: *mut c_uint,
+    infos: *mut nvmlProcessInfo_t,
+) -> nvmlReturn_t {
+    unsafe { running_processes(device, infoCount, infos) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nvmlDeviceGetGraphicsRunningProcesses_v3(
+    device: nvmlDevice_t,
+    infoCount: *mut c_uint,
+    infos: *mut nvmlProcessInfo_t,
+) -> nvmlReturn_t {
+    unsafe { running_processes(device, infoCount, infos) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apple_smi::error::EXIT_OTHER;
+
+    #[test]
+    fn errors_map_to_nvml_returns() {
+        let timeout = Error::Cmd(CmdError::Timeout {
+            program: String::from("system_profiler"),
+            timeout: Duration::from_secs(5),
+        });
+        assert_eq!(return_of(&timeout), NVML_ERROR_TIMEOUT);
+        let source = |exit_code| Error::Source {
+            backend: "system_profiler",
+            message: String::from("boom"),
+            exit_code,
+        };
+        for (code, ret) in [
+            (EXIT_INVALID_ARGUMENT, NVML_ERROR_INVALID_ARGUMENT),
+            (EXIT_NOT_SUPPORTED, NVML_ERROR_NOT_SUPPORTED),
+            (EXIT_NO_PERMISSION, NVML_ERROR_NO_PERMISSION),
+            (EXIT_QUERY_FAILED, NVML_ERROR_NOT_FOUND),
+            (EXIT_DRIVER_NOT_LOADED, NVML_ERROR_DRIVER_NOT_LOADED),
+            (EXIT_OTHER, NVML_ERROR_UNKNOWN),
+        ] {
+            assert_eq!(return_of(&source(code)), ret, "exit code {}", code);
+        }
+    }
+
+    #[test]
+    fn missing_values_say_why() {
+        let why = |status, error: Option<&str>| {
+            let mut p = BTreeMap::new();
+            let mut source = MetricSource::default();
+            source.status = status;
+            source.error = error.map(String::from);
+            p.insert(String::from("power_w"), source);
+            missing(&p, "power_w")
+        };
+        assert_eq!(
+            why(StatusKind::Skipped, Some("needs root")),
+            NVML_ERROR_NO_PERMISSION
+        );
+        assert_eq!(
+            why(StatusKind::Skipped, Some("Apple GPU only")),
+            NVML_ERROR_NOT_SUPPORTED
+        );
+        assert_eq!(why(StatusKind::TimedOut, None), NVML_ERROR_TIMEOUT);
+        assert_eq!(why(StatusKind::Failed, Some("boom")), NVML_ERROR_UNKNOWN);
+        assert_eq!(why(StatusKind::Ok, None), NVML_ERROR_NOT_SUPPORTED);
+        assert_eq!(
+            missing(&BTreeMap::new(), "power_w"),
+            NVML_ERROR_NOT_SUPPORTED
+        );
+    }
+}
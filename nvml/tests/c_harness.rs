@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * nvml/tests/c_harness.rs
+ * Build harness.c against the cdylib and run it on the M4 replay recording.
+ */
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// target/debug/deps, where cargo test puts the cdylib next to this test
+fn lib_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn c_client_reads_replay() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = lib_dir();
+    let harness = lib_dir.join("nvml-harness");
+
+    let cc = std::env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let built = Command::new(&cc)
+        .arg(manifest.join("tests/harness.c"))
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg("-lapple_smi_nvml")
+        .arg("-o")
+        .arg(&harness)
+        .status()
+        .unwrap_or_else(|e| panic!("can't run {}: {}", cc, e));
+    assert!(built.success(), "harness.c didn't build");
+
+    let out = Command::new(&harness)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env(
+            "APPLE_SMI_REPLAY",
+            manifest.join("../fixtures/replay/Mac16,10-M4.ndjson"),
+        )
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // First record of the recording, idle
+    let expected = [
+        "count: 1",
+        "gpu 0 name: Apple M4",
+        "gpu 0 temperature: 38 C",
+        "gpu 0 power: 41 mW",
+        "gpu 0 graphics clock: 338 MHz",
+        "gpu 0 memory: 1127 / 12124 MiB",
+        "gpu 0 utilization: 3%",
+        "gpu 0 processes: 1",
+        "gpu 0 process: 412",
+    ];
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, expected);
+}
@@ -0,0 +1,93 @@
+/* SPDX-License-Identifier: MIT */
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * nvml/tests/harness.c
+ * Drive libapple_smi_nvml the way an NVML client would, print what it reports.
+ * Run by tests/c_harness.rs against a replay recording.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include "nvml.h"
+
+static int failures = 0;
+
+#define EXPECT(call, want)                                                          \
+    do {                                                                            \
+        nvmlReturn_t r_ = (call);                                                   \
+        if (r_ != (want)) {                                                         \
+            fprintf(stderr, "%s:%d: %s returned %d (%s), want %d\n", __FILE__,      \
+                    __LINE__, #call, r_, nvmlErrorString(r_), (want));              \
+            failures++;                                                             \
+        }                                                                           \
+    } while (0)
+
+int main(void) {
+    unsigned int count = 0;
+    nvmlDevice_t dev;
+
+    /* Nothing works before init */
+    EXPECT(nvmlDeviceGetCount(&count), NVML_ERROR_UNINITIALIZED);
+    EXPECT(nvmlShutdown(), NVML_ERROR_UNINITIALIZED);
+
+    EXPECT(nvmlInit(), NVML_SUCCESS);
+    /* Reference counted, the second shutdown below is the real one */
+    EXPECT(nvmlInit(), NVML_SUCCESS);
+
+    EXPECT(nvmlDeviceGetCount(&count), NVML_SUCCESS);
+    printf("count: %u\n", count);
+    EXPECT(nvmlDeviceGetHandleByIndex(count, &dev), NVML_ERROR_INVALID_ARGUMENT);
+    EXPECT(nvmlDeviceGetCount(NULL), NVML_ERROR_INVALID_ARGUMENT);
+
+    for (unsigned int i = 0; i < count; i++) {
+        char name[NVML_DEVICE_NAME_BUFFER_SIZE];
+        unsigned int temp, power, clock;
+        nvmlMemory_t mem;
+        nvmlUtilization_t util;
+        nvmlProcessInfo_t procs[8];
+        unsigned int nprocs = 0;
+
+        EXPECT(nvmlDeviceGetHandleByIndex(i, &dev), NVML_SUCCESS);
+
+        EXPECT(nvmlDeviceGetName(dev, name, sizeof(name)), NVML_SUCCESS);
+        printf("gpu %u name: %s\n", i, name);
+        EXPECT(nvmlDeviceGetName(dev, name, 4), NVML_ERROR_INSUFFICIENT_SIZE);
+
+        EXPECT(nvmlDeviceGetTemperature(dev, NVML_TEMPERATURE_GPU, &temp), NVML_SUCCESS);
+        printf("gpu %u temperature: %u C\n", i, temp);
+
+        EXPECT(nvmlDeviceGetPowerUsage(dev, &power), NVML_SUCCESS);
+        printf("gpu %u power: %u mW\n", i, power);
+
+        EXPECT(nvmlDeviceGetClockInfo(dev, NVML_CLOCK_GRAPHICS, &clock), NVML_SUCCESS);
+        printf("gpu %u graphics clock: %u MHz\n", i, clock);
+        EXPECT(nvmlDeviceGetClockInfo(dev, NVML_CLOCK_MEM, &clock), NVML_ERROR_NOT_SUPPORTED);
+
+        EXPECT(nvmlDeviceGetMemoryInfo(dev, &mem), NVML_SUCCESS);
+        printf("gpu %u memory: %llu / %llu MiB\n", i, mem.used >> 20, mem.total >> 20);
+
+        EXPECT(nvmlDeviceGetUtilizationRates(dev, &util), NVML_SUCCESS);
+        printf("gpu %u utilization: %u%%\n", i, util.gpu);
+
+        /* Ask for the size first, then fetch */
+        EXPECT(nvmlDeviceGetComputeRunningProcesses(dev, &nprocs, NULL),
+               NVML_ERROR_INSUFFICIENT_SIZE);
+        printf("gpu %u processes: %u\n", i, nprocs);
+        nprocs = sizeof(procs) / sizeof(procs[0]);
+        EXPECT(nvmlDeviceGetComputeRunningProcesses(dev, &nprocs, procs), NVML_SUCCESS);
+        for (unsigned int p = 0; p < nprocs; p++) {
+            printf("gpu %u process: %u\n", i, procs[p].pid);
+        }
+    }
+
+    EXPECT(nvmlShutdown(), NVML_SUCCESS);
+    EXPECT(nvmlDeviceGetCount(&count), NVML_SUCCESS);
+    EXPECT(nvmlShutdown(), NVML_SUCCESS);
+    EXPECT(nvmlDeviceGetHandleByIndex(0, &dev), NVML_ERROR_UNINITIALIZED);
+
+    if (failures) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return EXIT_FAILURE;
+    }
+    return EXIT_SUCCESS;
+}
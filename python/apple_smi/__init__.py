@@ -0,0 +1,107 @@
+# SPDX-License-Identifier: MIT
+#
+# apple-smi: Apple Silicon System Management Interface
+# Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+# python/apple_smi/__init__.py
+# Python bindings over the apple-smi library.
+"""Apple Silicon GPU, sensor and process monitoring.
+
+    >>> import apple_smi
+    >>> snap = apple_smi.snapshot()
+    >>> snap.metrics[0].utilization_pct
+    >>> with apple_smi.energy() as e:
+    ...     train_one_epoch()
+    >>> e.gpu_joules
+
+Set APPLE_SMI_REPLAY to a recording (fixtures/replay) to run without the hardware.
+"""
+
+from __future__ import annotations
+
+import json
+import threading
+from pathlib import Path
+from typing import Any, Dict, Optional
+
+from . import _native
+from ._native import AppleSmiError
+from .model import (
+    Fan,
+    GpuInfo,
+    GpuMetrics,
+    MetricSource,
+    Process,
+    Sensors,
+    Snapshot,
+    SourceInfo,
+    SystemInfo,
+)
+from .sampling import EnergyMeter, Sampler
+
+__version__ = _native.__version__
+
+__all__ = [
+    "AppleSmiError",
+    "Collector",
+    "EnergyMeter",
+    "Fan",
+    "GpuInfo",
+    "GpuMetrics",
+    "MetricSource",
+    "Process",
+    "Sampler",
+    "Sensors",
+    "Snapshot",
+    "SourceInfo",
+    "SystemInfo",
+    "energy",
+    "snapshot",
+]
+
+
+class Collector:
+    """Takes snapshots, from the hardware or from a `replay` recording.
+
+    Processes need root (powermetrics). Sources that fail leave their fields
+    None and say why in `Snapshot.sources`; only a failing system_profiler
+    raises AppleSmiError.
+    """
+
+    def __init__(self, replay: Optional[str] = None, processes: bool = True, gpu_power: bool = True):
+        self._native = _native.Collector(replay, processes, gpu_power)
+        # One collection at a time, samplers may share a collector
+        self._lock = threading.Lock()
+
+    def snapshot_dict(self) -> Dict[str, Any]:
+        with self._lock:
+            return json.loads(self._native.snapshot_json())
+
+    def snapshot(self) -> Snapshot:
+        return Snapshot.from_dict(self.snapshot_dict())
+
+    @property
+    def replay_path(self) -> Optional[Path]:
+        return self._native.replay_path
+
+
+_default: Optional[Collector] = None
+_default_lock = threading.Lock()
+
+
+def _collector() -> Collector:
+    global _default
+    with _default_lock:
+        if _default is None:
+            _default = Collector()
+        return _default
+
+
+def snapshot(as_dict: bool = False):
+    """One snapshot from a shared collector, as a Snapshot or a plain dict."""
+    c = _collector()
+    return c.snapshot_dict() if as_dict else c.snapshot()
+
+
+def energy(interval: float = 0.2, collector: Optional[Collector] = None) -> EnergyMeter:
+    """`with apple_smi.energy() as e:` measures the block, see EnergyMeter."""
+    return EnergyMeter(interval, collector or _collector())
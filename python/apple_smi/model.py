@@ -0,0 +1,169 @@
+# SPDX-License-Identifier: MIT
+#
+# apple-smi: Apple Silicon System Management Interface
+# Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+# python/apple_smi/model.py
+# The snapshot model as dataclasses, same fields as the Rust library's.
+
+from __future__ import annotations
+
+import dataclasses
+from dataclasses import dataclass, field
+from typing import Any, Dict, List, Optional
+
+
+def _build(cls, d: Dict[str, Any]):
+    # Fields a newer library added are dropped, like #[non_exhaustive] on the Rust side
+    names = {f.name for f in dataclasses.fields(cls)}
+    return cls(**{k: v for k, v in d.items() if k in names})
+
+
+@dataclass
+class SystemInfo:
+    os_version: Optional[str] = None
+    model_name: Optional[str] = None
+    model_identifier: Optional[str] = None
+    chip: Optional[str] = None
+    memory_mib: Optional[int] = None
+    cpu_cores: Optional[int] = None
+    performance_cores: Optional[int] = None
+    efficiency_cores: Optional[int] = None
+
+
+@dataclass
+class GpuInfo:
+    index: int = 0
+    name: str = ""
+    bus: str = ""
+    metal_family: str = ""
+    core_count: Optional[int] = None
+    registry_id: Optional[int] = None
+    unified_memory: Optional[bool] = None
+    memory_total_mib: Optional[int] = None
+    displays: int = 0
+
+
+@dataclass
+class MetricSource:
+    # The backend, a key of Snapshot.sources
+    source: str = ""
+    # "ok", "skipped", "failed" or "timed_out"
+    status: str = ""
+    window_ms: Optional[int] = None
+    error: Optional[str] = None
+
+
+def _provenance(d: Dict[str, Any]) -> Dict[str, MetricSource]:
+    return {k: _build(MetricSource, v) for k, v in d.get("provenance", {}).items()}
+
+
+@dataclass
+class GpuMetrics:
+    index: int = 0
+    utilization_pct: Optional[float] = None
+    utilization_source: Optional[str] = None
+    frequency_mhz: Optional[int] = None
+    performance_state: Optional[int] = None
+    power_w: Optional[float] = None
+    memory_used_mib: Optional[int] = None
+    memory_allocated_mib: Optional[int] = None
+    recovery_count: Optional[int] = None
+    # Per field, where it came from and why it is None
+    provenance: Dict[str, MetricSource] = field(default_factory=dict)
+
+    @classmethod
+    def from_dict(cls, d: Dict[str, Any]) -> "GpuMetrics":
+        m = _build(cls, d)
+        m.provenance = _provenance(d)
+        return m
+
+
+@dataclass
+class Process:
+    gpu: int = 0
+    pid: int = 0
+    name: str = ""
+
+
+@dataclass
+class Fan:
+    index: int = 0
+    rpm: float = 0.0
+    min_rpm: Optional[float] = None
+    max_rpm: Optional[float] = None
+    target_rpm: Optional[float] = None
+    manual: Optional[bool] = None
+
+
+@dataclass
+class Sensors:
+    gpu_temp_c: Optional[float] = None
+    fans: List[Fan] = field(default_factory=list)
+    system_power_w: Optional[float] = None
+    dc_in_w: Optional[float] = None
+    battery_w: Optional[float] = None
+    provenance: Dict[str, MetricSource] = field(default_factory=dict)
+
+    @classmethod
+    def from_dict(cls, d: Dict[str, Any]) -> "Sensors":
+        s = _build(cls, d)
+        s.fans = [_build(Fan, f) for f in d.get("fans", [])]
+        s.provenance = _provenance(d)
+        return s
+
+
+@dataclass
+class SourceInfo:
+    # "ok", "skipped", "failed" or "timed_out"
+    status: str = ""
+    window_ms: Optional[int] = None
+    error: Optional[str] = None
+
+
+@dataclass
+class Snapshot:
+    timestamp_ms: int = 0
+    system: SystemInfo = field(default_factory=SystemInfo)
+    gpus: List[GpuInfo] = field(default_factory=list)
+    metrics: List[GpuMetrics] = field(default_factory=list)
+    processes: List[Process] = field(default_factory=list)
+    sensors: Sensors = field(default_factory=Sensors)
+    sources: Dict[str, SourceInfo] = field(default_factory=dict)
+
+    @classmethod
+    def from_dict(cls, d: Dict[str, Any]) -> "Snapshot":
+        return cls(
+            timestamp_ms=d.get("timestamp_ms", 0),
+            system=_build(SystemInfo, d.get("system", {})),
+            gpus=[_build(GpuInfo, g) for g in d.get("gpus", [])],
+            metrics=[GpuMetrics.from_dict(m) for m in d.get("metrics", [])],
+            processes=[_build(Process, p) for p in d.get("processes", [])],
+            sensors=Sensors.from_dict(d.get("sensors", {})),
+            sources={k: _build(SourceInfo, v) for k, v in d.get("sources", {}).items()},
+        )
+
+    def to_dict(self) -> Dict[str, Any]:
+        return dataclasses.asdict(self)
+
+    def records(self) -> List[Dict[str, Any]]:
+        """One flat row per GPU, ready for pandas.DataFrame."""
+        rows = []
+        for gpu, m in zip(self.gpus, self.metrics):
+            rows.append(
+                {
+                    "timestamp_ms": self.timestamp_ms,
+                    "gpu": gpu.index,
+                    "name": gpu.name,
+                    "utilization_pct": m.utilization_pct,
+                    "utilization_source": m.utilization_source,
+                    "frequency_mhz": m.frequency_mhz,
+                    "performance_state": m.performance_state,
+                    "power_w": m.power_w,
+                    "memory_used_mib": m.memory_used_mib,
+                    "memory_allocated_mib": m.memory_allocated_mib,
+                    "gpu_temp_c": self.sensors.gpu_temp_c,
+                    "system_power_w": self.sensors.system_power_w,
+                    "processes": sum(1 for p in self.processes if p.gpu == gpu.index),
+                }
+            )
+        return rows
@@ -0,0 +1,134 @@
+# SPDX-License-Identifier: MIT
+#
+# apple-smi: Apple Silicon System Management Interface
+# Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+# python/apple_smi/sampling.py
+# Background sampling and energy over a block of code.
+
+from __future__ import annotations
+
+import threading
+from typing import Any, Dict, List, Optional
+
+from .model import Snapshot
+
+
+class Sampler:
+    """Take snapshots on a background thread until stopped.
+
+    `records` is a list of flat dicts, one per GPU per snapshot, so
+    `pandas.DataFrame(sampler.records)` just works. A snapshot that fails is
+    skipped and kept in `errors`.
+    """
+
+    def __init__(self, interval: float = 1.0, collector=None):
+        if collector is None:
+            from . import _collector
+
+            collector = _collector()
+        self.interval = interval
+        self.collector = collector
+        self.snapshots: List[Snapshot] = []
+        self.errors: List[Exception] = []
+        self._stop = threading.Event()
+        self._thread: Optional[threading.Thread] = None
+
+    def _run(self) -> None:
+        while True:
+            try:
+                self.snapshots.append(self.collector.snapshot())
+            except Exception as e:  # keep sampling, like `events` does on a failed ioreg
+                self.errors.append(e)
+            if self._stop.wait(self.interval):
+                break
+
+    def start(self) -> "Sampler":
+        if self._thread is None:
+            self._stop.clear()
+            self._thread = threading.Thread(target=self._run, name="apple-smi-sampler", daemon=True)
+            self._thread.start()
+        return self
+
+    def stop(self) -> "Sampler":
+        if self._thread is not None:
+            self._stop.set()
+            self._thread.join()
+            self._thread = None
+        return self
+
+    def __enter__(self) -> "Sampler":
+        return self.start()
+
+    def __exit__(self, *exc) -> None:
+        self.stop()
+
+    @property
+    def records(self) -> List[Dict[str, Any]]:
+        return [row for snap in self.snapshots for row in snap.records()]
+
+
+def _trapezoid(points) -> float:
+    # (timestamp_ms, watts) -> joules; a replay starting over goes back in time, skip that step
+    joules = 0.0
+    for (t0, w0), (t1, w1) in zip(points, points[1:]):
+        dt = (t1 - t0) / 1000.0
+        if dt > 0:
+            joules += (w0 + w1) / 2.0 * dt
+    return joules
+
+
+class EnergyMeter:
+    """Energy used while the `with` block runs, from sampled power.
+
+    GPU power comes from IOReport and system power from the SMC, both are
+    integrated over the snapshot timestamps. Blocks shorter than two samples
+    leave the totals at 0.
+    """
+
+    def __init__(self, interval: float = 0.2, collector=None):
+        self._sampler = Sampler(interval, collector)
+        self.seconds = 0.0
+        self.gpu_joules = 0.0
+        self.system_joules = 0.0
+        self.samples = 0
+
+    def __enter__(self) -> "EnergyMeter":
+        self._sampler.start()
+        return self
+
+    def __exit__(self, *exc) -> None:
+        self._sampler.stop()
+        self.measure(self._sampler.snapshots)
+
+    def measure(self, snaps: List[Snapshot]) -> "EnergyMeter":
+        """Set the totals from snapshots in time order."""
+        self.samples = len(snaps)
+        # A snapshot without any GPU power reading is a gap, not 0 W
+        self.gpu_joules = _trapezoid(
+            [
+                (s.timestamp_ms, sum(m.power_w for m in s.metrics if m.power_w is not None))
+                for s in snaps
+                if any(m.power_w is not None for m in s.metrics)
+            ]
+        )
+        self.system_joules = _trapezoid(
+            [
+                (s.timestamp_ms, s.sensors.system_power_w)
+                for s in snaps
+                if s.sensors.system_power_w is not None
+            ]
+        )
+        self.seconds = sum(
+            max(b.timestamp_ms - a.timestamp_ms, 0) for a, b in zip(snaps, snaps[1:])
+        ) / 1000.0
+        return self
+
+    @property
+    def snapshots(self) -> List[Snapshot]:
+        return self._sampler.snapshots
+
+    def __repr__(self) -> str:
+        return (
+            f"EnergyMeter(seconds={self.seconds:.2f}, gpu_joules={self.gpu_joules:.3f}, "
+            f"system_joules={self.system_joules:.3f}, samples={self.samples})"
+        )
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * python/src/lib.rs
+ * apple_smi._native, the Collector as a Python class. Snapshots cross over as
+ * JSON, the apple_smi package turns them into dataclasses.
+ */
+use apple_smi::error;
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use std::path::PathBuf;
+
+create_exception!(
+    _native,
+    AppleSmiError,
+    PyRuntimeError,
+    "Collection failed. args are (message, exit code), the code is apple-smi's."
+);
+
+// "outer: cause: root cause", like anyhow's {:#}
+fn to_py(e: apple_smi::Error) -> PyErr {
+    let mut message = e.to_string();
+    let mut cause = std::error::Error::source(&e);
+    while let Some(c) = cause {
+        message = format!("{}: {}", message, c);
+        cause = c.source();
+    }
+    AppleSmiError::new_err((message, e.exit_code()))
+}
+
+#[pyclass(module = "apple_smi._native")]
+struct Collector {
+    inner: apple_smi::Collector,
+}
+
+#[pymethods]
+impl Collector {
+    #[new]
+    #[pyo3(signature = (replay = None, processes = true, gpu_power = true))]
+    fn new(replay: Option<PathBuf>, processes: bool, gpu_power: bool) -> PyResult<Self> {
+        let inner = match replay {
+            Some(path) => apple_smi::Collector::replay(path),
+            None => apple_smi::Collector::new(),
+        }
+        .map_err(to_py)?;
+        Ok(Self {
+            inner: inner.processes(processes).gpu_power(gpu_power),
+        })
+    }
+
+    /// One snapshot as JSON. Sampling takes a few hundred ms, other threads keep running.
+    fn snapshot_json(&mut self, py: Python<'_>) -> PyResult<String> {
+        let snap = py.detach(|| self.inner.snapshot()).map_err(to_py)?;
+        serde_json::to_string(&snap)
+            .map_err(|e| AppleSmiError::new_err((e.to_string(), error::EXIT_OTHER)))
+    }
+
+    #[getter]
+    fn replay_path(&self) -> Option<PathBuf> {
+        self.inner.replay_path().map(|p| p.to_path_buf())
+    }
+}
+
+#[pymodule]
+fn _native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Collector>()?;
+    m.add("AppleSmiError", m.py().get_type::<AppleSmiError>())?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
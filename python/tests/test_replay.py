@@ -0,0 +1,126 @@
+# SPDX-License-Identifier: MIT
+#
+# apple-smi: Apple Silicon System Management Interface
+# Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+# python/tests/test_replay.py
+# The bindings against the M4 replay recording: python -m unittest discover python/tests
+
+import os
+import tempfile
+import time
+import unittest
+from pathlib import Path
+
+FIXTURE = str(Path(__file__).resolve().parents[2] / "fixtures" / "replay" / "Mac16,10-M4.ndjson")
+os.environ["APPLE_SMI_REPLAY"] = FIXTURE
+
+import apple_smi  # noqa: E402
+
+
+class SnapshotTest(unittest.TestCase):
+    def test_first_record(self):
+        snap = apple_smi.Collector(replay=FIXTURE).snapshot()
+        self.assertIsInstance(snap, apple_smi.Snapshot)
+        self.assertEqual(snap.system.chip, "Apple M4")
+        self.assertEqual(snap.gpus[0].name, "Apple M4")
+        self.assertEqual(snap.gpus[0].core_count, 10)
+        self.assertEqual(snap.metrics[0].frequency_mhz, 338)
+        self.assertAlmostEqual(snap.metrics[0].power_w, 0.041, places=3)
+        self.assertEqual(snap.sensors.fans[0].min_rpm, 1000.0)
+        self.assertEqual(snap.processes, [apple_smi.Process(gpu=0, pid=412, name="WindowServer")])
+        self.assertEqual(snap.sources["powermetrics"].status, "ok")
+        power = snap.metrics[0].provenance["power_w"]
+        self.assertEqual((power.source, power.status, power.window_ms), ("ioreport", "ok", 200))
+        self.assertEqual(snap.sensors.provenance["fans"].source, "smc")
+
+    def test_replay_cycles(self):
+        c = apple_smi.Collector(replay=FIXTURE)
+        first = c.snapshot()
+        for _ in range(7):
+            c.snapshot()
+        self.assertEqual(c.snapshot(), first)
+        self.assertEqual(c.replay_path, Path(FIXTURE))
+
+    def test_module_snapshot_uses_env(self):
+        d = apple_smi.snapshot(as_dict=True)
+        self.assertEqual(d["system"]["model_identifier"], "Mac16,10")
+        self.assertEqual(apple_smi.Snapshot.from_dict(d).to_dict(), d)
+
+    def test_unknown_fields_ignored(self):
+        snap = apple_smi.Snapshot.from_dict({"timestamp_ms": 1, "gpus": [{"index": 0, "new_field": 1}]})
+        self.assertEqual(snap.gpus[0].index, 0)
+
+    def test_provenance_explains_none(self):
+        snap = apple_smi.Snapshot.from_dict(
+            {
+                "metrics": [
+                    {
+                        "index": 0,
+                        "power_w": None,
+                        "provenance": {
+                            "power_w": {"source": "ioreport", "status": "timed_out", "error": "timed out after 2000 ms"}
+                        },
+                    }
+                ]
+            }
+        )
+        why = snap.metrics[0].provenance["power_w"]
+        self.assertEqual(why, apple_smi.MetricSource("ioreport", "timed_out", None, "timed out after 2000 ms"))
+        self.assertEqual(snap.sensors.provenance, {})
+
+    def test_records(self):
+        rows = apple_smi.Collector(replay=FIXTURE).snapshot().records()
+        self.assertEqual(len(rows), 1)
+        self.assertEqual(rows[0]["gpu"], 0)
+        self.assertEqual(rows[0]["processes"], 1)
+        self.assertAlmostEqual(rows[0]["gpu_temp_c"], 38.4, places=3)
+
+    def test_bad_replay(self):
+        with tempfile.NamedTemporaryFile("w", suffix=".ndjson") as f:
+            f.write('{"timestamp_ms":\n')
+            f.flush()
+            with self.assertRaises(apple_smi.AppleSmiError) as cm:
+                apple_smi.Collector(replay=f.name)
+        # EXIT_QUERY_FAILED, unparsable input
+        self.assertEqual(cm.exception.args[1], 6)
+
+
+class SamplingTest(unittest.TestCase):
+    def test_sampler(self):
+        with apple_smi.Sampler(interval=0.01, collector=apple_smi.Collector(replay=FIXTURE)) as s:
+            time.sleep(0.1)
+        self.assertGreaterEqual(len(s.snapshots), 2)
+        self.assertEqual(s.errors, [])
+        self.assertEqual(len(s.records), len(s.snapshots))
+        self.assertEqual(s.records[1]["frequency_mhz"], 338)
+
+    def test_energy(self):
+        c = apple_smi.Collector(replay=FIXTURE)
+        # Exactly the first three records: 0 s, 1 s and 2 s
+        meter = apple_smi.EnergyMeter(collector=c).measure([c.snapshot() for _ in range(3)])
+        self.assertEqual(meter.samples, 3)
+        self.assertAlmostEqual(meter.seconds, 2.0)
+        self.assertAlmostEqual(meter.gpu_joules, (0.041 + 0.063) / 2 + (0.063 + 2.84) / 2, places=4)
+        self.assertAlmostEqual(meter.system_joules, (7.9 + 8.1) / 2 + (8.1 + 14.6) / 2, places=4)
+
+    def test_energy_skips_missing_power(self):
+        c = apple_smi.Collector(replay=FIXTURE)
+        snaps = [c.snapshot() for _ in range(3)]
+        for m in snaps[1].metrics:
+            m.power_w = None
+        snaps[1].sensors.system_power_w = None
+        meter = apple_smi.EnergyMeter(collector=c).measure(snaps)
+        # Both bridge the missing middle sample instead of reading it as 0 W
+        self.assertAlmostEqual(meter.gpu_joules, (0.041 + 2.84) / 2 * 2, places=4)
+        self.assertAlmostEqual(meter.system_joules, (7.9 + 14.6) / 2 * 2, places=4)
+
+    def test_energy_block(self):
+        with apple_smi.energy(interval=0.01, collector=apple_smi.Collector(replay=FIXTURE)) as e:
+            time.sleep(0.1)
+        self.assertGreaterEqual(e.samples, 2)
+        self.assertGreater(e.gpu_joules, 0.0)
+        self.assertGreater(e.system_joules, 0.0)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,492 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * api.rs
+ * The stable library API: a Collector that hands out Snapshots.
+ */
+use crate::collect::{self, Collected, Plan, Provenance, StatusKind};
+use crate::devices;
+use crate::error::{CmdError, Error, ParseError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Play back recorded snapshots instead of reading the hardware, like `APPLE_SMI_SMC_DUMP`.
+pub const REPLAY_ENV: &str = "APPLE_SMI_REPLAY";
+
+const MIB: u64 = 1024 * 1024;
+
+/// The machine, from system_profiler.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct SystemInfo {
+    // "26.2 (25C56)"
+    pub os_version: Option<String>,
+    // "Mac mini"
+    pub model_name: Option<String>,
+    // "Mac16,10"
+    pub model_identifier: Option<String>,
+    // "Apple M4"
+    pub chip: Option<String>,
+    pub memory_mib: Option<u64>,
+    pub cpu_cores: Option<u32>,
+    pub performance_cores: Option<u32>,
+    pub efficiency_cores: Option<u32>,
+}
+
+/// What a GPU is; doesn't change while the machine is up.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct GpuInfo {
+    pub index: usize,
+    pub name: String,
+    // "Built-in", "PCIe", ...
+    pub bus: String,
+    // "3" for Metal 3
+    pub metal_family: String,
+    pub core_count: Option<u32>,
+    // IORegistry entry ID, same as MTLDevice.registryID
+    pub registry_id: Option<u64>,
+    pub unified_memory: Option<bool>,
+    // Metal's recommended working set, the closest thing to a VRAM size
+    pub memory_total_mib: Option<u64>,
+    pub displays: usize,
+}
+
+/// One sample of what a GPU is doing, matched to `GpuInfo` by `index`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct GpuMetrics {
+    pub index: usize,
+    // percentage points
+    pub utilization_pct: Option<f64>,
+    // "powermetrics", "ioreg", "metal" (stage-utilization counters) or
+    // "metal-estimate" (probe queueing delay, approximate)
+    pub utilization_source: Option<String>,
+    pub frequency_mhz: Option<u32>,
+    // SW_Pn, 0 is the lowest
+    pub performance_state: Option<u32>,
+    // Whole-GPU power from IOReport, Apple GPUs only
+    pub power_w: Option<f32>,
+    // ioreg's "inuse_vram", None when the driver doesn't report it
+    pub memory_used_mib: Option<u64>,
+    pub memory_allocated_mib: Option<u64>,
+    // GPU hangs the driver recovered from since boot
+    pub recovery_count: Option<u64>,
+    // Per field above, where it came from and why it is None
+    #[serde(default)]
+    pub provenance: BTreeMap<String, MetricSource>,
+}
+
+/// A process using the GPU.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Process {
+    // powermetrics doesn't say which GPU, this is always the built-in one
+    pub gpu: usize,
+    pub pid: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Fan {
+    pub index: u8,
+    pub rpm: f32,
+    pub min_rpm: Option<f32>,
+    pub max_rpm: Option<f32>,
+    pub target_rpm: Option<f32>,
+    // The fan is under manual control (F?Md = 1)
+    pub manual: Option<bool>,
+}
+
+/// SMC readings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Sensors {
+    // Average of the GPU die sensors
+    pub gpu_temp_c: Option<f32>,
+    pub fans: Vec<Fan>,
+    pub system_power_w: Option<f32>,
+    pub dc_in_w: Option<f32>,
+    pub battery_w: Option<f32>,
+    // Per field above, all from the SMC
+    #[serde(default)]
+    pub provenance: BTreeMap<String, MetricSource>,
+}
+
+/// Where one metric came from, and when it is `None` why.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct MetricSource {
+    // The backend, a key of `Snapshot::sources`
+    pub source: String,
+    pub status: StatusKind,
+    pub window_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+// {"power_w": {"source": "ioreport", "status": "ok", ...}, ...}
+fn provenance_of(fields: &[(&[&str], &Provenance)]) -> BTreeMap<String, MetricSource> {
+    let mut map = BTreeMap::new();
+    for (names, p) in fields {
+        for name in *names {
+            let source = MetricSource {
+                source: p.source.to_string(),
+                status: p.status,
+                window_ms: p.window_ms,
+                error: p.error.clone(),
+            };
+            map.insert(name.to_string(), source);
+        }
+    }
+    map
+}
+
+// powermetrics and IOReport only know about the Apple GPU
+fn apple_only(p: Provenance, apple: bool) -> Provenance {
+    match apple {
+        true => p,
+        false => Provenance {
+            status: StatusKind::Skipped,
+            error: Some(String::from("Apple GPU only")),
+            ..p
+        },
+    }
+}
+
+/// How one data source fared, see the README on provenance.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct SourceInfo {
+    pub status: StatusKind,
+    pub window_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Everything one collection gathered.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Snapshot {
+    // Unix time
+    pub timestamp_ms: u64,
+    pub system: SystemInfo,
+    pub gpus: Vec<GpuInfo>,
+    pub metrics: Vec<GpuMetrics>,
+    pub processes: Vec<Process>,
+    pub sensors: Sensors,
+    // Keyed by source: system_profiler, powermetrics, ioreg, smc, metal, ioreport, processes
+    pub sources: BTreeMap<String, SourceInfo>,
+}
+
+// Recorded snapshots, handed out in order and round again
+#[derive(Debug, Clone)]
+struct Replay {
+    path: PathBuf,
+    snapshots: Vec<Snapshot>,
+    next: usize,
+}
+
+impl Replay {
+    // One snapshot per line (what the snapshot example writes), or a JSON array of them
+    fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            context: format!("can't read replay file {}", path.display()),
+            source,
+        })?;
+        let snapshots = if text.trim_start().starts_with('[') {
+            serde_json::from_str(&text).map_err(|e| ParseError::json("replay file", &text, &e))?
+        } else {
+            text.lines()
+                .enumerate()
+                .filter(|(_, l)| !l.trim().is_empty())
+                .map(|(no, l)| {
+                    serde_json::from_str(l)
+                        .map_err(|e| ParseError::new("replay file", no + 1, l, e.to_string()))
+                })
+                .collect::<Result<Vec<Snapshot>, _>>()?
+        };
+        if snapshots.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "no snapshots in {}",
+                path.display()
+            )));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            snapshots,
+            next: 0,
+        })
+    }
+
+    fn next(&mut self) -> Snapshot {
+        let snap = self.snapshots[self.next].clone();
+        self.next = (self.next + 1) % self.snapshots.len();
+        snap
+    }
+}
+
+/// Gathers snapshots from the hardware, or from a replay file.
+///
+/// Every source runs on its own thread with its own deadline, so a snapshot
+/// takes as long as the slowest source (about 200 ms with powermetrics or
+/// IOReport sampling) and a hung tool costs at most its timeout.
+#[derive(Debug, Clone)]
+pub struct Collector {
+    plan: Plan,
+    replay: Option<Replay>,
+}
+
+impl Collector {
+    fn with_replay(replay: Option<Replay>) -> Self {
+        Self {
+            plan: Plan {
+                powermetrics: true,
+                gpu_power: true,
+                processes: true,
+                adapter: false,
+                thermal: false,
+                metal_utilization: true,
+                displays: true,
+                serial: false,
+            },
+            replay,
+        }
+    }
+
+    /// Collects everything, or replays the file named by [`REPLAY_ENV`] if it is set.
+    pub fn new() -> Result<Self, Error> {
+        match std::env::var_os(REPLAY_ENV) {
+            Some(path) => Self::replay(path),
+            None => Ok(Self::with_replay(None)),
+        }
+    }
+
+    /// Plays back snapshots recorded as JSON lines, cycling through them.
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::with_replay(Some(Replay::load(path.as_ref())?)))
+    }
+
+    /// Whether to sample GPU power through IOReport, on by default.
+    pub fn gpu_power(mut self, on: bool) -> Self {
+        self.plan.gpu_power = on;
+        self
+    }
+
+    /// Whether to list GPU processes (powermetrics, root only), on by default.
+    pub fn processes(mut self, on: bool) -> Self {
+        self.plan.processes = on;
+        self
+    }
+
+    /// The replay file in use, if any.
+    pub fn replay_path(&self) -> Option<&Path> {
+        self.replay.as_ref().map(|r| r.path.as_path())
+    }
+
+    /// Take one snapshot. Fails only when system_profiler does; other sources
+    /// that fail leave their fields `None` and say why in `sources`.
+    pub fn snapshot(&mut self) -> Result<Snapshot, Error> {
+        if let Some(r) = &mut self.replay {
+            return Ok(r.next());
+        }
+        snapshot_of(&collect::collect(self.plan))
+    }
+
+    /// The machine and its GPUs, without sampling anything.
+    pub fn static_info(&mut self) -> Result<(SystemInfo, Vec<GpuInfo>), Error> {
+        if let Some(r) = &mut self.replay {
+            let snap = r.next();
+            return Ok((snap.system, snap.gpus));
+        }
+        let snap = snapshot_of(&collect::collect(Plan::default()))?;
+        Ok((snap.system, snap.gpus))
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+fn snapshot_of(c: &Collected) -> Result<Snapshot, Error> {
+    // Same rule as the CLI: without system_profiler there is no GPU list
+    let (root, os) = match (&c.syspf.value, &c.syspf.status) {
+        (Some((root, os)), _) => (root, os),
+        (None, collect::SourceStatus::TimedOut(t)) => {
+            return Err(CmdError::Timeout {
+                program: String::from("system_profiler"),
+                timeout: *t,
+            }
+            .into());
+        }
+        (None, collect::SourceStatus::Failed { message, exit_code }) => {
+            return Err(Error::Source {
+                backend: "system_profiler",
+                message: message.clone(),
+                exit_code: *exit_code,
+            });
+        }
+        (None, status) => {
+            return Err(Error::NotSupported(format!("system_profiler {}", status)));
+        }
+    };
+
+    let hw = os.hardware.first();
+    let (cpu_cores, performance_cores, efficiency_cores) =
+        hw.map(|h| h.cpu_cores()).unwrap_or_default();
+    let system = SystemInfo {
+        os_version: os
+            .system
+            .first()
+            .and_then(|s| non_empty(s.os_version_label())),
+        model_name: hw.and_then(|h| non_empty(&h.machine_name)),
+        model_identifier: hw.and_then(|h| non_empty(&h.machine_model)),
+        chip: hw.and_then(|h| non_empty(&h.chip_type)),
+        memory_mib: hw.and_then(|h| h.memory_mib()),
+        cpu_cores,
+        performance_cores,
+        efficiency_cores,
+    };
+
+    let no_metal = Vec::new();
+    let no_accels = Vec::new();
+    let cards = devices::match_cards(
+        &root.gpus,
+        c.metal.get().unwrap_or(&no_metal),
+        c.accels.get().unwrap_or(&no_accels),
+    );
+    let p = c.powermetrics.value.clone().unwrap_or_default();
+    let mut gpus = Vec::new();
+    let mut metrics = Vec::new();
+    for (index, card) in cards.iter().enumerate() {
+        let g = card.gpu;
+        let m = card.metal;
+        let vram = card.accel.map(|a| &a.vram);
+        let apple = m.is_none_or(|m| m.has_unified_memory);
+        gpus.push(GpuInfo {
+            index,
+            name: g.name.clone(),
+            bus: g.bus_label().to_string(),
+            metal_family: g.metal_lable().to_string(),
+            core_count: card.core_count(),
+            registry_id: card
+                .accel
+                .and_then(|a| a.registry_id)
+                .or(m.map(|m| m.registry_id)),
+            unified_memory: m.map(|m| m.has_unified_memory),
+            memory_total_mib: m.map(|m| m.recommended_max_working_set_size / MIB),
+            displays: g.displays.len(),
+        });
+        let util = card.utilization(&p, c.metal_utilization.get().map_or(&[][..], |v| v));
+        let provenance = provenance_of(&[
+            (
+                &["utilization_pct", "utilization_source"],
+                &c.utilization_provenance(util.as_ref().map(|u| u.source)),
+            ),
+            (
+                &["frequency_mhz", "performance_state"],
+                &apple_only(c.powermetrics.provenance(), apple),
+            ),
+            (&["power_w"], &apple_only(c.gpu_power.provenance(), apple)),
+            (
+                &["memory_used_mib", "memory_allocated_mib", "recovery_count"],
+                &c.accels.provenance(),
+            ),
+        ]);
+        metrics.push(GpuMetrics {
+            index,
+            utilization_pct: util.as_ref().map(|u| u.pct),
+            utilization_source: util.map(|u| u.source.to_string()),
+            // powermetrics and IOReport only know about the Apple GPU
+            frequency_mhz: p.gpu_hw_freq.filter(|_| apple),
+            performance_state: p.gpu_sw_state.filter(|_| apple).map(|s| s as u32),
+            power_w: c.gpu_power.get().copied().filter(|_| apple),
+            // Metal's currentAllocatedSize only counts our own allocations, so ioreg or nothing
+            memory_used_mib: vram.and_then(|v| v.inuse_vram),
+            memory_allocated_mib: vram.and_then(|v| v.alloc_vram),
+            recovery_count: card.accel.and_then(|a| a.recovery_count),
+            provenance,
+        });
+    }
+
+    let processes = c
+        .processes
+        .get()
+        .map(|procs| {
+            procs
+                .iter()
+                .map(|p| Process {
+                    gpu: 0,
+                    pid: p.pid,
+                    name: p.name.clone(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let smc_fields = provenance_of(&[(
+        &[
+            "gpu_temp_c",
+            "fans",
+            "system_power_w",
+            "dc_in_w",
+            "battery_w",
+        ],
+        &c.smc.provenance(),
+    )]);
+    let mut sensors = match c.smc.get() {
+        Some(s) => Sensors {
+            gpu_temp_c: s.gpu_temp_avg,
+            fans: s
+                .fans
+                .iter()
+                .map(|f| Fan {
+                    index: f.index,
+                    rpm: f.rpm,
+                    min_rpm: f.min_rpm,
+                    max_rpm: f.max_rpm,
+                    target_rpm: f.target_rpm,
+                    manual: f.mode.map(|m| m == 1),
+                })
+                .collect(),
+            system_power_w: s.power.system_total,
+            dc_in_w: s.power.dc_in,
+            battery_w: s.power.battery,
+            provenance: BTreeMap::new(),
+        },
+        None => Sensors::default(),
+    };
+    sensors.provenance = smc_fields;
+
+    let sources = c
+        .provenance()
+        .into_iter()
+        .map(|(key, p)| {
+            let info = SourceInfo {
+                status: p.status,
+                window_ms: p.window_ms,
+                error: p.error,
+            };
+            (key.to_string(), info)
+        })
+        .collect();
+
+    Ok(Snapshot {
+        timestamp_ms: now_ms(),
+        system,
+        gpus,
+        metrics,
+        processes,
+        sensors,
+        sources,
+    })
+}
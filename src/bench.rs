@@ -0,0 +1,499 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * bench.rs
+ * Metal compute micro-benchmark with frequency / power / temperature sampling.
+ */
+use anyhow::{Result, anyhow};
+use apple_smi::error::Error;
+use apple_smi::ioreport;
+use apple_smi::mtlapi;
+use apple_smi::pwrmtcs;
+use apple_smi::smc;
+use apple_smi::thermal::{self, ThermalPressure};
+use apple_smi::utils;
+use objc2::rc::Retained;
+use objc2::runtime::ProtocolObject;
+use objc2_foundation::NSString;
+use objc2_metal::{
+    MTLBuffer, MTLCommandBuffer, MTLCommandEncoder, MTLCommandQueue, MTLComputeCommandEncoder,
+    MTLComputePipelineState, MTLCreateSystemDefaultDevice, MTLDevice, MTLLibrary,
+    MTLResourceOptions, MTLSize,
+};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const KERNELS: &str = r#"
+#include <metal_stdlib>
+using namespace metal;
+
+// 4 independent chains keep the FMA pipes busy, 8 flops per iteration
+kernel void fma_loop(device float *out [[buffer(0)]],
+                     uint id [[thread_position_in_grid]]) {
+    float b = 1.0001f, c = -0.0001f;
+    float x0 = float(id) * 1e-7f, x1 = x0 + 1.0f, x2 = x0 + 2.0f, x3 = x0 + 3.0f;
+    for (int i = 0; i < FMA_ITERS; i++) {
+        x0 = fma(x0, b, c); x1 = fma(x1, b, c);
+        x2 = fma(x2, b, c); x3 = fma(x3, b, c);
+    }
+    out[id] = x0 + x1 + x2 + x3;
+}
+
+kernel void copy_vec(device const float4 *src [[buffer(0)]],
+                     device float4 *dst [[buffer(1)]],
+                     uint id [[thread_position_in_grid]]) {
+    dst[id] = src[id];
+}
+"#;
+
+const FMA_ITERS: u64 = 1024;
+const FMA_THREADS: u64 = 1 << 20;
+// Two buffers this size, large enough to defeat the SLC
+const COPY_BYTES: u64 = 256 * 1024 * 1024;
+// GFLOPS of the last quarter, or the last clock, below this share of the first counts as throttling
+const THROTTLE_RATIO: f64 = 0.9;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub t_s: f64,
+    pub freq_mhz: Option<u32>,
+    pub gpu_power_w: Option<f32>,
+    pub gpu_temp_c: Option<f32>,
+    pub thermal_pressure: Option<&'static str>,
+    #[serde(skip)]
+    pressure: Option<ThermalPressure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Phase {
+    pub seconds: f64,
+    // GFLOPS for fma, GB/s for copy
+    pub throughput: f64,
+    pub per_second: Vec<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Throttle {
+    pub detected: bool,
+    // last quarter / first quarter FMA throughput
+    pub ratio: Option<f64>,
+    pub freq_first_mhz: Option<u32>,
+    pub freq_last_mhz: Option<u32>,
+    pub worst_thermal_pressure: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub device: String,
+    pub architecture: Option<String>,
+    pub apple_smi_version: &'static str,
+    pub duration_s: u64,
+    pub fma: Phase,
+    pub copy: Phase,
+    pub gpu_power_avg_w: Option<f64>,
+    pub gflops_per_w: Option<f64>,
+    pub gpu_temp_max_c: Option<f32>,
+    pub throttle: Throttle,
+    pub samples: Vec<Sample>,
+}
+
+fn ns_err(e: Retained<objc2_foundation::NSError>) -> anyhow::Error {
+    anyhow!("{}", e.localizedDescription())
+}
+
+struct Kernels {
+    queue: Retained<ProtocolObject<dyn MTLCommandQueue>>,
+    fma: Retained<ProtocolObject<dyn MTLComputePipelineState>>,
+    copy: Retained<ProtocolObject<dyn MTLComputePipelineState>>,
+}
+
+impl Kernels {
+    fn build(device: &ProtocolObject<dyn MTLDevice>) -> Result<Self> {
+        let source = KERNELS.replace("FMA_ITERS", &FMA_ITERS.to_string());
+        let lib = device
+            .newLibraryWithSource_options_error(&NSString::from_str(&source), None)
+            .map_err(ns_err)?;
+        let pipeline = |name: &str| {
+            let f = lib
+                .newFunctionWithName(&NSString::from_str(name))
+                .ok_or_else(|| anyhow!("kernel {} not found", name))?;
+            device
+                .newComputePipelineStateWithFunction_error(&f)
+                .map_err(ns_err)
+        };
+        Ok(Self {
+            fma: pipeline("fma_loop")?,
+            copy: pipeline("copy_vec")?,
+            queue: device
+                .newCommandQueue()
+                .ok_or_else(|| anyhow!("no command queue"))?,
+        })
+    }
+
+    // Run one dispatch to completion
+    fn dispatch(
+        &self,
+        pso: &ProtocolObject<dyn MTLComputePipelineState>,
+        buffers: &[&ProtocolObject<dyn MTLBuffer>],
+        threads: u64,
+    ) -> Result<()> {
+        let cb = self
+            .queue
+            .commandBuffer()
+            .ok_or_else(|| anyhow!("no command buffer"))?;
+        let enc = cb
+            .computeCommandEncoder()
+            .ok_or_else(|| anyhow!("no compute encoder"))?;
+        enc.setComputePipelineState(pso);
+        for (i, b) in buffers.iter().enumerate() {
+            unsafe { enc.setBuffer_offset_atIndex(Some(b), 0, i) };
+        }
+        let size = |w: u64| MTLSize {
+            width: w as usize,
+            height: 1,
+            depth: 1,
+        };
+        enc.dispatchThreads_threadsPerThreadgroup(
+            size(threads),
+            size(pso.maxTotalThreadsPerThreadgroup() as u64),
+        );
+        enc.endEncoding();
+        cb.commit();
+        cb.waitUntilCompleted();
+        match cb.error() {
+            Some(e) => Err(ns_err(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Repeat `step` for `secs`, bucketing the work units done per second
+fn run_phase(secs: u64, work_per_step: f64, mut step: impl FnMut() -> Result<()>) -> Result<Phase> {
+    let start = Instant::now();
+    let limit = Duration::from_secs(secs);
+    let mut per_second = vec![0.0; secs.max(1) as usize];
+    while start.elapsed() < limit && !utils::stop_requested() {
+        step()?;
+        let bucket = (start.elapsed().as_secs() as usize).min(per_second.len() - 1);
+        per_second[bucket] += work_per_step;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    let total: f64 = per_second.iter().sum();
+    Ok(Phase {
+        seconds,
+        throughput: if seconds > 0.0 { total / seconds } else { 0.0 },
+        per_second,
+    })
+}
+
+// One sample per second until told to stop
+fn sampler(stop: Arc<AtomicBool>, out: Arc<Mutex<Vec<Sample>>>) {
+    let start = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        let tick = Instant::now();
+        // powermetrics and IOReport both block ~200ms
+        let freq_mhz = if utils::is_root() {
+            pwrmtcs::run_pwrmtcs().ok().and_then(|p| p.gpu_hw_freq)
+        } else {
+            None
+        };
+        let pressure = thermal::thermal_pressure().ok();
+        let sample = Sample {
+            t_s: (start.elapsed().as_secs_f64() * 10.0).round() / 10.0,
+            freq_mhz,
+            gpu_power_w: ioreport::sample_gpu_power_once(200).ok().flatten(),
+            gpu_temp_c: smc::read_smc_snapshot().ok().and_then(|s| s.gpu_temp_avg),
+            thermal_pressure: pressure.map(|p| p.label()),
+            pressure,
+        };
+        out.lock().unwrap().push(sample);
+        while tick.elapsed() < Duration::from_secs(1) && !stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+fn mean(v: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, n) = v.fold((0.0, 0), |(s, n), x| (s + x, n + 1));
+    (n > 0).then(|| sum / n as f64)
+}
+
+fn throttle_of(fma: &Phase, fma_samples: &[Sample], samples: &[Sample]) -> Throttle {
+    // Drop the last bucket, it is usually partial
+    let full = &fma.per_second[..fma.per_second.len().saturating_sub(1)];
+    let q = (full.len() / 4).max(1);
+    let ratio = match (
+        mean(full.iter().take(q).copied()),
+        mean(full.iter().rev().take(q).copied()),
+    ) {
+        (Some(first), Some(last)) if full.len() >= 2 && first > 0.0 => Some(last / first),
+        _ => None,
+    };
+    let worst = samples.iter().filter_map(|s| s.pressure).max();
+    let freq_first_mhz = fma_samples.iter().find_map(|s| s.freq_mhz);
+    let freq_last_mhz = fma_samples.iter().rev().find_map(|s| s.freq_mhz);
+    let clock_dropped = match (freq_first_mhz, freq_last_mhz) {
+        (Some(first), Some(last)) => (last as f64) < first as f64 * THROTTLE_RATIO,
+        _ => false,
+    };
+    Throttle {
+        detected: ratio.is_some_and(|r| r < THROTTLE_RATIO)
+            || clock_dropped
+            || worst.is_some_and(|p| p >= ThermalPressure::Heavy),
+        ratio: ratio.map(|r| (r * 1000.0).round() / 1000.0),
+        freq_first_mhz,
+        freq_last_mhz,
+        worst_thermal_pressure: worst.map(|p| p.label()),
+    }
+}
+
+pub fn run(duration_s: u64, json: bool) -> Result<()> {
+    let device = MTLCreateSystemDefaultDevice()
+        .ok_or_else(|| Error::NoDevice(String::from("no Metal device available")))?;
+    let info = mtlapi::device_info_of(&device);
+    let kernels = Kernels::build(&device)?;
+
+    let shared = MTLResourceOptions::StorageModeShared;
+    let private = MTLResourceOptions::StorageModePrivate;
+    let alloc = |len: u64, opts| {
+        device
+            .newBufferWithLength_options(len as usize, opts)
+            .ok_or_else(|| anyhow!("can't allocate {} MiB", len >> 20))
+    };
+    let fma_out = alloc(FMA_THREADS * 4, shared)?;
+    let src = alloc(COPY_BYTES, private)?;
+    let dst = alloc(COPY_BYTES, private)?;
+
+    if !json {
+        println!(
+            "Benchmarking {} for {}s ({}s FP32 FMA, then {}s memory copy)...",
+            info.name,
+            duration_s,
+            duration_s.div_ceil(2),
+            duration_s / 2
+        );
+    }
+
+    utils::install_stop_handler();
+    let stop = Arc::new(AtomicBool::new(false));
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let handle = {
+        let (stop, samples) = (stop.clone(), samples.clone());
+        thread::spawn(move || sampler(stop, samples))
+    };
+
+    let t0 = Instant::now();
+    let mut fma_end = 0.0;
+    let phases = (|| -> Result<(Phase, Phase)> {
+        let flops = (FMA_THREADS * FMA_ITERS * 8) as f64 / 1e9;
+        let fma = run_phase(duration_s.div_ceil(2), flops, || {
+            kernels.dispatch(&kernels.fma, &[&fma_out], FMA_THREADS)
+        })?;
+        fma_end = t0.elapsed().as_secs_f64();
+        // Read + write
+        let bytes = (2 * COPY_BYTES) as f64 / 1e9;
+        let copy = run_phase(duration_s / 2, bytes, || {
+            kernels.dispatch(&kernels.copy, &[&src, &dst], COPY_BYTES / 16)
+        })?;
+        Ok((fma, copy))
+    })();
+
+    // Stop the sampler even if a kernel failed
+    stop.store(true, Ordering::Relaxed);
+    let _ = handle.join();
+    let (fma, copy) = phases?;
+    let samples = samples.lock().unwrap().clone();
+
+    let fma_samples: Vec<Sample> = samples
+        .iter()
+        .filter(|s| s.t_s <= fma_end)
+        .cloned()
+        .collect();
+    let power = mean(
+        fma_samples
+            .iter()
+            .filter_map(|s| s.gpu_power_w.map(|w| w as f64)),
+    );
+    let result = BenchResult {
+        device: info.name.clone(),
+        architecture: info.architecture.clone(),
+        apple_smi_version: utils::project_version(),
+        duration_s,
+        gpu_power_avg_w: power.map(|w| (w * 100.0).round() / 100.0),
+        gflops_per_w: power
+            .filter(|w| *w > 0.0)
+            .map(|w| (fma.throughput / w * 10.0).round() / 10.0),
+        gpu_temp_max_c: samples
+            .iter()
+            .filter_map(|s| s.gpu_temp_c)
+            .fold(None, |m: Option<f32>, t| Some(m.map_or(t, |m| m.max(t)))),
+        throttle: throttle_of(&fma, &fma_samples, &samples),
+        fma,
+        copy,
+        samples,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else {
+        print_result(&result);
+    }
+    Ok(())
+}
+
+fn print_result(r: &BenchResult) {
+    let na = |v: Option<String>| v.unwrap_or_else(|| String::from("N/A"));
+    println!();
+    println!("  t(s)    MHz   GPU W   Temp  Pressure");
+    for s in &r.samples {
+        println!(
+            "{:>6.1} {:>6} {:>7} {:>6}  {}",
+            s.t_s,
+            na(s.freq_mhz.map(|f| f.to_string())),
+            na(s.gpu_power_w.map(|w| format!("{:.2}", w))),
+            na(s.gpu_temp_c.map(|t| format!("{:.0}C", t))),
+            s.thermal_pressure.unwrap_or("N/A"),
+        );
+    }
+    println!();
+    println!("Device          : {}", r.device);
+    println!("FP32 FMA        : {:.1} GFLOPS", r.fma.throughput);
+    println!("Memory copy     : {:.1} GB/s", r.copy.throughput);
+    println!(
+        "GPU power (avg) : {}",
+        na(r.gpu_power_avg_w.map(|w| format!("{:.2} W", w)))
+    );
+    println!(
+        "Perf/W          : {}",
+        na(r.gflops_per_w.map(|g| format!("{:.1} GFLOPS/W", g)))
+    );
+    println!(
+        "Max temperature : {}",
+        na(r.gpu_temp_max_c.map(|t| format!("{:.0} C", t)))
+    );
+    let t = &r.throttle;
+    println!(
+        "Throttling      : {} (last/first throughput {}, frequency {} -> {} MHz, worst pressure {})",
+        if t.detected { "DETECTED" } else { "none" },
+        na(t.ratio.map(|x| format!("{:.3}", x))),
+        na(t.freq_first_mhz.map(|f| f.to_string())),
+        na(t.freq_last_mhz.map(|f| f.to_string())),
+        t.worst_thermal_pressure.unwrap_or("N/A"),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phase(per_second: &[f64]) -> Phase {
+        Phase {
+            seconds: per_second.len() as f64,
+            throughput: 0.0,
+            per_second: per_second.to_vec(),
+        }
+    }
+
+    fn sample(freq_mhz: Option<u32>, pressure: Option<ThermalPressure>) -> Sample {
+        Sample {
+            t_s: 0.0,
+            freq_mhz,
+            gpu_power_w: None,
+            gpu_temp_c: None,
+            thermal_pressure: pressure.map(|p| p.label()),
+            pressure,
+        }
+    }
+
+    fn clocks(freqs: &[u32]) -> Vec<Sample> {
+        freqs.iter().map(|f| sample(Some(*f), None)).collect()
+    }
+
+    #[test]
+    fn steady_run_is_not_throttled() {
+        // The partial last bucket is ignored
+        let fma = phase(&[100.0, 100.0, 99.0, 100.0, 100.0, 100.0, 100.0, 100.0, 10.0]);
+        let samples = clocks(&[1578, 1578, 1578]);
+        let t = throttle_of(&fma, &samples, &samples);
+        assert!(!t.detected);
+        assert_eq!(t.ratio, Some(1.0));
+        assert_eq!(
+            (t.freq_first_mhz, t.freq_last_mhz),
+            (Some(1578), Some(1578))
+        );
+        assert_eq!(t.worst_thermal_pressure, None);
+    }
+
+    #[test]
+    fn throughput_drop_is_throttled() {
+        // Quarters of two buckets: 100 then 80
+        let fma = phase(&[100.0, 100.0, 95.0, 90.0, 85.0, 82.0, 80.0, 80.0, 10.0]);
+        let t = throttle_of(&fma, &[], &[]);
+        assert!(t.detected);
+        assert_eq!(t.ratio, Some(0.8));
+        // Exactly 90% is still fine
+        let t = throttle_of(&phase(&[100.0, 90.0, 0.0]), &[], &[]);
+        assert_eq!((t.detected, t.ratio), (false, Some(0.9)));
+    }
+
+    #[test]
+    fn clock_drop_thresholds() {
+        let fma = phase(&[100.0, 100.0, 0.0]);
+        let under = clocks(&[1578, 1500, 1398]);
+        let t = throttle_of(&fma, &under, &under);
+        assert!(t.detected);
+        assert_eq!(
+            (t.freq_first_mhz, t.freq_last_mhz),
+            (Some(1578), Some(1398))
+        );
+        let at = clocks(&[1500, 1350]);
+        assert!(!throttle_of(&fma, &at, &at).detected);
+        // Samples without a clock (not root) don't count
+        let mut gaps = vec![sample(None, None)];
+        gaps.extend(clocks(&[1578, 1000]));
+        gaps.push(sample(None, None));
+        let t = throttle_of(&fma, &gaps, &gaps);
+        assert!(t.detected);
+        assert_eq!(
+            (t.freq_first_mhz, t.freq_last_mhz),
+            (Some(1578), Some(1000))
+        );
+    }
+
+    #[test]
+    fn heavy_pressure_is_throttled() {
+        let fma = phase(&[100.0, 100.0, 0.0]);
+        let moderate = [sample(None, Some(ThermalPressure::Moderate))];
+        assert!(!throttle_of(&fma, &[], &moderate).detected);
+        let heavy = [
+            sample(None, Some(ThermalPressure::Nominal)),
+            sample(None, Some(ThermalPressure::Heavy)),
+            sample(None, Some(ThermalPressure::Moderate)),
+        ];
+        let t = throttle_of(&fma, &[], &heavy);
+        assert!(t.detected);
+        assert_eq!(t.worst_thermal_pressure, Some("Heavy"));
+    }
+
+    #[test]
+    fn too_short_or_empty_runs_have_no_ratio() {
+        for buckets in [&[][..], &[100.0], &[100.0, 50.0]] {
+            let t = throttle_of(&phase(buckets), &[], &[]);
+            assert!(!t.detected);
+            assert_eq!(t.ratio, None);
+            assert_eq!((t.freq_first_mhz, t.freq_last_mhz), (None, None));
+            assert_eq!(t.worst_thermal_pressure, None);
+        }
+        // An idle first quarter can't be divided by
+        assert_eq!(
+            throttle_of(&phase(&[0.0, 100.0, 0.0]), &[], &[]).ratio,
+            None
+        );
+        assert_eq!(mean(std::iter::empty()), None);
+        assert_eq!(mean([1.0, 2.0].into_iter()), Some(1.5));
+    }
+}
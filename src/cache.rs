@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * cache.rs
+ * Small JSON file cache for data that only changes with hardware / OS updates.
+ */
+use anyhow::{Context, Result};
+use serde::{Serialize, de::DeserializeOwned};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Override the cache location, mostly useful for sudo vs user runs sharing one cache
+pub const DIR_ENV: &str = "APPLE_SMI_CACHE_DIR";
+
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join("Library/Caches/apple-smi"),
+        None => std::env::temp_dir().join("apple-smi"),
+    }
+}
+
+/*
+ * The cache dir comes from the environment, and root feeds cached SMC key sizes
+ * into writes. So as root only files root owns and nobody else can write count.
+ */
+fn trusted(owner: u32, mode: u32, as_root: bool) -> bool {
+    !as_root || (owner == 0 && mode & 0o022 == 0)
+}
+
+/// Read a cache entry. Missing, unreadable or untrusted entries are just a cache miss.
+pub fn load<T: DeserializeOwned>(name: &str) -> Option<T> {
+    load_from(&cache_dir(), name)
+}
+
+pub fn load_from<T: DeserializeOwned>(dir: &Path, name: &str) -> Option<T> {
+    // Check the opened file, not the path, so it can't be swapped in between
+    let mut file = std::fs::File::open(dir.join(name)).ok()?;
+    let meta = file.metadata().ok()?;
+    if !meta.is_file() || !trusted(meta.uid(), meta.mode(), crate::utils::is_root()) {
+        return None;
+    }
+    let mut text = String::new();
+    file.read_to_string(&mut text).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+pub fn store<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    store_in(&cache_dir(), name, value)
+}
+
+pub fn store_in<T: Serialize>(dir: &Path, name: &str, value: &T) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    // Write then rename so a concurrent reader never sees half a file
+    let tmp = dir.join(format!("{}.tmp{}", name, std::process::id()));
+    std::fs::write(&tmp, serde_json::to_vec(value)?)?;
+    std::fs::rename(&tmp, dir.join(name))?;
+    Ok(())
+}
+
+/// "<hw.model>/<kern.osversion>", e.g. "Mac16,10/25C56". Caches are only valid for the same pair.
+pub fn machine_id() -> Option<String> {
+    let model = crate::utils::sysctl_string("hw.model")?;
+    let build = crate::utils::sysctl_string("kern.osversion")?;
+    Some(format!("{}/{}", model, build))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_only_trusts_root_owned_files() {
+        assert!(trusted(501, 0o666, false));
+        assert!(trusted(0, 0o644, true));
+        assert!(trusted(0, 0o600, true));
+        assert!(!trusted(501, 0o644, true));
+        assert!(!trusted(0, 0o664, true));
+        assert!(!trusted(0, 0o646, true));
+    }
+}
@@ -0,0 +1,542 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * collect.rs
+ * Run every data source at once, each with its own deadline.
+ */
+use crate::error;
+use crate::ioreg::{self, AccelEntry, AdapterInfo};
+#[cfg(feature = "ioreport")]
+use crate::ioreport;
+use crate::mtlapi::{self, MetalDeviceInfo, MetalUtilization};
+#[cfg(feature = "powermetrics")]
+use crate::pwrmtcs;
+use crate::pwrmtcs::{GpuMetrics, ProcGpu};
+use crate::smc::{self, SmcSnapshot};
+use crate::syspf::{self, Root, SysProf};
+use crate::thermal::{self, ThermalState};
+use crate::utils::ChildGroup;
+use anyhow::Result;
+#[cfg(feature = "ioreport")]
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A backend, how long we wait for it and how long it samples
+#[derive(Clone, Copy)]
+struct Source {
+    backend: &'static str,
+    timeout: Duration,
+    window_ms: Option<u64>,
+}
+
+// system_profiler is slow when its cache is cold
+const SYSPF: Source = Source {
+    backend: "system_profiler",
+    timeout: Duration::from_secs(15),
+    window_ms: None,
+};
+// One powermetrics run samples for 200 ms (-i 200 -n 1)
+const POWERMETRICS: Source = Source {
+    backend: "powermetrics",
+    timeout: Duration::from_secs(5),
+    window_ms: Some(200),
+};
+const IOREG: Source = Source {
+    backend: "ioreg",
+    timeout: Duration::from_secs(5),
+    window_ms: None,
+};
+const SMC: Source = Source {
+    backend: "smc",
+    timeout: Duration::from_secs(3),
+    window_ms: None,
+};
+const METAL: Source = Source {
+    backend: "metal",
+    timeout: Duration::from_secs(5),
+    window_ms: None,
+};
+// Fallback utilization from Metal timestamp probes, for GPUs ioreg has no figure for
+const METAL_UTIL_WINDOW_MS: u64 = 200;
+const METAL_PROBES: usize = 10;
+const METAL_UTIL: Source = Source {
+    backend: "metal",
+    timeout: Duration::from_secs(2),
+    window_ms: Some(METAL_UTIL_WINDOW_MS),
+};
+// IOReport energy window
+const POWER_WINDOW_MS: u64 = 200;
+const IOREPORT: Source = Source {
+    backend: "ioreport",
+    timeout: Duration::from_secs(2),
+    window_ms: Some(POWER_WINDOW_MS),
+};
+const PMSET: Source = Source {
+    backend: "pmset",
+    timeout: Duration::from_secs(3),
+    window_ms: None,
+};
+
+#[derive(Debug, Clone)]
+pub enum SourceStatus {
+    Ok,
+    // The view doesn't need it, not worth reporting
+    NotRequested,
+    Skipped(&'static str),
+    // Exit code of the typed error, in case the source was required
+    Failed { message: String, exit_code: i32 },
+    TimedOut(Duration),
+}
+
+/// `SourceStatus` without its details, how it shows up in JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusKind {
+    Ok,
+    #[default]
+    NotRequested,
+    Skipped,
+    Failed,
+    TimedOut,
+}
+
+impl SourceStatus {
+    pub fn kind(&self) -> StatusKind {
+        match self {
+            Self::Ok => StatusKind::Ok,
+            Self::NotRequested => StatusKind::NotRequested,
+            Self::Skipped(_) => StatusKind::Skipped,
+            Self::Failed { .. } => StatusKind::Failed,
+            Self::TimedOut(_) => StatusKind::TimedOut,
+        }
+    }
+}
+
+impl fmt::Display for SourceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::NotRequested => write!(f, "not requested"),
+            Self::Skipped(why) => write!(f, "skipped ({})", why),
+            Self::Failed { message, .. } => write!(f, "failed: {}", message),
+            Self::TimedOut(t) => write!(f, "timed out after {} ms", t.as_millis()),
+        }
+    }
+}
+
+/// Where a value came from and why it may be missing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    pub source: &'static str,
+    pub status: StatusKind,
+    pub window_ms: Option<u64>,
+    // Skip reason, error or timeout; None when the source is ok
+    pub error: Option<String>,
+}
+
+// "powermetrics skipped (needs root)", "ioreport failed: ..."
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.status, &self.error) {
+            (StatusKind::Ok, _) => write!(f, "{} ok", self.source),
+            (StatusKind::NotRequested, _) => write!(f, "{} not requested", self.source),
+            (StatusKind::Skipped, Some(e)) => write!(f, "{} skipped ({})", self.source, e),
+            (StatusKind::TimedOut, Some(e)) => write!(f, "{} {}", self.source, e),
+            (_, Some(e)) => write!(f, "{} failed: {}", self.source, e),
+            (_, None) => write!(f, "{} failed", self.source),
+        }
+    }
+}
+
+/// One source's result: the value if it produced one, and what happened.
+#[derive(Debug, Clone)]
+pub struct Sourced<T> {
+    pub value: Option<T>,
+    pub status: SourceStatus,
+    pub source: &'static str,
+    pub window_ms: Option<u64>,
+}
+
+impl<T> Sourced<T> {
+    fn unstarted(src: Source, status: SourceStatus) -> Self {
+        Self {
+            value: None,
+            status,
+            source: src.backend,
+            window_ms: src.window_ms,
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    // One part of a source that produced several, `NotRequested` unless `wanted`
+    fn part<U>(&self, wanted: bool, f: impl FnOnce(&T) -> Option<U>) -> Sourced<U> {
+        let (value, status) = match wanted {
+            true => (self.value.as_ref().and_then(f), self.status.clone()),
+            false => (None, SourceStatus::NotRequested),
+        };
+        Sourced {
+            value,
+            status,
+            source: self.source,
+            window_ms: self.window_ms,
+        }
+    }
+
+    pub fn provenance(&self) -> Provenance {
+        let error = match &self.status {
+            SourceStatus::Ok | SourceStatus::NotRequested => None,
+            SourceStatus::Skipped(why) => Some(why.to_string()),
+            SourceStatus::Failed { message, .. } => Some(message.clone()),
+            SourceStatus::TimedOut(_) => Some(self.status.to_string()),
+        };
+        Provenance {
+            source: self.source,
+            status: self.status.kind(),
+            window_ms: self.window_ms,
+            error,
+        }
+    }
+}
+
+// A source running on its own thread
+struct Pending<T> {
+    rx: mpsc::Receiver<Result<T>>,
+    start: Instant,
+    src: Source,
+}
+
+// Child processes the source starts go into `children`
+fn spawn<T: Send + 'static>(
+    children: &ChildGroup,
+    src: Source,
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Pending<T> {
+    let (tx, rx) = mpsc::channel();
+    let children = children.clone();
+    thread::spawn(move || {
+        // The receiver is gone once we gave up on this source
+        let _ = tx.send(children.enter(f));
+    });
+    Pending {
+        rx,
+        start: Instant::now(),
+        src,
+    }
+}
+
+impl<T> Pending<T> {
+    /*
+     * Everything was spawned up front, so waiting on sources one by one still ends
+     * at the slowest deadline. A source that times out is abandoned; its child
+     * processes are killed at the end of collect(), through its ChildGroup.
+     */
+    fn wait(self) -> Sourced<T> {
+        let timeout = self.src.timeout;
+        let left = timeout.saturating_sub(self.start.elapsed());
+        let (value, status) = match self.rx.recv_timeout(left) {
+            Ok(Ok(v)) => (Some(v), SourceStatus::Ok),
+            Ok(Err(e)) => (
+                None,
+                SourceStatus::Failed {
+                    message: format!("{:#}", e),
+                    exit_code: error::exit_code(&e),
+                },
+            ),
+            Err(RecvTimeoutError::Timeout) => (None, SourceStatus::TimedOut(timeout)),
+            Err(RecvTimeoutError::Disconnected) => (
+                None,
+                SourceStatus::Failed {
+                    message: String::from("collector panicked"),
+                    exit_code: error::EXIT_OTHER,
+                },
+            ),
+        };
+        Sourced {
+            value,
+            status,
+            source: self.src.backend,
+            window_ms: self.src.window_ms,
+        }
+    }
+}
+
+fn maybe<T: Send + 'static>(
+    start: bool,
+    children: &ChildGroup,
+    src: Source,
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Option<Pending<T>> {
+    start.then(|| spawn(children, src, f))
+}
+
+// Why an optional source wasn't started. Built and requested means it needs root,
+// that's the only other reason to leave one out
+fn not_started(requested: bool, built: bool) -> SourceStatus {
+    match (requested, built) {
+        (false, _) => SourceStatus::NotRequested,
+        (true, false) => SourceStatus::Skipped("not built in"),
+        (true, true) => SourceStatus::Skipped("needs root"),
+    }
+}
+
+// `not_started` explains a source that wasn't started
+fn finish<T>(p: Option<Pending<T>>, src: Source, not_started: SourceStatus) -> Sourced<T> {
+    match p {
+        Some(p) => p.wait(),
+        None => Sourced::unstarted(src, not_started),
+    }
+}
+
+/// Which optional sources a view needs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Plan {
+    pub powermetrics: bool,
+    pub gpu_power: bool,
+    pub processes: bool,
+    pub adapter: bool,
+    pub thermal: bool,
+    // Metal probes for GPUs without a utilization figure in ioreg
+    pub metal_utilization: bool,
+    // Live display list, system_profiler's cache leaves it out
+    pub displays: bool,
+    // Serial number, also kept out of the cache
+    pub serial: bool,
+}
+
+// GPU metrics and processes, from the same powermetrics run
+type PowermetricsSample = (Option<GpuMetrics>, Option<Vec<ProcGpu>>);
+
+/// Everything one snapshot gathered, with per-source status.
+pub struct Collected {
+    pub syspf: Sourced<(Root, SysProf)>,
+    pub powermetrics: Sourced<GpuMetrics>,
+    pub accels: Sourced<Vec<AccelEntry>>,
+    pub smc: Sourced<SmcSnapshot>,
+    pub metal: Sourced<Vec<MetalDeviceInfo>>,
+    pub metal_utilization: Sourced<Vec<MetalUtilization>>,
+    pub gpu_power: Sourced<f32>,
+    pub adapter: Sourced<AdapterInfo>,
+    pub thermal: Sourced<ThermalState>,
+    pub processes: Sourced<Vec<ProcGpu>>,
+    pub serial: Sourced<Option<String>>,
+}
+
+impl Collected {
+    /// Every source that was asked for, keyed the way JSON reports them.
+    pub fn provenance(&self) -> Vec<(&'static str, Provenance)> {
+        let all = [
+            ("system_profiler", self.syspf.provenance()),
+            ("powermetrics", self.powermetrics.provenance()),
+            ("ioreg", self.accels.provenance()),
+            ("smc", self.smc.provenance()),
+            ("metal", self.metal.provenance()),
+            ("metal_utilization", self.metal_utilization.provenance()),
+            ("ioreport", self.gpu_power.provenance()),
+            ("adapter", self.adapter.provenance()),
+            ("thermal", self.thermal.provenance()),
+            ("processes", self.processes.provenance()),
+            ("serial", self.serial.provenance()),
+        ];
+        all.into_iter()
+            .filter(|(_, p)| p.status != StatusKind::NotRequested)
+            .collect()
+    }
+
+    /// The utilization tier that produced a figure; with none, the preferred one tells why.
+    pub fn utilization_provenance(&self, source: Option<&str>) -> Provenance {
+        match source {
+            Some("ioreg") => self.accels.provenance(),
+            Some("metal" | "metal-estimate") => self.metal_utilization.provenance(),
+            _ => self.powermetrics.provenance(),
+        }
+    }
+}
+
+pub fn collect(plan: Plan) -> Collected {
+    let with_powermetrics = cfg!(feature = "powermetrics");
+    let with_ioreport = cfg!(feature = "ioreport");
+    let with_metal = cfg!(feature = "metal");
+    let children = ChildGroup::default();
+
+    let syspf = spawn(&children, SYSPF, move || {
+        let json = syspf::run_syspf(plan.displays)?;
+        let root: Root = syspf::parse(&json)?;
+        let os: SysProf = syspf::parse(&json)?;
+        Ok((root, os))
+    });
+    // One powermetrics for both, it refuses to run without root
+    #[cfg(feature = "powermetrics")]
+    let powermetrics = maybe(
+        (plan.powermetrics || plan.processes) && crate::utils::is_root(),
+        &children,
+        POWERMETRICS,
+        move || pwrmtcs::run_pwrmtcs_sample(plan.powermetrics, plan.processes),
+    );
+    #[cfg(not(feature = "powermetrics"))]
+    let powermetrics: Option<Pending<PowermetricsSample>> = None;
+    // ioreg tells the Metal probes which GPUs already have a utilization figure
+    let (covered_tx, covered_rx) = mpsc::channel::<Vec<u64>>();
+    let accels = spawn(&children, IOREG, move || {
+        let accels = ioreg::run_ioreg()?;
+        let covered = accels
+            .iter()
+            .filter(|a| a.device_utilization.is_some())
+            .filter_map(|a| a.registry_id)
+            .collect();
+        let _ = covered_tx.send(covered);
+        Ok(accels)
+    });
+    let smc = spawn(&children, SMC, smc::read_smc_snapshot);
+    let metal = maybe(with_metal, &children, METAL, || Ok(mtlapi::device_infos()));
+    let metal_utilization = maybe(
+        plan.metal_utilization && with_metal,
+        &children,
+        METAL_UTIL,
+        move || {
+            // ioreg failing or running late leaves nothing covered, the probes need
+            // the rest of the budget
+            let deadline = Instant::now() + METAL_UTIL.timeout;
+            let window = Duration::from_millis(METAL_UTIL_WINDOW_MS);
+            let covered = covered_rx
+                .recv_timeout(METAL_UTIL.timeout.saturating_sub(window * 2))
+                .unwrap_or_default();
+            Ok(mtlapi::sample_utilizations(
+                &covered,
+                METAL_UTIL_WINDOW_MS,
+                METAL_PROBES,
+                deadline,
+            ))
+        },
+    );
+    #[cfg(feature = "ioreport")]
+    let gpu_power = maybe(plan.gpu_power, &children, IOREPORT, || {
+        ioreport::sample_gpu_power_once(POWER_WINDOW_MS)?
+            .ok_or_else(|| anyhow!("no GPU energy channel in IOReport"))
+    });
+    #[cfg(not(feature = "ioreport"))]
+    let gpu_power = None;
+    let adapter = maybe(plan.adapter, &children, IOREG, ioreg::run_ioreg_adapter);
+    let thermal = maybe(plan.thermal, &children, PMSET, || {
+        Ok(thermal::read_thermal_state())
+    });
+    let serial = maybe(plan.serial, &children, IOREG, ioreg::run_ioreg_serial);
+
+    let sample: Sourced<PowermetricsSample> = finish(
+        powermetrics,
+        POWERMETRICS,
+        not_started(plan.powermetrics || plan.processes, with_powermetrics),
+    );
+    let snap = Collected {
+        syspf: syspf.wait(),
+        powermetrics: sample.part(plan.powermetrics, |s| s.0.clone()),
+        accels: accels.wait(),
+        smc: smc.wait(),
+        metal: finish(metal, METAL, not_started(true, with_metal)),
+        metal_utilization: finish(
+            metal_utilization,
+            METAL_UTIL,
+            not_started(plan.metal_utilization, with_metal),
+        ),
+        gpu_power: finish(
+            gpu_power,
+            IOREPORT,
+            not_started(plan.gpu_power, with_ioreport),
+        ),
+        adapter: finish(adapter, IOREG, SourceStatus::NotRequested),
+        thermal: finish(thermal, PMSET, SourceStatus::NotRequested),
+        processes: sample.part(plan.processes, |s| s.1.clone()),
+        serial: finish(serial, IOREG, SourceStatus::NotRequested),
+    };
+    // Whatever is still running belongs to a source we gave up on
+    children.kill_all();
+    snap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{CmdError, EXIT_QUERY_FAILED};
+    use crate::utils::output_timeout;
+    use std::process::Command;
+
+    fn fake(timeout_ms: u64) -> Source {
+        Source {
+            backend: "fake",
+            timeout: Duration::from_millis(timeout_ms),
+            window_ms: None,
+        }
+    }
+
+    #[test]
+    fn slow_and_failed_sources_leave_the_rest() {
+        let children = ChildGroup::default();
+        let start = Instant::now();
+        let slow = spawn(&children, fake(100), || {
+            thread::sleep(Duration::from_secs(2));
+            Ok(1)
+        });
+        let failed = spawn(&children, fake(1000), || -> Result<u32> {
+            Err(CmdError::Timeout {
+                program: String::from("ioreg"),
+                timeout: Duration::from_secs(5),
+            }
+            .into())
+        });
+        let ok = spawn(&children, fake(1000), || Ok(3));
+        let skipped = finish::<u32>(None, fake(1000), not_started(true, true));
+
+        let slow = slow.wait();
+        assert!(matches!(slow.status, SourceStatus::TimedOut(t) if t == Duration::from_millis(100)));
+        assert_eq!(slow.value, None);
+        let failed = failed.wait();
+        assert!(matches!(
+            failed.status,
+            SourceStatus::Failed { exit_code: EXIT_QUERY_FAILED, .. }
+        ));
+        assert_eq!(failed.provenance().status, StatusKind::Failed);
+        let ok = ok.wait();
+        assert_eq!((ok.value, ok.status.kind()), (Some(3), StatusKind::Ok));
+        assert_eq!(skipped.provenance().error.as_deref(), Some("needs root"));
+        // Waiting ends at the slowest deadline, not the slow source
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn timed_out_sources_lose_their_children() {
+        let children = ChildGroup::default();
+        let (done_tx, done_rx) = mpsc::channel();
+        let stuck = spawn(&children, fake(200), move || {
+            let out = output_timeout(Command::new("sleep").arg("30"), Duration::from_secs(60));
+            let _ = done_tx.send(out.map(|o| o.status.success()));
+            Ok(())
+        });
+        assert_eq!(stuck.wait().status.kind(), StatusKind::TimedOut);
+        assert!(done_rx.try_recv().is_err());
+
+        children.kill_all();
+        let killed = done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!killed.unwrap(), "the child outlived kill_all");
+    }
+
+    #[test]
+    fn shared_source_splits_by_request() {
+        let sample = Sourced {
+            value: Some((Some(7), None::<u32>)),
+            status: SourceStatus::Ok,
+            source: "powermetrics",
+            window_ms: Some(200),
+        };
+        let gpu = sample.part(true, |s| s.0);
+        assert_eq!((gpu.value, gpu.status.kind()), (Some(7), StatusKind::Ok));
+        let procs = sample.part(false, |s| s.1);
+        assert_eq!(procs.status.kind(), StatusKind::NotRequested);
+    }
+}
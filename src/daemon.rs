@@ -0,0 +1,636 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * daemon.rs
+ * One long-running set of samplers, served as JSON-RPC lines over a Unix socket.
+ * The CLI asks it first so powermetrics and IOReport aren't started per call.
+ */
+use crate::events::{EventTracker, GpuEvent};
+use anyhow::{Context, Result, anyhow, bail};
+use apple_smi::collect::{self, Collected, Plan, SourceStatus, Sourced, StatusKind};
+use apple_smi::error::Error;
+use apple_smi::pwrmtcs::{GpuMetrics, ProcGpu};
+use apple_smi::{Collector, Snapshot, ioreg, utils};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Lines, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const SOCKET_ENV: &str = "APPLE_SMI_SOCKET";
+pub const DEFAULT_SOCKET: &str = "/var/run/apple-smi.sock";
+
+// The CLI only takes snapshots younger than this, otherwise it samples itself
+const MAX_AGE_MS: u64 = 5000;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+// A request connection that sends nothing for this long is closed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CLIENTS: usize = 32;
+// Notifications queued per subscriber; one that falls this far behind is dropped
+const SUBSCRIBER_QUEUE: usize = 16;
+// How often an idle subscriber connection is checked for a hangup
+const HANGUP_POLL: Duration = Duration::from_secs(1);
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+// Server defined: nothing sampled yet, or only older than max_age_ms
+const NO_DATA: i32 = -32001;
+const TOO_MANY_CLIENTS: i32 = -32002;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// `--no-daemon`: always sample in this process.
+pub fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// `$APPLE_SMI_SOCKET`, or the system-wide socket.
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET))
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+fn reply(id: &Value, result: Result<Value, (i32, String)>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => {
+            json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+        }
+    }
+}
+
+fn notification(method: &str, params: Value) -> String {
+    json!({"jsonrpc": "2.0", "method": method, "params": params}).to_string()
+}
+
+struct Subscriber {
+    id: u64,
+    snapshots: bool,
+    events: bool,
+    tx: SyncSender<String>,
+}
+
+struct Shared {
+    started: Instant,
+    interval: Duration,
+    capacity: usize,
+    // Oldest first, stamped with when we took them; replayed snapshots keep old timestamps
+    history: VecDeque<(Instant, Snapshot)>,
+    subscribers: Vec<Subscriber>,
+    next_id: u64,
+}
+
+impl Shared {
+    fn new(interval: Duration, capacity: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            interval,
+            capacity,
+            history: VecDeque::with_capacity(capacity),
+            subscribers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    fn subscribe(&mut self, snapshots: bool, events: bool, tx: SyncSender<String>) -> u64 {
+        self.next_id += 1;
+        self.subscribers.push(Subscriber {
+            id: self.next_id,
+            snapshots,
+            events,
+            tx,
+        });
+        self.next_id
+    }
+
+    fn unsubscribe(&mut self, id: u64) {
+        self.subscribers.retain(|s| s.id != id);
+    }
+
+    fn publish_snapshot(&mut self, snap: Snapshot) {
+        if self.subscribers.iter().any(|s| s.snapshots) {
+            let line = notification("snapshot", json!(snap));
+            // A full queue is a client not keeping up, a closed one a client gone; drop both
+            self.subscribers
+                .retain(|s| !s.snapshots || s.tx.try_send(line.clone()).is_ok());
+        }
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back((Instant::now(), snap));
+    }
+
+    fn publish_event(&mut self, e: &GpuEvent) {
+        let line = notification("event", json!(e));
+        self.subscribers
+            .retain(|s| !s.events || s.tx.try_send(line.clone()).is_ok());
+    }
+
+    fn call(&self, method: &str, params: &Value) -> Result<Value, (i32, String)> {
+        match method {
+            "status" => Ok(json!({
+                "version": utils::project_version(),
+                "pid": std::process::id(),
+                "uptime_s": self.started.elapsed().as_secs(),
+                "interval_ms": self.interval.as_millis() as u64,
+                "history": self.history.len(),
+                "capacity": self.capacity,
+                "subscribers": self.subscribers.len(),
+            })),
+            "snapshot" => {
+                let max_age = param_u64(params, "max_age_ms")?.map(Duration::from_millis);
+                match self.history.back() {
+                    Some((at, snap)) if max_age.is_none_or(|m| at.elapsed() <= m) => {
+                        Ok(json!(snap))
+                    }
+                    Some(_) => Err((NO_DATA, String::from("latest snapshot is too old"))),
+                    None => Err((NO_DATA, String::from("no snapshot yet"))),
+                }
+            }
+            // {"seconds": N} for a window, {"limit": N} for the newest N, oldest first
+            "history" => {
+                let window = param_u64(params, "seconds")?.map(Duration::from_secs);
+                let limit = param_u64(params, "limit")?.map(|n| n as usize);
+                let picked: Vec<&Snapshot> = self
+                    .history
+                    .iter()
+                    .filter(|(at, _)| window.is_none_or(|w| at.elapsed() <= w))
+                    .map(|(_, snap)| snap)
+                    .collect();
+                let skip = limit.map_or(0, |n| picked.len().saturating_sub(n));
+                Ok(json!(picked[skip..]))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        }
+    }
+}
+
+fn param_u64(params: &Value, key: &str) -> Result<Option<u64>, (i32, String)> {
+    match params.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| (INVALID_PARAMS, format!("'{}' must be a whole number", key))),
+    }
+}
+
+// "snapshot" and "event" unless params.topics says otherwise
+fn topics(params: &Value) -> Result<(bool, bool), (i32, String)> {
+    let Some(list) = params.get("topics") else {
+        return Ok((true, true));
+    };
+    let list = list
+        .as_array()
+        .ok_or_else(|| (INVALID_PARAMS, String::from("'topics' must be a list")))?;
+    let (mut snapshots, mut events) = (false, false);
+    for t in list {
+        match t.as_str() {
+            Some("snapshot") => snapshots = true,
+            Some("event") => events = true,
+            _ => return Err((INVALID_PARAMS, format!("unknown topic {}", t))),
+        }
+    }
+    Ok((snapshots, events))
+}
+
+// Any number of requests per connection, until one subscribes
+fn serve(stream: UnixStream, shared: Arc<Mutex<Shared>>) -> Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut out = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let req: Request = match serde_json::from_str(&line) {
+            Ok(req) => req,
+            Err(e) => {
+                let resp = reply(&Value::Null, Err((PARSE_ERROR, e.to_string())));
+                writeln!(out, "{}", resp)?;
+                continue;
+            }
+        };
+        if req.method == "subscribe" {
+            let (snapshots, events) = match topics(&req.params) {
+                Ok(t) => t,
+                Err(e) => {
+                    writeln!(out, "{}", reply(&req.id, Err(e)))?;
+                    continue;
+                }
+            };
+            let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+            let Ok(id) = shared
+                .lock()
+                .map(|mut s| s.subscribe(snapshots, events, tx))
+            else {
+                return Ok(());
+            };
+            let mut list = Vec::new();
+            if snapshots {
+                list.push("snapshot");
+            }
+            if events {
+                list.push("event");
+            }
+            let result = writeln!(out, "{}", reply(&req.id, Ok(json!({ "topics": list }))))
+                .map_err(Into::into)
+                .and_then(|_| notify(&mut out, &rx));
+            if let Ok(mut s) = shared.lock() {
+                s.unsubscribe(id);
+            }
+            return result;
+        }
+        let result = match shared.lock() {
+            Ok(s) => s.call(&req.method, &req.params),
+            Err(_) => Err((NO_DATA, String::from("sampler crashed"))),
+        };
+        writeln!(out, "{}", reply(&req.id, result))?;
+    }
+    Ok(())
+}
+
+// From here on the connection only carries notifications, until we drop it or it hangs up
+fn notify(out: &mut UnixStream, rx: &Receiver<String>) -> Result<()> {
+    loop {
+        match rx.recv_timeout(HANGUP_POLL) {
+            Ok(line) => writeln!(out, "{}", line)?,
+            // Dropped by the publisher for lagging behind
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) if hung_up(out)? => return Ok(()),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+// Subscribers have nothing more to say, so a readable socket is EOF or noise to skip
+fn hung_up(stream: &UnixStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut buf = [0u8; 256];
+    let res = (&*stream).read(&mut buf);
+    stream.set_nonblocking(false)?;
+    match res {
+        Ok(n) => Ok(n == 0),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Counts itself in `clients` for as long as the connection is served
+fn accept_client(stream: UnixStream, shared: Arc<Mutex<Shared>>, clients: Arc<AtomicUsize>) {
+    let setup = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(REQUEST_TIMEOUT)))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)));
+    if setup.is_err() {
+        return;
+    }
+    if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+        clients.fetch_sub(1, Ordering::SeqCst);
+        let busy = reply(
+            &Value::Null,
+            Err((TOO_MANY_CLIENTS, String::from("too many clients"))),
+        );
+        let _ = writeln!(&stream, "{}", busy);
+        return;
+    }
+    thread::spawn(move || {
+        // A client hanging up mid-reply is its own business
+        let _ = serve(stream, shared);
+        clients.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+fn sample(mut collector: Collector, shared: Arc<Mutex<Shared>>, interval: Duration) {
+    let mut tracker = EventTracker::new();
+    while !utils::stop_requested() {
+        let tick = Instant::now();
+        // A failed snapshot (system_profiler) is logged and tried again next tick
+        match collector.snapshot() {
+            Ok(snap) => {
+                if let Ok(mut s) = shared.lock() {
+                    s.publish_snapshot(snap);
+                }
+            }
+            Err(e) => eprintln!("apple-smi daemon: {:#}", e),
+        }
+        if let Ok(accels) = ioreg::run_ioreg() {
+            for e in tracker.observe(&accels) {
+                if let Ok(mut s) = shared.lock() {
+                    s.publish_event(&e);
+                }
+            }
+        }
+        while tick.elapsed() < interval && !utils::stop_requested() {
+            thread::sleep(Duration::from_millis(50).min(interval));
+        }
+    }
+}
+
+// A socket file nobody answers on is left over from a daemon that died
+fn claim(socket: &Path) -> Result<()> {
+    let meta = match std::fs::symlink_metadata(socket) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("checking {}", socket.display())),
+    };
+    // Whatever else sits there isn't ours to delete
+    if !meta.file_type().is_socket() {
+        bail!(Error::InvalidArgument(format!(
+            "{} exists and is not a socket, refusing to remove it",
+            socket.display()
+        )));
+    }
+    if UnixStream::connect(socket).is_ok() {
+        bail!(Error::InvalidArgument(format!(
+            "a daemon is already listening on {}",
+            socket.display()
+        )));
+    }
+    std::fs::remove_file(socket)
+        .with_context(|| format!("removing stale socket {}", socket.display()))
+}
+
+/// `daemon`: sample every `interval_ms`, keep `history` snapshots and serve them until interrupted.
+pub fn run(socket: &Path, interval_ms: u64, history: usize) -> Result<()> {
+    utils::install_stop_handler();
+    let collector = Collector::new()?;
+    claim(socket)?;
+    let listener =
+        UnixListener::bind(socket).with_context(|| format!("binding {}", socket.display()))?;
+    // The API is read-only, so users can talk to a daemon running as root
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o666))?;
+    listener.set_nonblocking(true)?;
+
+    let interval = Duration::from_millis(interval_ms);
+    let shared = Arc::new(Mutex::new(Shared::new(interval, history)));
+    let sampler = {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || sample(collector, shared, interval))
+    };
+    let clients = Arc::new(AtomicUsize::new(0));
+    eprintln!(
+        "apple-smi daemon: listening on {}, sampling every {} ms",
+        socket.display(),
+        interval_ms
+    );
+
+    let result = loop {
+        if utils::stop_requested() || sampler.is_finished() {
+            break Ok(());
+        }
+        match listener.accept() {
+            Ok((stream, _)) => accept_client(stream, Arc::clone(&shared), Arc::clone(&clients)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => break Err(anyhow!(e).context("accepting a connection")),
+        }
+    };
+    let _ = std::fs::remove_file(socket);
+    // Let a collection in flight finish, it kills the children it gave up on itself
+    utils::request_stop();
+    let _ = sampler.join();
+    result
+}
+
+fn open(socket: &Path) -> Option<UnixStream> {
+    let stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    Some(stream)
+}
+
+fn connect() -> Option<UnixStream> {
+    if DISABLED.load(Ordering::SeqCst) {
+        return None;
+    }
+    open(&socket_path())
+}
+
+// One request, one response line; errors come back as Err
+fn request(stream: &mut UnixStream, method: &str, params: Value) -> Result<Value> {
+    let req = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    writeln!(stream, "{}", req)?;
+    let mut line = String::new();
+    BufReader::new(&*stream).read_line(&mut line)?;
+    let mut resp: Value = serde_json::from_str(&line)?;
+    if let Some(e) = resp.get("error") {
+        bail!("daemon: {}", e["message"].as_str().unwrap_or("error"));
+    }
+    Ok(resp["result"].take())
+}
+
+/// The `status` reply of the daemon on `socket`, if one answers.
+pub fn status(socket: &Path) -> Option<Value> {
+    request(&mut open(socket)?, "status", Value::Null).ok()
+}
+
+/// The daemon's latest snapshot, if a daemon is running and has a fresh one.
+pub fn latest() -> Option<Snapshot> {
+    let mut stream = connect()?;
+    let v = request(&mut stream, "snapshot", json!({ "max_age_ms": MAX_AGE_MS })).ok()?;
+    serde_json::from_value(v).ok()
+}
+
+/// Event notifications from the daemon, one JSON object per line.
+pub fn subscribe_events() -> Option<Lines<BufReader<UnixStream>>> {
+    let mut stream = connect()?;
+    request(&mut stream, "subscribe", json!({ "topics": ["event"] })).ok()?;
+    // Events are rare, wait for them as long as it takes
+    stream.set_read_timeout(None).ok()?;
+    Some(BufReader::new(stream).lines())
+}
+
+fn from_daemon<T>(snap: &Snapshot, key: &str, value: T) -> Sourced<T> {
+    Sourced {
+        value: Some(value),
+        status: SourceStatus::Ok,
+        source: "daemon",
+        window_ms: snap.sources.get(key).and_then(|s| s.window_ms),
+    }
+}
+
+/// `collect::collect`, but the sources the daemon has working (powermetrics,
+/// IOReport power, processes) are taken from its snapshot instead of started here.
+pub fn collect(mut plan: Plan) -> Collected {
+    let Some(snap) = latest() else {
+        return collect::collect(plan);
+    };
+    // powermetrics and IOReport only know about the Apple GPU
+    let Some(m) = snap
+        .gpus
+        .iter()
+        .position(|g| g.unified_memory != Some(false))
+        .and_then(|i| snap.metrics.get(i))
+    else {
+        return collect::collect(plan);
+    };
+    let ok = |key: &str| {
+        snap.sources
+            .get(key)
+            .is_some_and(|s| s.status == StatusKind::Ok)
+    };
+
+    let powermetrics = (plan.powermetrics && ok("powermetrics")).then(|| GpuMetrics {
+        gpu_hw_freq: m.frequency_mhz,
+        gpu_hw_residency: m
+            .utilization_pct
+            .filter(|_| m.utilization_source.as_deref() == Some("powermetrics")),
+        gpu_sw_state: m.performance_state.map(|s| s as usize),
+    });
+    let gpu_power = m.power_w.filter(|_| plan.gpu_power && ok("ioreport"));
+    let processes = (plan.processes && ok("processes")).then(|| {
+        snap.processes
+            .iter()
+            .map(|p| ProcGpu {
+                name: p.name.clone(),
+                pid: p.pid,
+            })
+            .collect::<Vec<_>>()
+    });
+
+    plan.powermetrics &= powermetrics.is_none();
+    plan.gpu_power &= gpu_power.is_none();
+    plan.processes &= processes.is_none();
+    let mut c = collect::collect(plan);
+    if let Some(v) = powermetrics {
+        c.powermetrics = from_daemon(&snap, "powermetrics", v);
+    }
+    if let Some(v) = gpu_power {
+        c.gpu_power = from_daemon(&snap, "ioreport", v);
+    }
+    if let Some(v) = processes {
+        c.processes = from_daemon(&snap, "processes", v);
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Snapshots stamped `timestamp_ms` 0, 1, 2, ... taken `ages_ms` ago
+    fn shared(ages_ms: &[u64]) -> Shared {
+        let mut s = Shared::new(Duration::from_secs(1), 10);
+        for (i, age) in ages_ms.iter().enumerate() {
+            let mut snap = Snapshot::default();
+            snap.timestamp_ms = i as u64;
+            let at = Instant::now() - Duration::from_millis(*age);
+            s.history.push_back((at, snap));
+        }
+        s
+    }
+
+    fn stamps(v: Value) -> Vec<u64> {
+        v.as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["timestamp_ms"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_honours_max_age() {
+        let code = |r: Result<Value, (i32, String)>| r.unwrap_err().0;
+        assert_eq!(code(shared(&[]).call("snapshot", &Value::Null)), NO_DATA);
+
+        let s = shared(&[3000, 2000]);
+        let latest = s.call("snapshot", &Value::Null).unwrap();
+        assert_eq!(latest["timestamp_ms"], 1);
+        let fresh = s.call("snapshot", &json!({ "max_age_ms": 5000 })).unwrap();
+        assert_eq!(fresh["timestamp_ms"], 1);
+        assert_eq!(
+            code(s.call("snapshot", &json!({ "max_age_ms": 1000 }))),
+            NO_DATA
+        );
+        assert_eq!(
+            code(s.call("snapshot", &json!({ "max_age_ms": "1000" }))),
+            INVALID_PARAMS
+        );
+        assert_eq!(code(s.call("snapshots", &Value::Null)), METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn history_by_window_and_limit() {
+        let s = shared(&[9000, 6000, 3000, 0]);
+        assert_eq!(
+            stamps(s.call("history", &Value::Null).unwrap()),
+            [0, 1, 2, 3]
+        );
+        let recent = s.call("history", &json!({ "seconds": 5 })).unwrap();
+        assert_eq!(stamps(recent), [2, 3]);
+        let newest = s.call("history", &json!({ "limit": 3 })).unwrap();
+        assert_eq!(stamps(newest), [1, 2, 3]);
+        let both = s
+            .call("history", &json!({ "seconds": 7, "limit": 1 }))
+            .unwrap();
+        assert_eq!(stamps(both), [3]);
+        assert_eq!(
+            stamps(s.call("history", &json!({ "limit": 0 })).unwrap()),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn parses_params_and_topics() {
+        let p = json!({ "n": 5, "null": null, "neg": -1, "text": "5" });
+        assert_eq!(param_u64(&p, "n"), Ok(Some(5)));
+        assert_eq!(param_u64(&p, "missing"), Ok(None));
+        assert_eq!(param_u64(&p, "null"), Ok(None));
+        assert_eq!(param_u64(&p, "neg").unwrap_err().0, INVALID_PARAMS);
+        assert_eq!(param_u64(&p, "text").unwrap_err().0, INVALID_PARAMS);
+
+        assert_eq!(topics(&Value::Null), Ok((true, true)));
+        assert_eq!(topics(&json!({ "topics": ["event"] })), Ok((false, true)));
+        assert_eq!(topics(&json!({ "topics": [] })), Ok((false, false)));
+        assert!(topics(&json!({ "topics": ["gpu"] })).is_err());
+        assert!(topics(&json!({ "topics": "event" })).is_err());
+    }
+
+    #[test]
+    fn drops_lagging_and_gone_subscribers() {
+        let mut s = shared(&[]);
+        let (slow_tx, _slow_rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        let (gone_tx, gone_rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        let (live_tx, live_rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        let slow = s.subscribe(true, false, slow_tx);
+        s.subscribe(true, false, gone_tx);
+        let live = s.subscribe(true, false, live_tx);
+        drop(gone_rx);
+
+        s.publish_snapshot(Snapshot::default());
+        let ids: Vec<u64> = s.subscribers.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [slow, live]);
+
+        for _ in 0..SUBSCRIBER_QUEUE {
+            let _ = live_rx.try_recv();
+            s.publish_snapshot(Snapshot::default());
+        }
+        let ids: Vec<u64> = s.subscribers.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [live]);
+        s.unsubscribe(live);
+        assert!(s.subscribers.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * devices.rs
+ * Match system_profiler GPUs with Metal devices and IOAccelerator services.
+ */
+use crate::ioreg::AccelEntry;
+use crate::mtlapi::{MetalDeviceInfo, MetalUtilization};
+use crate::pwrmtcs::GpuMetrics;
+use crate::syspf::GpuEntry;
+
+/// Everything we know about one GPU row.
+pub struct Card<'a> {
+    pub gpu: &'a GpuEntry,
+    pub metal: Option<&'a MetalDeviceInfo>,
+    pub accel: Option<&'a AccelEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Utilization {
+    // percentage points
+    pub pct: f64,
+    pub source: &'static str,
+    // Probe details when the Metal fallback was used
+    pub metal: Option<MetalUtilization>,
+}
+
+impl Card<'_> {
+    /// system_profiler's sppci_cores, or gpu-core-count from IORegistry.
+    pub fn core_count(&self) -> Option<u32> {
+        self.gpu
+            .core_count()
+            .or_else(|| self.accel.and_then(|a| a.core_count))
+    }
+
+    /*
+     * Best source first:
+     * powermetrics HW residency (root) > IOAccelerator "Device Utilization %" > Metal timestamps
+     * powermetrics only knows about the Apple GPU, so it isn't used for other cards.
+     * `sampled` is what collect's Metal utilization source measured.
+     */
+    pub fn utilization(&self, p: &GpuMetrics, sampled: &[MetalUtilization]) -> Option<Utilization> {
+        let apple = self.metal.is_none_or(|m| m.has_unified_memory);
+        if let Some(pct) = p.gpu_hw_residency.filter(|_| apple) {
+            return Some(Utilization {
+                pct,
+                source: "powermetrics",
+                metal: None,
+            });
+        }
+        if let Some(pct) = self.accel.and_then(|a| a.device_utilization) {
+            return Some(Utilization {
+                pct: pct as f64,
+                source: "ioreg",
+                metal: None,
+            });
+        }
+        let m = self.metal?;
+        sampled
+            .iter()
+            .find(|u| u.registry_id == m.registry_id)
+            .map(|u| Utilization {
+                pct: u.busy_pct,
+                source: u.source(),
+                metal: Some(u.clone()),
+            })
+    }
+}
+
+fn norm(s: &str) -> String {
+    s.trim().to_ascii_lowercase()
+}
+
+// Exact name first, then "AMD Radeon Pro 5500M" vs "AMD Radeon Pro 5500M 8 GB" style
+fn find_metal(g: &GpuEntry, metal: &[MetalDeviceInfo], used: &[bool]) -> Option<usize> {
+    let name = norm(&g.name);
+    let free = |i: &usize| !used[*i];
+    (0..metal.len())
+        .filter(free)
+        .find(|i| norm(&metal[*i].name) == name)
+        .or_else(|| {
+            (0..metal.len()).filter(free).find(|i| {
+                let m = norm(&metal[*i].name);
+                !m.is_empty() && (m.contains(&name) || name.contains(&m))
+            })
+        })
+}
+
+fn find_accel<'a>(
+    g: &GpuEntry,
+    m: Option<&MetalDeviceInfo>,
+    accels: &'a [AccelEntry],
+    gpu_count: usize,
+) -> Option<&'a AccelEntry> {
+    // MTLDevice.registryID is the IORegistry entry ID of the accelerator
+    if let Some(a) = m.and_then(|m| accels.iter().find(|a| a.registry_id == Some(m.registry_id))) {
+        return Some(a);
+    }
+    let name = norm(&g.name);
+    accels
+        .iter()
+        .find(|a| a.model.as_deref().is_some_and(|x| norm(x) == name))
+        .or_else(|| {
+            // A single GPU can only be that one
+            (gpu_count == 1 && accels.len() == 1).then(|| &accels[0])
+        })
+}
+
+pub fn match_cards<'a>(
+    gpus: &'a [GpuEntry],
+    metal: &'a [MetalDeviceInfo],
+    accels: &'a [AccelEntry],
+) -> Vec<Card<'a>> {
+    let mut used = vec![false; metal.len()];
+    let mut picked: Vec<Option<usize>> = gpus
+        .iter()
+        .map(|g| {
+            let mi = find_metal(g, metal, &used);
+            if let Some(mi) = mi {
+                used[mi] = true;
+            }
+            mi
+        })
+        .collect();
+    // Same order as a last resort, both list the built-in GPU first. Runs
+    // after every name match so it cannot take a device named for another GPU.
+    for (i, mi) in picked.iter_mut().enumerate() {
+        if mi.is_none() && i < metal.len() && !used[i] {
+            used[i] = true;
+            *mi = Some(i);
+        }
+    }
+    gpus.iter()
+        .zip(picked)
+        .map(|(g, mi)| {
+            let m = mi.map(|mi| &metal[mi]);
+            Card {
+                gpu: g,
+                metal: m,
+                accel: find_accel(g, m, accels, gpus.len()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioreg::VramInfo;
+
+    fn gpus(names: &[&str]) -> Vec<GpuEntry> {
+        names
+            .iter()
+            .map(|n| serde_json::from_value(serde_json::json!({ "_name": n })).unwrap())
+            .collect()
+    }
+
+    fn metal(name: &str, registry_id: u64) -> MetalDeviceInfo {
+        MetalDeviceInfo {
+            name: name.to_string(),
+            registry_id,
+            architecture: None,
+            has_unified_memory: false,
+            is_low_power: false,
+            is_removable: false,
+            is_headless: false,
+            recommended_max_working_set_size: 0,
+            current_allocated_size: 0,
+            max_buffer_length: 0,
+            max_threadgroup_memory_length: 0,
+            gpu_families: Vec::new(),
+            counter_sets: Vec::new(),
+        }
+    }
+
+    fn accel(registry_id: Option<u64>, model: Option<&str>) -> AccelEntry {
+        AccelEntry {
+            registry_id,
+            class_name: String::from("IOAccelerator"),
+            model: model.map(String::from),
+            core_count: None,
+            vram: VramInfo {
+                alloc_vram: None,
+                inuse_vram: None,
+            },
+            device_utilization: None,
+            recovery_count: None,
+            last_recovery_time: None,
+        }
+    }
+
+    // (Metal registry ID, accelerator registry ID) per card
+    fn ids(cards: &[Card]) -> Vec<(Option<u64>, Option<u64>)> {
+        cards
+            .iter()
+            .map(|c| {
+                (
+                    c.metal.map(|m| m.registry_id),
+                    c.accel.and_then(|a| a.registry_id),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_name_gpus_get_one_device_each() {
+        let g = gpus(&["AMD Radeon Pro W6800X Duo", "AMD Radeon Pro W6800X Duo"]);
+        let m = [
+            metal("AMD Radeon Pro W6800X Duo", 0x10),
+            metal("AMD Radeon Pro W6800X Duo", 0x20),
+        ];
+        let a = [
+            accel(Some(0x20), Some("AMD Radeon Pro W6800X Duo")),
+            accel(Some(0x10), Some("AMD Radeon Pro W6800X Duo")),
+        ];
+        let cards = match_cards(&g, &m, &a);
+        assert_eq!(ids(&cards), [(Some(0x10), Some(0x10)), (Some(0x20), Some(0x20))]);
+    }
+
+    #[test]
+    fn egpu_and_built_in_match_by_name_not_order() {
+        let g = gpus(&["Intel UHD Graphics 630", "AMD Radeon RX 580"]);
+        // Metal lists the eGPU first here, and with a longer name
+        let m = [
+            metal("AMD Radeon RX 580 8 GB", 0x30),
+            metal("Intel(R) UHD Graphics 630", 0x40),
+            metal("Intel UHD Graphics 630", 0x50),
+        ];
+        let a = [accel(Some(0x50), None), accel(Some(0x30), None)];
+        let cards = match_cards(&g, &m, &a);
+        assert_eq!(ids(&cards), [(Some(0x50), Some(0x50)), (Some(0x30), Some(0x30))]);
+    }
+
+    #[test]
+    fn unknown_names_fall_back_to_index() {
+        let g = gpus(&["Apple M4", "Mystery GPU"]);
+        let m = [metal("AGX G16G", 0x1), metal("Something Else", 0x2)];
+        let cards = match_cards(&g, &m, &[]);
+        assert_eq!(ids(&cards), [(Some(0x1), None), (Some(0x2), None)]);
+        // A name match wins over another GPU's index
+        let m = [metal("Mystery GPU", 0x1)];
+        let cards = match_cards(&g, &m, &[]);
+        assert_eq!(ids(&cards), [(None, None), (Some(0x1), None)]);
+    }
+
+    #[test]
+    fn registry_id_beats_model_then_single_gpu() {
+        let g = gpus(&["Apple M4"]);
+        let m = [metal("Apple M4", 0x1000005b3)];
+        let a = [accel(Some(0x99), Some("Apple M4")), accel(Some(0x1000005b3), Some("AGX"))];
+        assert_eq!(ids(&match_cards(&g, &m, &a)), [(Some(0x1000005b3), Some(0x1000005b3))]);
+        // Without Metal the model decides, and one GPU with one accelerator pairs up
+        assert_eq!(ids(&match_cards(&g, &[], &a)), [(None, Some(0x99))]);
+        let a = [accel(Some(0x7), None)];
+        assert_eq!(ids(&match_cards(&g, &[], &a)), [(None, Some(0x7))]);
+        let two = gpus(&["Apple M4", "AMD Radeon RX 580"]);
+        assert_eq!(ids(&match_cards(&two, &[], &a)), [(None, None), (None, None)]);
+    }
+}
@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * doctor.rs
+ * Probe every backend and explain what's missing and how to fix it.
+ */
+use anyhow::Result;
+use apple_smi::ioreg;
+use apple_smi::ioreport;
+use apple_smi::mtlapi;
+use apple_smi::pwrmtcs;
+use apple_smi::smc;
+use apple_smi::syspf;
+use apple_smi::utils;
+use objc2_metal::MTLDevice;
+use serde::Serialize;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const POWERMETRICS_BIN: &str = "/usr/bin/powermetrics";
+const ENERGY_GROUP: &str = "Energy Model";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    // Everything this backend feeds is available
+    Ok,
+    // Works, but some of its fields stay N/A
+    Limited,
+    Unavailable,
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Limited => "limited",
+            Self::Unavailable => "unavailable",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Probe {
+    pub backend: &'static str,
+    pub status: Status,
+    pub detail: String,
+    // What apple-smi reads from this backend
+    pub provides: &'static str,
+    pub fix: Option<String>,
+}
+
+fn probe(backend: &'static str, provides: &'static str, status: Status, detail: String) -> Probe {
+    Probe {
+        backend,
+        status,
+        detail,
+        provides,
+        fix: None,
+    }
+}
+
+impl Probe {
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+fn probe_syspf() -> Probe {
+    const PROVIDES: &str = "GPU list, displays, OS and hardware info";
+    // Bypass the cache, this is about whether system_profiler works right now
+    syspf::force_refresh();
+    let parsed = syspf::run_syspf(false).and_then(|json| Ok(syspf::parse::<syspf::Root>(&json)?));
+    match parsed {
+        Ok(root) if root.gpus.is_empty() => probe(
+            "system_profiler",
+            PROVIDES,
+            Status::Limited,
+            String::from("no GPU in SPDisplaysDataType"),
+        ),
+        Ok(root) => probe(
+            "system_profiler",
+            PROVIDES,
+            Status::Ok,
+            format!("{} GPU(s)", root.gpus.len()),
+        ),
+        Err(e) => probe(
+            "system_profiler",
+            PROVIDES,
+            Status::Unavailable,
+            format!("{:#}", e),
+        )
+        .fix("apple-smi needs macOS; check that /usr/sbin/system_profiler runs"),
+    }
+}
+
+// Owned by root and not group/world writable, anything else could be swapped for another program
+fn root_only(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.uid() == 0 && m.mode() & 0o022 == 0)
+}
+
+/*
+ * Running apple-smi as root is what unlocks powermetrics. NOPASSWD on a binary a
+ * user can replace hands out root, so only suggest it for a root-owned install.
+ */
+fn sudoers_fix() -> String {
+    const RUN: &str = "run `sudo apple-smi`";
+    let Some(exe) = std::env::current_exe().and_then(|p| p.canonicalize()).ok() else {
+        return String::from(RUN);
+    };
+    if !root_only(&exe) || !exe.parent().is_some_and(root_only) {
+        return format!(
+            "{}; {} is writable by non-root users, so don't put it in sudoers",
+            RUN,
+            exe.display()
+        );
+    }
+    let user = std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| String::from("<user>"));
+    format!(
+        "{}; to skip the password prompt add to /etc/sudoers.d/apple-smi (edit with `sudo visudo -f /etc/sudoers.d/apple-smi`):\n{} ALL=(root) NOPASSWD: {}",
+        RUN,
+        user,
+        exe.display()
+    )
+}
+
+fn probe_powermetrics() -> Probe {
+    const PROVIDES: &str = "GPU frequency, P-state, residency, processes";
+    if !Path::new(POWERMETRICS_BIN).exists() {
+        return probe(
+            "powermetrics",
+            PROVIDES,
+            Status::Unavailable,
+            format!("{} not found", POWERMETRICS_BIN),
+        );
+    }
+    if !utils::is_root() {
+        return probe(
+            "powermetrics",
+            PROVIDES,
+            Status::Unavailable,
+            String::from("needs root"),
+        )
+        .fix(sudoers_fix());
+    }
+    match pwrmtcs::run_pwrmtcs() {
+        Ok(m) if m.gpu_hw_freq.is_some() => probe(
+            "powermetrics",
+            PROVIDES,
+            Status::Ok,
+            String::from("gpu_power sampler works"),
+        ),
+        Ok(_) => probe(
+            "powermetrics",
+            PROVIDES,
+            Status::Limited,
+            String::from("gpu_power sampler printed no GPU frequency"),
+        ),
+        Err(e) => probe(
+            "powermetrics",
+            PROVIDES,
+            Status::Unavailable,
+            format!("{:#}", e),
+        ),
+    }
+}
+
+fn probe_ioreg() -> Probe {
+    const PROVIDES: &str = "memory usage, utilization fallback, recovery counters";
+    match ioreg::run_ioreg() {
+        Ok(a) if a.is_empty() => probe(
+            "ioreg",
+            PROVIDES,
+            Status::Unavailable,
+            String::from("no IOAccelerator service"),
+        ),
+        Ok(a) => {
+            let stats = a.iter().filter(|e| e.device_utilization.is_some()).count();
+            let detail = format!(
+                "{} IOAccelerator(s), {} with PerformanceStatistics",
+                a.len(),
+                stats
+            );
+            let status = if stats == a.len() {
+                Status::Ok
+            } else {
+                Status::Limited
+            };
+            probe("ioreg", PROVIDES, status, detail)
+        }
+        Err(e) => probe("ioreg", PROVIDES, Status::Unavailable, format!("{:#}", e)),
+    }
+}
+
+fn probe_smc() -> Probe {
+    const PROVIDES: &str = "temperatures, fans, system power";
+    let mut conn = match smc::SMC::new() {
+        Ok(c) => c,
+        Err(e) => {
+            return probe("smc", PROVIDES, Status::Unavailable, format!("{:#}", e))
+                .fix("AppleSMC is missing in virtual machines; on real hardware check that nothing else holds it exclusively");
+        }
+    };
+    match conn.key_count() {
+        Ok(n) => probe(
+            "smc",
+            PROVIDES,
+            Status::Ok,
+            format!("AppleSMC open, {} keys", n),
+        ),
+        Err(e) => probe(
+            "smc",
+            PROVIDES,
+            Status::Limited,
+            format!("opened but #KEY failed: {:#}", e),
+        ),
+    }
+}
+
+fn probe_ioreport() -> Probe {
+    const PROVIDES: &str = "GPU power draw";
+    match ioreport::channels_in_group(ENERGY_GROUP) {
+        Ok(ch) if ch.iter().any(|c| c.ends_with("GPU Energy")) => probe(
+            "ioreport",
+            PROVIDES,
+            Status::Ok,
+            format!(
+                "\"{}\" group has {} channels incl. GPU Energy",
+                ENERGY_GROUP,
+                ch.len()
+            ),
+        ),
+        Ok(ch) => probe(
+            "ioreport",
+            PROVIDES,
+            Status::Unavailable,
+            format!(
+                "\"{}\" group has {} channels but no GPU Energy",
+                ENERGY_GROUP,
+                ch.len()
+            ),
+        )
+        .fix("GPU energy is only reported on Apple Silicon"),
+        Err(e) => probe(
+            "ioreport",
+            PROVIDES,
+            Status::Unavailable,
+            format!("{:#}", e),
+        ),
+    }
+}
+
+fn probe_metal() -> Probe {
+    const PROVIDES: &str = "device properties, memory and utilization fallbacks, bench, health";
+    let devices = mtlapi::all_devices();
+    if devices.is_empty() {
+        return probe(
+            "metal",
+            PROVIDES,
+            Status::Unavailable,
+            String::from("no Metal device"),
+        )
+        .fix("Metal needs a GPU; VMs without paravirtualized graphics have none");
+    }
+    let names: Vec<String> = devices.iter().map(|d| d.name().to_string()).collect();
+    probe("metal", PROVIDES, Status::Ok, names.join(", "))
+}
+
+pub fn run_probes() -> Vec<Probe> {
+    vec![
+        probe_syspf(),
+        probe_powermetrics(),
+        probe_ioreg(),
+        probe_smc(),
+        probe_ioreport(),
+        probe_metal(),
+    ]
+}
+
+/// `doctor`: what works on this machine and how to get the rest.
+pub fn run(json: bool) -> Result<()> {
+    let probes = run_probes();
+    if json {
+        let report = serde_json::json!({
+            "root": utils::is_root(),
+            "backends": probes,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!(
+        "apple-smi {} running as {}",
+        utils::project_version(),
+        if utils::is_root() {
+            "root"
+        } else {
+            "a regular user"
+        }
+    );
+    println!();
+    for p in &probes {
+        println!("{:<16} {:<12} {}", p.backend, p.status.label(), p.detail);
+        println!("{:<16} {:<12} provides: {}", "", "", p.provides);
+        if let Some(fix) = &p.fix {
+            for (i, line) in fix.lines().enumerate() {
+                let head = if i == 0 { "fix:" } else { "    " };
+                println!("{:<16} {:<12} {} {}", "", "", head, line);
+            }
+        }
+    }
+    Ok(())
+}
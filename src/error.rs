@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * error.rs
+ * Typed errors per subsystem and the exit codes they map to.
+ */
+use crate::smc::{
+    SMC_BAD_COMMAND, SMC_INDEX_RANGE, SMC_KEY_NOT_FOUND, SMC_KEY_NOT_READABLE,
+    SMC_KEY_NOT_WRITABLE, SMC_KEY_SIZE_MISMATCH,
+};
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+use thiserror::Error;
+
+// Same values as nvidia-smi's documented return codes, so scripts can treat both alike
+pub const EXIT_INVALID_ARGUMENT: i32 = 2;
+pub const EXIT_NOT_SUPPORTED: i32 = 3;
+pub const EXIT_NO_PERMISSION: i32 = 4;
+pub const EXIT_QUERY_FAILED: i32 = 6;
+pub const EXIT_DRIVER_NOT_LOADED: i32 = 9;
+pub const EXIT_OTHER: i32 = 255;
+
+/// A kern_return_t / IOReturn from IOKit, printed with its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernReturn(pub i32);
+
+// IOReturn.h / kern_return.h, the ones IOKit hands back to a user client
+const KERN_NAMES: [(u32, &str); 20] = [
+    (0x0000_0000, "KERN_SUCCESS"),
+    (0x0000_0004, "KERN_INVALID_ARGUMENT"),
+    (0x0000_0005, "KERN_FAILURE"),
+    (0x0000_0008, "KERN_NO_ACCESS"),
+    (0xe000_02bc, "kIOReturnError"),
+    (0xe000_02bd, "kIOReturnNoMemory"),
+    (0xe000_02be, "kIOReturnNoResources"),
+    (0xe000_02bf, "kIOReturnIPCError"),
+    (0xe000_02c0, "kIOReturnNoDevice"),
+    (0xe000_02c1, "kIOReturnNotPrivileged"),
+    (0xe000_02c2, "kIOReturnBadArgument"),
+    (0xe000_02c5, "kIOReturnExclusiveAccess"),
+    (0xe000_02c7, "kIOReturnUnsupported"),
+    (0xe000_02cd, "kIOReturnNotOpen"),
+    (0xe000_02d5, "kIOReturnBusy"),
+    (0xe000_02d6, "kIOReturnTimeout"),
+    (0xe000_02d8, "kIOReturnNotReady"),
+    (0xe000_02e2, "kIOReturnNotPermitted"),
+    (0xe000_02ed, "kIOReturnNotResponding"),
+    (0xe000_02f0, "kIOReturnNotFound"),
+];
+
+impl KernReturn {
+    pub fn name(&self) -> Option<&'static str> {
+        KERN_NAMES
+            .iter()
+            .find(|(code, _)| *code == self.0 as u32)
+            .map(|(_, name)| *name)
+    }
+
+    pub fn is_permission(&self) -> bool {
+        matches!(
+            self.name(),
+            Some("KERN_NO_ACCESS" | "kIOReturnNotPrivileged" | "kIOReturnNotPermitted")
+        )
+    }
+}
+
+impl fmt::Display for KernReturn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} (0x{:08x})", name, self.0 as u32),
+            None => write!(f, "0x{:08x}", self.0 as u32),
+        }
+    }
+}
+
+fn smc_result_name(code: u8) -> &'static str {
+    match code {
+        SMC_BAD_COMMAND => "bad command",
+        SMC_KEY_NOT_FOUND => "key not found",
+        SMC_KEY_NOT_READABLE => "key not readable",
+        SMC_KEY_NOT_WRITABLE => "key not writable",
+        SMC_KEY_SIZE_MISMATCH => "key size mismatch",
+        SMC_INDEX_RANGE => "index out of range",
+        _ => "error",
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SmcError {
+    #[error("AppleSMCKeysEndpoint not found")]
+    ServiceNotFound,
+    #[error("{call}: {kr}")]
+    Kern { call: &'static str, kr: KernReturn },
+    #[error("SMC key must be 4 chars, got '{0}'")]
+    BadKey(String),
+    #[error("SMC key '{0}' not found")]
+    KeyNotFound(String),
+    #[error("SMC key '{key}': {} ({code})", smc_result_name(*.code))]
+    Result { key: String, code: u8 },
+    #[error("SMC key '{key}' expects {expected} bytes, got {got}")]
+    WrongSize {
+        key: String,
+        expected: u32,
+        got: usize,
+    },
+    #[error("don't know how to encode {unit} for SMC key '{key}'")]
+    Encode { key: String, unit: String },
+}
+
+impl SmcError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::ServiceNotFound => EXIT_DRIVER_NOT_LOADED,
+            Self::Kern { kr, .. } if kr.is_permission() => EXIT_NO_PERMISSION,
+            Self::Kern { call, .. } if *call == "IOServiceOpen" => EXIT_DRIVER_NOT_LOADED,
+            Self::Kern { .. } => EXIT_QUERY_FAILED,
+            Self::BadKey(_) | Self::WrongSize { .. } => EXIT_INVALID_ARGUMENT,
+            Self::KeyNotFound(_) => EXIT_QUERY_FAILED,
+            Self::Result {
+                code: SMC_KEY_NOT_WRITABLE,
+                ..
+            }
+            | Self::Encode { .. } => EXIT_NOT_SUPPORTED,
+            Self::Result {
+                code: SMC_KEY_SIZE_MISMATCH,
+                ..
+            } => EXIT_INVALID_ARGUMENT,
+            Self::Result { .. } => EXIT_QUERY_FAILED,
+        }
+    }
+}
+
+/// A child process (system_profiler, powermetrics, ioreg, pmset) that didn't deliver.
+#[derive(Debug, Error)]
+pub enum CmdError {
+    #[error("can't run {program}")]
+    Spawn { program: String, source: io::Error },
+    #[error("{program} exited with {status}")]
+    Exit { program: String, status: ExitStatus },
+    #[error("{program} timed out after {} ms", .timeout.as_millis())]
+    Timeout { program: String, timeout: Duration },
+}
+
+impl CmdError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // The tool itself is missing, most likely not macOS
+            Self::Spawn { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+                EXIT_NOT_SUPPORTED
+            }
+            Self::Spawn { source, .. } if source.kind() == io::ErrorKind::PermissionDenied => {
+                EXIT_NO_PERMISSION
+            }
+            Self::Spawn { .. } => EXIT_OTHER,
+            Self::Exit { .. } | Self::Timeout { .. } => EXIT_QUERY_FAILED,
+        }
+    }
+}
+
+/// Output we couldn't make sense of, with the line that broke it.
+#[derive(Debug, Error)]
+#[error("{what} line {line}: {reason}: '{text}'")]
+pub struct ParseError {
+    pub what: &'static str,
+    // 1-based
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+// Offending lines can be whole JSON documents, keep messages readable
+const MAX_LINE_CHARS: usize = 80;
+
+impl ParseError {
+    pub fn new(what: &'static str, line: usize, text: &str, reason: impl Into<String>) -> Self {
+        Self {
+            what,
+            line,
+            text: text.trim().chars().take(MAX_LINE_CHARS).collect(),
+            reason: reason.into(),
+        }
+    }
+
+    /// serde_json knows the line, pick its text out of the input.
+    pub fn json(what: &'static str, input: &str, e: &serde_json::Error) -> Self {
+        let text = input.lines().nth(e.line().saturating_sub(1)).unwrap_or("");
+        Self::new(what, e.line(), text, e.to_string())
+    }
+}
+
+/// Errors that aren't tied to one backend.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    #[error("{0}")]
+    NotSupported(String),
+    #[error("{0}")]
+    NoDevice(String),
+    // A collector source that failed on its own thread, typed error already flattened
+    #[error("{backend} failed: {message}")]
+    Source {
+        backend: &'static str,
+        message: String,
+        exit_code: i32,
+    },
+    #[error(transparent)]
+    Cmd(#[from] CmdError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("{context}")]
+    Io { context: String, source: io::Error },
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::InvalidArgument(_) => EXIT_INVALID_ARGUMENT,
+            Self::PermissionDenied(_) => EXIT_NO_PERMISSION,
+            Self::NotSupported(_) => EXIT_NOT_SUPPORTED,
+            Self::NoDevice(_) => EXIT_DRIVER_NOT_LOADED,
+            Self::Source { exit_code, .. } => *exit_code,
+            Self::Cmd(e) => e.exit_code(),
+            Self::Parse(_) => EXIT_QUERY_FAILED,
+            Self::Io { .. } => EXIT_OTHER,
+        }
+    }
+}
+
+/// Exit code for an error that reached main, from the first typed error in its chain.
+pub fn exit_code(e: &anyhow::Error) -> i32 {
+    for cause in e.chain() {
+        if let Some(e) = cause.downcast_ref::<SmcError>() {
+            return e.exit_code();
+        }
+        if let Some(e) = cause.downcast_ref::<CmdError>() {
+            return e.exit_code();
+        }
+        if cause.downcast_ref::<ParseError>().is_some() {
+            return EXIT_QUERY_FAILED;
+        }
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            return e.exit_code();
+        }
+    }
+    EXIT_OTHER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn kern(call: &'static str, kr: u32) -> SmcError {
+        SmcError::Kern {
+            call,
+            kr: KernReturn(kr as i32),
+        }
+    }
+
+    fn result(code: u8) -> SmcError {
+        SmcError::Result {
+            key: String::from("F0Md"),
+            code,
+        }
+    }
+
+    #[test]
+    fn smc_exit_codes() {
+        let key = || String::from("F0Md");
+        let table = [
+            (SmcError::ServiceNotFound, EXIT_DRIVER_NOT_LOADED),
+            (
+                kern("IOConnectCallStructMethod", 0xe000_02c1),
+                EXIT_NO_PERMISSION,
+            ),
+            (kern("IOServiceOpen", 0xe000_02e2), EXIT_NO_PERMISSION),
+            (kern("IOServiceOpen", 0xe000_02c0), EXIT_DRIVER_NOT_LOADED),
+            (
+                kern("IOConnectCallStructMethod", 0xe000_02d6),
+                EXIT_QUERY_FAILED,
+            ),
+            (SmcError::BadKey(String::from("F0")), EXIT_INVALID_ARGUMENT),
+            (SmcError::KeyNotFound(key()), EXIT_QUERY_FAILED),
+            (result(SMC_KEY_NOT_WRITABLE), EXIT_NOT_SUPPORTED),
+            (result(SMC_KEY_SIZE_MISMATCH), EXIT_INVALID_ARGUMENT),
+            (result(SMC_KEY_NOT_READABLE), EXIT_QUERY_FAILED),
+            (
+                SmcError::WrongSize {
+                    key: key(),
+                    expected: 1,
+                    got: 4,
+                },
+                EXIT_INVALID_ARGUMENT,
+            ),
+            (
+                SmcError::Encode {
+                    key: key(),
+                    unit: String::from("ch8*"),
+                },
+                EXIT_NOT_SUPPORTED,
+            ),
+        ];
+        for (e, code) in table {
+            assert_eq!(e.exit_code(), code, "{}", e);
+        }
+    }
+
+    #[test]
+    fn cmd_exit_codes() {
+        let spawn = |kind| CmdError::Spawn {
+            program: String::from("powermetrics"),
+            source: io::Error::from(kind),
+        };
+        let table = [
+            (spawn(io::ErrorKind::NotFound), EXIT_NOT_SUPPORTED),
+            (spawn(io::ErrorKind::PermissionDenied), EXIT_NO_PERMISSION),
+            (spawn(io::ErrorKind::Other), EXIT_OTHER),
+            (
+                CmdError::Exit {
+                    program: String::from("ioreg"),
+                    status: ExitStatus::from_raw(1 << 8),
+                },
+                EXIT_QUERY_FAILED,
+            ),
+            (
+                CmdError::Timeout {
+                    program: String::from("system_profiler"),
+                    timeout: Duration::from_secs(5),
+                },
+                EXIT_QUERY_FAILED,
+            ),
+        ];
+        for (e, code) in table {
+            assert_eq!(e.exit_code(), code, "{}", e);
+        }
+    }
+
+    #[test]
+    fn error_exit_codes() {
+        let msg = || String::from("x");
+        let table = [
+            (Error::InvalidArgument(msg()), EXIT_INVALID_ARGUMENT),
+            (Error::PermissionDenied(msg()), EXIT_NO_PERMISSION),
+            (Error::NotSupported(msg()), EXIT_NOT_SUPPORTED),
+            (Error::NoDevice(msg()), EXIT_DRIVER_NOT_LOADED),
+            (
+                Error::Source {
+                    backend: "smc",
+                    message: msg(),
+                    exit_code: EXIT_NO_PERMISSION,
+                },
+                EXIT_NO_PERMISSION,
+            ),
+            (
+                Error::Cmd(CmdError::Exit {
+                    program: msg(),
+                    status: ExitStatus::from_raw(1 << 8),
+                }),
+                EXIT_QUERY_FAILED,
+            ),
+            (
+                Error::Parse(ParseError::new("ioreg", 3, "<", "bad plist")),
+                EXIT_QUERY_FAILED,
+            ),
+            (
+                Error::Io {
+                    context: msg(),
+                    source: io::Error::from(io::ErrorKind::Other),
+                },
+                EXIT_OTHER,
+            ),
+        ];
+        for (e, code) in table {
+            assert_eq!(e.exit_code(), code, "{}", e);
+        }
+    }
+
+    #[test]
+    fn chain_uses_the_first_typed_error() {
+        let wrapped: anyhow::Error = anyhow::Error::new(SmcError::ServiceNotFound)
+            .context("reading fans")
+            .context("apple-smi fan");
+        assert_eq!(exit_code(&wrapped), EXIT_DRIVER_NOT_LOADED);
+
+        let io = Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied));
+        let cmd = io
+            .map_err(|source| CmdError::Spawn {
+                program: String::from("pmset"),
+                source,
+            })
+            .context("battery")
+            .unwrap_err();
+        assert_eq!(exit_code(&cmd), EXIT_NO_PERMISSION);
+
+        let parse = anyhow::Error::new(ParseError::new("pmset", 1, "?", "no percentage"));
+        assert_eq!(exit_code(&parse.context("battery")), EXIT_QUERY_FAILED);
+        let typed = anyhow::Error::new(Error::NotSupported(String::from("Intel Mac")));
+        assert_eq!(exit_code(&typed.context("doctor")), EXIT_NOT_SUPPORTED);
+
+        assert_eq!(exit_code(&anyhow::anyhow!("untyped")), EXIT_OTHER);
+    }
+}
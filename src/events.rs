@@ -0,0 +1,307 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * events.rs
+ * GPU recovery events, the closest thing Apple GPUs have to Xid errors.
+ */
+use crate::daemon;
+use anyhow::Result;
+use apple_smi::ioreg::{self, AccelEntry};
+use apple_smi::pwrmtcs;
+use apple_smi::utils;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventProcess {
+    pub pid: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuEvent {
+    pub timestamp: String,
+    pub gpu: usize,
+    pub registry_id: Option<String>,
+    pub kind: String,
+    pub previous_count: u64,
+    pub recovery_count: u64,
+    pub last_recovery_time: Option<u64>,
+    // Processes seen when the event was noticed, needs root (powermetrics)
+    pub processes: Vec<EventProcess>,
+}
+
+// (recoveryCount, lastRecoveryTime)
+type Counters = (u64, Option<u64>);
+
+/// Remembers counters between samples and reports increases.
+#[derive(Default)]
+pub struct EventTracker {
+    last: HashMap<String, Counters>,
+}
+
+// Registry ID survives reordering, the index is only a fallback
+fn accel_key(i: usize, a: &AccelEntry) -> String {
+    match a.registry_id {
+        Some(id) => format!("0x{:x}", id),
+        None => format!("#{}", i),
+    }
+}
+
+impl EventTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first sample of a GPU only sets its baseline.
+    pub fn observe(&mut self, accels: &[AccelEntry]) -> Vec<GpuEvent> {
+        let mut events = self.changes(accels);
+        if !events.is_empty() {
+            let procs = active_processes();
+            for e in &mut events {
+                e.processes = procs.clone();
+            }
+        }
+        events
+    }
+
+    fn changes(&mut self, accels: &[AccelEntry]) -> Vec<GpuEvent> {
+        let mut events = Vec::new();
+        let mut seen = Vec::with_capacity(accels.len());
+        for (i, a) in accels.iter().enumerate() {
+            let Some(count) = a.recovery_count else {
+                continue;
+            };
+            let key = accel_key(i, a);
+            seen.push(key.clone());
+            let now = (count, a.last_recovery_time);
+            if let Some(prev) = self.last.insert(key, now) {
+                // A changed lastRecoveryTime is a new recovery too, even when a driver
+                // reload reset the count below what we saw last
+                if count > prev.0 || (count > 0 && now.1 != prev.1) {
+                    events.push(GpuEvent {
+                        timestamp: Local::now().format("%a %b %e %T %Y").to_string(),
+                        gpu: i,
+                        registry_id: a.registry_id.map(|id| format!("0x{:x}", id)),
+                        kind: String::from("recovery"),
+                        previous_count: prev.0,
+                        recovery_count: count,
+                        last_recovery_time: a.last_recovery_time,
+                        processes: Vec::new(),
+                    });
+                }
+            }
+        }
+        // A GPU that comes back (or another one at its index) starts a new baseline
+        self.last.retain(|k, _| seen.contains(k));
+        events
+    }
+}
+
+fn active_processes() -> Vec<EventProcess> {
+    if !utils::is_root() {
+        return Vec::new();
+    }
+    pwrmtcs::run_pwrmtcs_procs()
+        .map(|procs| {
+            procs
+                .into_iter()
+                .map(|p| EventProcess {
+                    pid: p.pid,
+                    name: p.name,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn print_event(e: &GpuEvent) {
+    let procs = if e.processes.is_empty() {
+        String::from("N/A")
+    } else {
+        e.processes
+            .iter()
+            .map(|p| format!("{} ({})", p.name, p.pid))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    println!(
+        "{}  GPU {} ({})  {}: count {} -> {}, lastRecoveryTime {}, processes: {}",
+        e.timestamp,
+        e.gpu,
+        e.registry_id.as_deref().unwrap_or("N/A"),
+        e.kind,
+        e.previous_count,
+        e.recovery_count,
+        e.last_recovery_time
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| String::from("N/A")),
+        procs
+    );
+}
+
+// The daemon already polls (and lists processes as root), print what it sends
+fn follow_daemon(lines: impl Iterator<Item = std::io::Result<String>>, json: bool) -> Result<()> {
+    if !json {
+        println!("Watching for recovery events from the apple-smi daemon, Ctrl-C to stop.");
+    }
+    for line in lines {
+        let msg: serde_json::Value = serde_json::from_str(&line?)?;
+        let e: GpuEvent = serde_json::from_value(msg["params"].clone())?;
+        if json {
+            println!("{}", serde_json::to_string(&e)?);
+        } else {
+            print_event(&e);
+        }
+    }
+    Ok(())
+}
+
+/// `events`: poll IOAccelerator statistics and stream recovery events until interrupted.
+pub fn run(interval_ms: u64, json: bool) -> Result<()> {
+    if let Some(lines) = daemon::subscribe_events() {
+        return follow_daemon(lines, json);
+    }
+    utils::install_stop_handler();
+    let mut tracker = EventTracker::new();
+    let baseline = ioreg::run_ioreg()?;
+    tracker.observe(&baseline);
+    if !json {
+        println!(
+            "Watching {} GPU(s) for recovery events every {} ms, Ctrl-C to stop.",
+            baseline.len(),
+            interval_ms
+        );
+    }
+
+    let interval = Duration::from_millis(interval_ms);
+    while !utils::stop_requested() {
+        let tick = Instant::now();
+        while tick.elapsed() < interval && !utils::stop_requested() {
+            std::thread::sleep(Duration::from_millis(50).min(interval));
+        }
+        if utils::stop_requested() {
+            break;
+        }
+        // ioreg can fail transiently, just try again next tick
+        let Ok(accels) = ioreg::run_ioreg() else {
+            continue;
+        };
+        for e in tracker.observe(&accels) {
+            if json {
+                // One object per line so it can be piped
+                println!("{}", serde_json::to_string(&e)?);
+            } else {
+                print_event(&e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apple_smi::ioreg::VramInfo;
+
+    fn accel(registry_id: Option<u64>, count: Option<u64>, time: Option<u64>) -> AccelEntry {
+        AccelEntry {
+            registry_id,
+            class_name: String::from("AGXAcceleratorG16G"),
+            model: None,
+            core_count: None,
+            vram: VramInfo {
+                alloc_vram: None,
+                inuse_vram: None,
+            },
+            device_utilization: None,
+            recovery_count: count,
+            last_recovery_time: time,
+        }
+    }
+
+    // (gpu, previous_count, recovery_count) per event
+    fn seen(t: &mut EventTracker, accels: &[AccelEntry]) -> Vec<(usize, u64, u64)> {
+        t.changes(accels)
+            .iter()
+            .map(|e| (e.gpu, e.previous_count, e.recovery_count))
+            .collect()
+    }
+
+    #[test]
+    fn first_sample_is_the_baseline() {
+        let mut t = EventTracker::new();
+        assert_eq!(seen(&mut t, &[accel(Some(0x10), Some(3), Some(500))]), []);
+        assert_eq!(seen(&mut t, &[accel(Some(0x10), Some(3), Some(500))]), []);
+        // No counter, nothing to compare
+        assert_eq!(seen(&mut t, &[accel(Some(0x20), None, None)]), []);
+    }
+
+    #[test]
+    fn one_event_per_gpu_that_recovered() {
+        let mut t = EventTracker::new();
+        let idle = [
+            accel(Some(0x10), Some(0), None),
+            accel(Some(0x20), Some(1), Some(100)),
+        ];
+        seen(&mut t, &idle);
+        // Several recoveries between samples are still one event
+        let both = [
+            accel(Some(0x10), Some(3), Some(900)),
+            accel(Some(0x20), Some(1), Some(800)),
+        ];
+        assert_eq!(seen(&mut t, &both), [(0, 0, 3), (1, 1, 1)]);
+        assert_eq!(seen(&mut t, &both), []);
+
+        // Reordered GPUs keep their baseline through the registry ID
+        let swapped = [
+            accel(Some(0x20), Some(1), Some(800)),
+            accel(Some(0x10), Some(4), Some(950)),
+        ];
+        let events = t.changes(&swapped);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].gpu, events[0].registry_id.as_deref()),
+            (1, Some("0x10"))
+        );
+        assert_eq!(
+            (events[0].kind.as_str(), events[0].last_recovery_time),
+            ("recovery", Some(950))
+        );
+    }
+
+    #[test]
+    fn counter_reset_after_reload() {
+        let mut t = EventTracker::new();
+        seen(&mut t, &[accel(Some(0x10), Some(5), Some(900))]);
+        // Reboot or driver reload: back to zero is not a recovery
+        assert_eq!(seen(&mut t, &[accel(Some(0x10), Some(0), None)]), []);
+        assert_eq!(
+            seen(&mut t, &[accel(Some(0x10), Some(1), Some(40))]),
+            [(0, 0, 1)]
+        );
+        // Reset straight to a lower non-zero count with a new time still counts
+        seen(&mut t, &[accel(Some(0x10), Some(5), Some(900))]);
+        assert_eq!(
+            seen(&mut t, &[accel(Some(0x10), Some(2), Some(30))]),
+            [(0, 5, 2)]
+        );
+    }
+
+    #[test]
+    fn gpu_disappearing_drops_its_baseline() {
+        let mut t = EventTracker::new();
+        let two = [accel(None, Some(0), None), accel(None, Some(7), Some(700))];
+        seen(&mut t, &two);
+        // eGPU unplugged
+        assert_eq!(seen(&mut t, &two[..1]), []);
+        // Another one at the same index is a new baseline, not a jump from 7
+        let other = [accel(None, Some(0), None), accel(None, Some(9), Some(20))];
+        assert_eq!(seen(&mut t, &other), []);
+        assert_eq!(seen(&mut t, &[accel(None, Some(0), None)]), []);
+        assert!(t.last.keys().eq(["#0"].iter()));
+    }
+}
@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * fan.rs
+ * Hold a fan in manual mode via SMC writes, with automatic mode restored on drop.
+ */
+use crate::error::{Error, SmcError};
+use crate::smc::{self, FanReading, SMC};
+use anyhow::{Context, Result};
+
+/// Look up a fan and its limits; errors if the fan doesn't exist.
+pub fn probe_fan(smc: &mut SMC, index: u8) -> Result<FanReading> {
+    let ac = smc::fan_key(index, "Ac");
+    // Only a missing key means there is no such fan, anything else is the SMC's own error
+    let v = match smc.read_val(&ac) {
+        Ok(v) => v,
+        Err(SmcError::KeyNotFound(_)) => {
+            return Err(Error::InvalidArgument(format!("fan {} not found", index)).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut fan = FanReading {
+        index,
+        rpm: smc::decode_numeric(&v).unwrap_or(0.0),
+        key: ac,
+        encoding: v.unit,
+        min_rpm: None,
+        max_rpm: None,
+        target_rpm: None,
+        mode: None,
+    };
+    smc::read_fan_details(smc, &mut fan);
+    Ok(fan)
+}
+
+/*
+ * Reject anything outside F?Mn..F?Mx (NaN included). If either limit can't be read
+ * we refuse rather than guess, running a fan below its minimum is how boards get cooked.
+ */
+pub fn validate_rpm(fan: &FanReading, rpm: f32) -> Result<()> {
+    let (min, max) = match (fan.min_rpm, fan.max_rpm) {
+        (Some(min), Some(max)) => (min, max),
+        _ => {
+            return Err(Error::NotSupported(format!(
+                "fan {} doesn't report min/max speed, refusing to set it",
+                fan.index
+            ))
+            .into());
+        }
+    };
+    if !(min..=max).contains(&rpm) {
+        return Err(Error::InvalidArgument(format!(
+            "{} RPM is outside fan {} range {:.0}..{:.0} RPM",
+            rpm, fan.index, min, max
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// A fan held in manual mode. Dropping it hands control back to the SMC.
+pub struct ManualFan<'a> {
+    smc: &'a mut SMC,
+    index: u8,
+    // Apple Silicon only accepts F?Md writes after "Ftst" is set, remember to clear it
+    unlocked: bool,
+}
+
+impl<'a> ManualFan<'a> {
+    pub fn engage(smc: &'a mut SMC, index: u8, rpm: f32) -> Result<Self> {
+        let fan = probe_fan(smc, index)?;
+        validate_rpm(&fan, rpm)?;
+
+        let md = smc::fan_key(index, "Md");
+        let mut unlocked = false;
+        if smc.write_num(&md, 1.0).is_err() {
+            smc.write_num("Ftst", 1.0)
+                .context("unable to unlock fan control")?;
+            unlocked = true;
+            // No guard yet, so undo the unlock ourselves
+            if let Err(e) = smc.write_num(&md, 1.0) {
+                let _ = smc.write_num("Ftst", 0.0);
+                return Err(e).context("unable to switch fan to manual mode");
+            }
+        }
+
+        let mut guard = Self {
+            smc,
+            index,
+            unlocked,
+        };
+        guard.set_rpm(rpm)?;
+        Ok(guard)
+    }
+
+    pub fn set_rpm(&mut self, rpm: f32) -> Result<()> {
+        Ok(self.smc.write_num(&smc::fan_key(self.index, "Tg"), rpm)?)
+    }
+}
+
+impl Drop for ManualFan<'_> {
+    fn drop(&mut self) {
+        let _ = restore_auto(self.smc, self.index, self.unlocked);
+    }
+}
+
+/// Put a fan back under SMC control, clearing Ftst too if `unlocked`.
+pub fn restore_auto(smc: &mut SMC, index: u8, unlocked: bool) -> Result<()> {
+    let res = smc.write_num(&smc::fan_key(index, "Md"), 0.0);
+    if unlocked {
+        smc.write_num("Ftst", 0.0)?;
+    }
+    Ok(res?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::exit_code;
+    use crate::smcsim::SimSmc;
+
+    fn sim(text: &str) -> SMC {
+        SMC::with_conn(Box::new(SimSmc::parse(text).unwrap()))
+    }
+
+    fn fixture(name: &str) -> SMC {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/smc")
+            .join(name);
+        SMC::from_dump(&path).unwrap()
+    }
+
+    fn num(smc: &mut SMC, key: &str) -> f32 {
+        let v = smc.read_val(key).unwrap();
+        smc::decode_numeric(&v)
+            .or_else(|| smc::decode_integer(&v).map(|x| x as f32))
+            .unwrap()
+    }
+
+    #[test]
+    fn rejects_out_of_range_and_nan() {
+        let mut smc = fixture("Mac16,10-M4.txt");
+        let fan = probe_fan(&mut smc, 0).unwrap();
+        assert_eq!((fan.min_rpm, fan.max_rpm), (Some(1000.0), Some(4900.0)));
+        assert!(validate_rpm(&fan, 1000.0).is_ok());
+        assert!(validate_rpm(&fan, 4900.0).is_ok());
+        for rpm in [999.0, 4901.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let e = validate_rpm(&fan, rpm).unwrap_err();
+            assert_eq!(exit_code(&e), 2, "{}", rpm);
+        }
+        assert!(ManualFan::engage(&mut smc, 0, f32::NAN).is_err());
+        assert_eq!(num(&mut smc, "F0Md"), 0.0);
+    }
+
+    #[test]
+    fn missing_fan_is_invalid_argument() {
+        let mut smc = fixture("MacBookAir10,1-M1.txt");
+        let e = probe_fan(&mut smc, 0).unwrap_err();
+        assert_eq!(exit_code(&e), 2);
+    }
+
+    #[test]
+    fn probe_keeps_smc_errors() {
+        // F0Ac exists but can't be read, that's the SMC failing, not "fan not found"
+        let mut smc = sim("F0Ac  [flt ]     4  0x80  -\n");
+        let e = probe_fan(&mut smc, 0).unwrap_err();
+        assert!(
+            matches!(e.downcast_ref(), Some(SmcError::Result { .. })),
+            "{:#}",
+            e
+        );
+        assert_ne!(exit_code(&e), 2);
+    }
+
+    #[test]
+    fn unlocks_with_ftst_and_restores_on_drop() {
+        let mut smc = fixture("Mac14,9-M2Pro.txt");
+        {
+            let _fan = ManualFan::engage(&mut smc, 1, 3000.0).unwrap();
+        }
+        assert_eq!(num(&mut smc, "F1Md"), 0.0);
+        assert_eq!(num(&mut smc, "Ftst"), 0.0);
+        assert_eq!(num(&mut smc, "F1Tg"), 3000.0);
+
+        let mut fan = ManualFan::engage(&mut smc, 1, 3000.0).unwrap();
+        fan.set_rpm(3500.0).unwrap();
+        assert_eq!(num(fan.smc, "F1Md"), 1.0);
+        assert_eq!(num(fan.smc, "Ftst"), 1.0);
+        assert_eq!(num(fan.smc, "F1Tg"), 3500.0);
+        drop(fan);
+        assert_eq!((num(&mut smc, "F1Md"), num(&mut smc, "Ftst")), (0.0, 0.0));
+    }
+
+    #[test]
+    fn clears_ftst_when_manual_mode_fails() {
+        // F0Md is read-only here, so the unlock succeeds and the mode write still fails
+        let mut smc = sim("\
+Ftst  [ui8 ]     1  0xc0  00  0
+F0Ac  [flt ]     4  0x80  00 80 7a 44  1002
+F0Md  [ui8 ]     1  0x80  00  0
+F0Mn  [flt ]     4  0x80  00 00 7a 44  1000
+F0Mx  [flt ]     4  0x80  00 20 99 45  4900
+F0Tg  [flt ]     4  0xc0  00 80 7a 44  1002
+");
+        assert!(ManualFan::engage(&mut smc, 0, 2000.0).is_err());
+        assert_eq!(num(&mut smc, "Ftst"), 0.0);
+        assert_eq!(num(&mut smc, "F0Tg"), 1002.0);
+    }
+}
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * fanctl.rs
+ * `fan set`: pin a fan at an RPM until Ctrl-C, or hand it back to the SMC.
+ */
+use anyhow::Result;
+use apple_smi::error::Error;
+use apple_smi::fan::{ManualFan, probe_fan, restore_auto};
+use apple_smi::smc::SMC;
+use std::time::{Duration, Instant};
+
+pub enum FanTarget {
+    Auto,
+    Rpm(f32),
+}
+
+impl FanTarget {
+    pub fn parse(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        let rpm: f32 = s.parse().map_err(|_| {
+            Error::InvalidArgument(format!(
+                "fan target must be an RPM value or 'auto', got '{}'",
+                s
+            ))
+        })?;
+        Ok(Self::Rpm(rpm))
+    }
+}
+
+/// `apple-smi fan set <idx> <rpm|auto>`
+pub fn run_set(index: u8, target: FanTarget) -> Result<()> {
+    if !apple_smi::utils::is_root() {
+        return Err(Error::PermissionDenied(String::from(
+            "setting fan speed requires root, try sudo",
+        ))
+        .into());
+    }
+    let mut smc = SMC::new()?;
+
+    let rpm = match target {
+        FanTarget::Auto => {
+            probe_fan(&mut smc, index)?;
+            // Ftst may have been left set by a crashed run, clear it as well
+            let has_ftst = smc.read_val("Ftst").is_ok();
+            restore_auto(&mut smc, index, has_ftst)?;
+            println!("Fan {} returned to automatic control.", index);
+            return Ok(());
+        }
+        FanTarget::Rpm(rpm) => rpm,
+    };
+
+    apple_smi::utils::install_stop_handler();
+    let mut fan = ManualFan::engage(&mut smc, index, rpm)?;
+    println!(
+        "Fan {} pinned at {:.0} RPM. Press Ctrl-C to restore automatic control.",
+        index, rpm
+    );
+
+    // The SMC may drift back on its own, so re-assert the target while we hold the fan
+    let mut last = Instant::now();
+    while !apple_smi::utils::stop_requested() {
+        std::thread::sleep(Duration::from_millis(200));
+        if last.elapsed() >= Duration::from_secs(5) {
+            fan.set_rpm(rpm)?;
+            last = Instant::now();
+        }
+    }
+
+    drop(fan);
+    println!("Fan {} returned to automatic control.", index);
+    Ok(())
+}
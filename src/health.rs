@@ -0,0 +1,430 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * health.rs
+ * Pass / warn / fail checks with Nagios plugin exit codes.
+ */
+use apple_smi::ioreg;
+use apple_smi::mtlapi::{self, Liveness};
+use apple_smi::smc;
+use apple_smi::thermal::{self, ThermalPressure};
+use objc2_metal::MTLDevice;
+use serde::Serialize;
+use std::time::Duration;
+
+// Ordered by severity, the worst check decides the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Status {
+    Ok,
+    Unknown,
+    Warning,
+    Critical,
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Ok => "OK",
+            Self::Unknown => "UNKNOWN",
+            Self::Warning => "WARNING",
+            Self::Critical => "CRITICAL",
+        }
+    }
+
+    /// Nagios plugin API: 0 OK, 1 WARNING, 2 CRITICAL, 3 UNKNOWN.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Ok => 0,
+            Self::Warning => 1,
+            Self::Critical => 2,
+            Self::Unknown => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+    // Nagios perfdata, "label=value[UOM]"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub perfdata: Vec<String>,
+}
+
+fn check(name: &str, status: Status, message: impl Into<String>) -> Check {
+    Check {
+        name: name.to_string(),
+        status,
+        message: message.into(),
+        perfdata: Vec::new(),
+    }
+}
+
+fn liveness_check(name: &str, gpu: &str, liveness: Liveness, timeout: Duration) -> Check {
+    match liveness {
+        Liveness::Completed(t) => {
+            let ms = t.as_secs_f64() * 1000.0;
+            let mut c = check(
+                name,
+                Status::Ok,
+                format!("{} completed a command buffer in {:.1} ms", gpu, ms),
+            );
+            c.perfdata.push(format!("{}_latency={:.3}ms", name, ms));
+            c
+        }
+        Liveness::Failed(e) => check(name, Status::Critical, format!("{}: {}", gpu, e)),
+        Liveness::TimedOut => check(
+            name,
+            Status::Critical,
+            format!(
+                "{} didn't complete a command buffer within {} ms",
+                gpu,
+                timeout.as_millis()
+            ),
+        ),
+    }
+}
+
+fn check_metal(timeout: Duration) -> Vec<Check> {
+    let devices = mtlapi::all_devices();
+    if devices.is_empty() {
+        return vec![check("metal", Status::Critical, "no Metal device")];
+    }
+    devices
+        .iter()
+        .enumerate()
+        .map(|(i, d)| {
+            let liveness = mtlapi::probe_liveness(d, timeout);
+            liveness_check(
+                &format!("metal{}", i),
+                &d.name().to_string(),
+                liveness,
+                timeout,
+            )
+        })
+        .collect()
+}
+
+fn recovery_check(i: usize, a: &ioreg::AccelEntry) -> Check {
+    let name = format!("recovery{}", i);
+    let mut c = match a.recovery_count {
+        None => check(&name, Status::Unknown, "recoveryCount not reported"),
+        Some(0) => check(&name, Status::Ok, "no GPU recoveries since boot"),
+        Some(n) => check(
+            &name,
+            Status::Warning,
+            format!(
+                "{} GPU recoveries since boot (lastRecoveryTime {})",
+                n,
+                a.last_recovery_time.unwrap_or(0)
+            ),
+        ),
+    };
+    if let Some(n) = a.recovery_count {
+        c.perfdata.push(format!("{}_count={}", name, n));
+    }
+    c
+}
+
+fn check_recovery() -> Vec<Check> {
+    let accels = match ioreg::run_ioreg() {
+        Ok(a) if !a.is_empty() => a,
+        Ok(_) => return vec![check("recovery", Status::Unknown, "no IOAccelerator found")],
+        Err(e) => return vec![check("recovery", Status::Unknown, format!("ioreg: {}", e))],
+    };
+    accels
+        .iter()
+        .enumerate()
+        .map(|(i, a)| recovery_check(i, a))
+        .collect()
+}
+
+fn thermal_check(pressure: anyhow::Result<ThermalPressure>) -> Check {
+    match pressure {
+        Ok(p) => {
+            let status = match p {
+                ThermalPressure::Nominal => Status::Ok,
+                ThermalPressure::Moderate => Status::Warning,
+                _ => Status::Critical,
+            };
+            check("thermal", status, format!("thermal pressure {}", p.label()))
+        }
+        Err(e) => check("thermal", Status::Unknown, e.to_string()),
+    }
+}
+
+fn check_thermal() -> Check {
+    thermal_check(thermal::thermal_pressure())
+}
+
+// Below min is tolerated by this much before warning, the SMC ramps gradually
+const FAN_SLACK: f32 = 0.9;
+
+fn check_fan(f: &smc::FanReading) -> Check {
+    let name = format!("fan{}", f.index);
+    let commanded = f.mode == Some(1) || f.target_rpm.is_some_and(|t| t > 0.0);
+    let mut c = match (f.min_rpm, f.max_rpm) {
+        // Spinning down to 0 is normal at idle on Apple Silicon unless asked to spin
+        _ if f.rpm <= 0.0 && commanded => check(
+            &name,
+            Status::Critical,
+            format!(
+                "stalled: 0 RPM with target {:.0} RPM",
+                f.target_rpm.unwrap_or(0.0)
+            ),
+        ),
+        (Some(min), _) if f.rpm > 0.0 && f.rpm < min * FAN_SLACK => check(
+            &name,
+            Status::Warning,
+            format!("{:.0} RPM is below the {:.0} RPM minimum", f.rpm, min),
+        ),
+        (_, Some(max)) if f.rpm > max / FAN_SLACK => check(
+            &name,
+            Status::Warning,
+            format!("{:.0} RPM is above the {:.0} RPM maximum", f.rpm, max),
+        ),
+        _ => check(&name, Status::Ok, format!("{:.0} RPM", f.rpm)),
+    };
+    c.perfdata.push(format!("{}_rpm={:.0}", name, f.rpm));
+    c
+}
+
+fn check_smc() -> Vec<Check> {
+    let mut conn = match smc::SMC::new() {
+        Ok(c) => c,
+        Err(e) => {
+            return vec![
+                check("smc", Status::Critical, format!("can't open SMC: {}", e)),
+                check("fans", Status::Unknown, "SMC unavailable"),
+            ];
+        }
+    };
+    let mut checks = vec![match conn.key_count() {
+        Ok(n) => check("smc", Status::Ok, format!("{} keys", n)),
+        Err(e) => check("smc", Status::Critical, format!("can't read #KEY: {}", e)),
+    }];
+    match smc::read_snapshot_from(&mut conn) {
+        Ok(s) if s.fans.is_empty() => checks.push(check("fans", Status::Ok, "no fans")),
+        Ok(s) => checks.extend(s.fans.iter().map(check_fan)),
+        Err(e) => checks.push(check("fans", Status::Unknown, e.to_string())),
+    }
+    checks
+}
+
+pub fn run_checks(timeout: Duration) -> Vec<Check> {
+    let mut checks = check_metal(timeout);
+    checks.extend(check_recovery());
+    checks.push(check_thermal());
+    checks.extend(check_smc());
+    checks
+}
+
+pub fn overall(checks: &[Check]) -> Status {
+    checks
+        .iter()
+        .map(|c| c.status)
+        .max()
+        .unwrap_or(Status::Unknown)
+}
+
+/// Print the report and return the Nagios exit code.
+pub fn run(timeout_ms: u64, json: bool) -> i32 {
+    let checks = run_checks(Duration::from_millis(timeout_ms));
+    let status = overall(&checks);
+
+    if json {
+        let report = serde_json::json!({
+            "status": status,
+            "exit_code": status.exit_code(),
+            "checks": checks,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).unwrap_or_default()
+        );
+        return status.exit_code();
+    }
+
+    // First line is what Nagios shows, "SERVICE STATUS - summary | perfdata"
+    let bad: Vec<&Check> = checks.iter().filter(|c| c.status != Status::Ok).collect();
+    let summary = match bad.as_slice() {
+        [] => format!("{} checks passed", checks.len()),
+        _ => bad
+            .iter()
+            .map(|c| format!("{}: {}", c.name, c.message))
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    let perf: Vec<&str> = checks
+        .iter()
+        .flat_map(|c| c.perfdata.iter().map(|p| p.as_str()))
+        .collect();
+    if perf.is_empty() {
+        println!("APPLE-SMI {} - {}", status.label(), summary);
+    } else {
+        println!(
+            "APPLE-SMI {} - {} | {}",
+            status.label(),
+            summary,
+            perf.join(" ")
+        );
+    }
+    for c in &checks {
+        println!("[{:<8}] {:<10} {}", c.status.label(), c.name, c.message);
+    }
+    status.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use apple_smi::ioreg::{AccelEntry, VramInfo};
+
+    fn fan(rpm: f32, target_rpm: Option<f32>, mode: Option<u8>) -> smc::FanReading {
+        smc::FanReading {
+            index: 0,
+            rpm,
+            key: String::from("F0Ac"),
+            encoding: String::from("flt "),
+            min_rpm: Some(1000.0),
+            max_rpm: Some(5000.0),
+            target_rpm,
+            mode,
+        }
+    }
+
+    fn accel(recovery_count: Option<u64>) -> AccelEntry {
+        AccelEntry {
+            registry_id: None,
+            class_name: String::from("AGXAcceleratorG16G"),
+            model: None,
+            core_count: None,
+            vram: VramInfo {
+                alloc_vram: None,
+                inuse_vram: None,
+            },
+            device_utilization: None,
+            recovery_count,
+            last_recovery_time: recovery_count.map(|_| 12345),
+        }
+    }
+
+    fn with(status: Status) -> Check {
+        check("x", status, "")
+    }
+
+    #[test]
+    fn fan_thresholds() {
+        let status = |f: smc::FanReading| check_fan(&f).status;
+        // Idle fans may stop unless something asked them to spin
+        assert_eq!(status(fan(0.0, None, Some(0))), Status::Ok);
+        assert_eq!(status(fan(0.0, Some(0.0), None)), Status::Ok);
+        assert_eq!(status(fan(0.0, Some(2000.0), None)), Status::Critical);
+        assert_eq!(status(fan(0.0, None, Some(1)),), Status::Critical);
+        // 10% slack on either side of min / max
+        assert_eq!(status(fan(901.0, None, None)), Status::Ok);
+        assert_eq!(status(fan(899.0, None, None)), Status::Warning);
+        assert_eq!(status(fan(5550.0, None, None)), Status::Ok);
+        assert_eq!(status(fan(5600.0, None, None)), Status::Warning);
+        // Without limits any speed passes
+        let mut f = fan(99999.0, None, None);
+        (f.min_rpm, f.max_rpm) = (None, None);
+        assert_eq!(status(f), Status::Ok);
+
+        let c = check_fan(&fan(2400.4, Some(2400.0), Some(1)));
+        assert_eq!((c.name.as_str(), c.message.as_str()), ("fan0", "2400 RPM"));
+        assert_eq!(c.perfdata, ["fan0_rpm=2400"]);
+        let c = check_fan(&fan(0.0, Some(2000.0), None));
+        assert_eq!(c.message, "stalled: 0 RPM with target 2000 RPM");
+    }
+
+    #[test]
+    fn recovery_thresholds() {
+        let c = recovery_check(0, &accel(Some(0)));
+        assert_eq!(
+            (c.status, c.perfdata.as_slice()),
+            (Status::Ok, &["recovery0_count=0".to_string()][..])
+        );
+        let c = recovery_check(1, &accel(Some(2)));
+        assert_eq!(c.status, Status::Warning);
+        assert_eq!(
+            c.message,
+            "2 GPU recoveries since boot (lastRecoveryTime 12345)"
+        );
+        let c = recovery_check(0, &accel(None));
+        assert_eq!(c.status, Status::Unknown);
+        assert!(c.perfdata.is_empty());
+    }
+
+    #[test]
+    fn thermal_thresholds() {
+        let status = |p| thermal_check(Ok(p)).status;
+        assert_eq!(status(ThermalPressure::Nominal), Status::Ok);
+        assert_eq!(status(ThermalPressure::Moderate), Status::Warning);
+        assert_eq!(status(ThermalPressure::Heavy), Status::Critical);
+        assert_eq!(status(ThermalPressure::Trapping), Status::Critical);
+        assert_eq!(status(ThermalPressure::Sleeping), Status::Critical);
+        assert_eq!(
+            thermal_check(Err(anyhow!("notify_register_check failed"))).status,
+            Status::Unknown
+        );
+    }
+
+    #[test]
+    fn liveness_thresholds() {
+        let timeout = Duration::from_millis(2000);
+        let done = liveness_check(
+            "metal0",
+            "Apple M4",
+            Liveness::Completed(Duration::from_micros(1500)),
+            timeout,
+        );
+        assert_eq!(done.status, Status::Ok);
+        assert_eq!(done.perfdata, ["metal0_latency=1.500ms"]);
+        let failed = liveness_check(
+            "metal0",
+            "Apple M4",
+            Liveness::Failed(String::from("Internal Error")),
+            timeout,
+        );
+        assert_eq!(
+            (failed.status, failed.message.as_str()),
+            (Status::Critical, "Apple M4: Internal Error")
+        );
+        let hung = liveness_check("metal0", "Apple M4", Liveness::TimedOut, timeout);
+        assert_eq!(hung.status, Status::Critical);
+        assert_eq!(
+            hung.message,
+            "Apple M4 didn't complete a command buffer within 2000 ms"
+        );
+    }
+
+    #[test]
+    fn worst_check_decides_the_nagios_code() {
+        let code = |statuses: &[Status]| {
+            let checks: Vec<Check> = statuses.iter().map(|s| with(*s)).collect();
+            overall(&checks).exit_code()
+        };
+        assert_eq!(code(&[Status::Ok, Status::Ok]), 0);
+        assert_eq!(code(&[Status::Ok, Status::Warning]), 1);
+        assert_eq!(code(&[Status::Warning, Status::Critical, Status::Ok]), 2);
+        assert_eq!(code(&[Status::Ok, Status::Unknown]), 3);
+        // Something definitely wrong outranks not knowing
+        assert_eq!(code(&[Status::Unknown, Status::Warning]), 1);
+        assert_eq!(code(&[Status::Unknown, Status::Critical]), 2);
+        assert_eq!(code(&[]), 3);
+        for (s, label) in [
+            (Status::Ok, "OK"),
+            (Status::Warning, "WARNING"),
+            (Status::Critical, "CRITICAL"),
+            (Status::Unknown, "UNKNOWN"),
+        ] {
+            assert_eq!(s.label(), label);
+            assert_eq!(serde_json::to_value(s).unwrap(), label);
+        }
+    }
+}
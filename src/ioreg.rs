@@ -5,6 +5,7 @@
  * ioreg.rs
  * Fetch data by running ioreg output and parse that.
  */
+use crate::utils::{CMD_TIMEOUT, output_timeout};
 use anyhow::Result;
 use std::process::Command;
 
@@ -13,27 +14,146 @@ pub struct VramInfo {
     pub inuse_vram: Option<u64>,
 }
 
-pub fn run_ioreg() -> Result<VramInfo> {
-    let cmd = "ioreg -r -d 1 -w 0 -c IOAccelerator -l | grep memory | head -n 1";
-    let output = Command::new("sh").args(["-c", cmd]).output()?;
+pub struct AdapterInfo {
+    pub external_connected: Option<bool>,
+    // Rated wattage of the connected power adapter
+    pub watts: Option<u32>,
+}
+
+// Find `"label"=123` (nested dict style) and parse the digits.
+fn extract(label: &str, text: &str) -> Option<u64> {
+    let pat = format!("\"{}\"=", label);
+    let start = text.find(&pat)? + pat.len();
+    let digits: String = text[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// One IOAccelerator service, i.e. one GPU as IOKit sees it.
+pub struct AccelEntry {
+    // IORegistry entry ID, the same number as MTLDevice.registryID
+    pub registry_id: Option<u64>,
+    pub class_name: String,
+    pub model: Option<String>,
+    pub core_count: Option<u32>,
+    pub vram: VramInfo,
+    // PerformanceStatistics "Device Utilization %", readable without root
+    pub device_utilization: Option<u64>,
+    // GPU hangs the driver recovered from since boot
+    pub recovery_count: Option<u64>,
+    pub last_recovery_time: Option<u64>,
+}
+
+const MIB: u64 = 1024 * 1024;
+
+// "+-o AGXAcceleratorG16G  <class AGXAcceleratorG16G, id 0x1000005b3, registered, ...>"
+fn parse_header(line: &str) -> (String, Option<u64>) {
+    let class_name = line
+        .split("<class ")
+        .nth(1)
+        .and_then(|r| r.split(',').next())
+        .unwrap_or("")
+        .to_string();
+    let registry_id = line
+        .split(", id 0x")
+        .nth(1)
+        .and_then(|r| r.split(',').next())
+        .and_then(|h| u64::from_str_radix(h, 16).ok());
+    (class_name, registry_id)
+}
+
+// `"model" = "Apple M4"` or `"model" = <"AMD Radeon Pro 5500M">`
+fn extract_str(label: &str, text: &str) -> Option<String> {
+    let pat = format!("\"{}\" = ", label);
+    let start = text.find(&pat)? + pat.len();
+    let rest = text[start..].trim_start_matches('<').strip_prefix('"')?;
+    Some(rest.split('"').next()?.to_string())
+}
+
+fn parse_accel(header: &str, body: &str) -> AccelEntry {
+    let (class_name, registry_id) = parse_header(header);
+    // Apple Silicon shares system memory; discrete GPUs report their own VRAM instead
+    let (alloc_vram, inuse_vram) = match (
+        extract("Alloc system memory", body),
+        extract("In use system memory", body),
+    ) {
+        (None, None) => {
+            let used = extract("vramUsedBytes", body);
+            let free = extract("vramFreeBytes", body);
+            (used.zip(free).map(|(u, f)| u + f), used)
+        }
+        other => other,
+    };
+    let core_count = body
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("\"gpu-core-count\" = "))
+        .and_then(|v| v.trim().parse().ok());
+
+    AccelEntry {
+        registry_id,
+        class_name,
+        model: extract_str("model", body),
+        core_count,
+        vram: VramInfo {
+            // Convert from bytes to MiB
+            alloc_vram: alloc_vram.map(|v| v / MIB),
+            inuse_vram: inuse_vram.map(|v| v / MIB),
+        },
+        device_utilization: extract("Device Utilization %", body),
+        recovery_count: extract("recoveryCount", body),
+        last_recovery_time: extract("lastRecoveryTime", body),
+    }
+}
+
+pub fn run_ioreg() -> Result<Vec<AccelEntry>> {
+    let output = output_timeout(
+        Command::new("ioreg").args(["-r", "-d", "1", "-w", "0", "-c", "IOAccelerator", "-l"]),
+        CMD_TIMEOUT,
+    )?;
     let stdout = String::from_utf8_lossy(&output.stdout);
 
-    fn extract(label: &str, text: &str) -> Option<u64> {
-        let pat = format!("\"{}\"=", label);
-        let start = text.find(&pat)? + pat.len();
-        let digits: String = text[start..]
-            .chars()
-            .take_while(|c| c.is_ascii_digit())
-            .collect();
-        digits.parse().ok()
+    // Each service starts with a "+-o" line followed by its property dict
+    let mut entries = Vec::new();
+    for block in stdout.split("+-o ").skip(1) {
+        let (header, body) = block.split_once('\n').unwrap_or((block, ""));
+        entries.push(parse_accel(header, body));
     }
+    Ok(entries)
+}
+
+/// The Mac's serial number, read live so it never ends up in the system_profiler cache.
+pub fn run_ioreg_serial() -> Result<Option<String>> {
+    let output = output_timeout(
+        Command::new("ioreg").args(["-r", "-d", "1", "-w", "0", "-c", "IOPlatformExpertDevice"]),
+        CMD_TIMEOUT,
+    )?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(extract_str("IOPlatformSerialNumber", &stdout))
+}
+
+pub fn run_ioreg_adapter() -> Result<AdapterInfo> {
+    let output = output_timeout(
+        Command::new("ioreg").args(["-r", "-w", "0", "-c", "AppleSmartBattery"]),
+        CMD_TIMEOUT,
+    )?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
 
-    let alloc_vram = extract("Alloc system memory", &stdout);
-    let inuse_vram = extract("In use system memory", &stdout);
+    // Top level properties look like `"ExternalConnected" = Yes`
+    let external_connected = stdout
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("\"ExternalConnected\" = "))
+        .map(|v| v.trim() == "Yes");
+    // Nested in `"AdapterDetails" = {"Watts"=96,...}`, absent without an adapter
+    let watts = stdout
+        .lines()
+        .find(|l| l.trim_start().starts_with("\"AdapterDetails\""))
+        .and_then(|l| extract("Watts", l))
+        .map(|w| w as u32);
 
-    Ok(VramInfo {
-        // Convert from bytes to MiB
-        alloc_vram: alloc_vram.map(|v| v / (1024 * 1024)),
-        inuse_vram: inuse_vram.map(|v| v / (1024 * 1024)),
+    Ok(AdapterInfo {
+        external_connected,
+        watts,
     })
 }
@@ -288,6 +288,29 @@ impl Drop for EnergyModelSampler {
     }
 }
 
+/// Channel names in an IOReport group, e.g. "GPU Energy" in "Energy Model".
+pub fn channels_in_group(group: &str) -> Result<Vec<String>> {
+    let g = cfstr(group)?;
+    let channels = unsafe { IOReportCopyChannelsInGroup(g, null(), 0, 0, 0) };
+    unsafe { CFRelease(g as CFTypeRef) };
+    if channels.is_null() {
+        return Err(anyhow!("IOReportCopyChannelsInGroup(\"{group}\") failed"));
+    }
+
+    let mut names = Vec::new();
+    if let Some(arr) = dict_get(channels, "IOReportChannels") {
+        let arr = arr as CFArrayRef;
+        for i in 0..unsafe { CFArrayGetCount(arr) } {
+            let item = unsafe { CFArrayGetValueAtIndex(arr, i) } as CFDictionaryRef;
+            if !item.is_null() {
+                names.push(from_cfstr(unsafe { IOReportChannelGetChannelName(item) }));
+            }
+        }
+    }
+    unsafe { CFRelease(channels as CFTypeRef) };
+    Ok(names)
+}
+
 /// One-shot helper (creates subscription each call).
 /// Prefer using `EnergyModelSampler` for repeated sampling.
 pub fn sample_gpu_power_once(window_ms: u64) -> Result<Option<f32>> {
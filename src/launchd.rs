@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * launchd.rs
+ * The LaunchDaemon plist behind `apple-smi service`, plain text so it builds anywhere.
+ */
+use crate::error::Error;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_LABEL: &str = "com.github.zlicdt.apple-smi";
+pub const DEFAULT_LOG_DIR: &str = "/var/log";
+pub const LAUNCH_DAEMONS: &str = "/Library/LaunchDaemons";
+// launchd waits this long before restarting a daemon that exited
+const THROTTLE_S: u32 = 10;
+
+/// What the LaunchDaemon runs and where it logs.
+pub struct Service {
+    pub label: String,
+    pub program: PathBuf,
+    pub socket: Option<PathBuf>,
+    pub interval_ms: u64,
+    pub history: usize,
+    pub log_dir: PathBuf,
+}
+
+/// Labels name files as root, so only reverse-DNS characters get through.
+pub fn check_label(label: &str) -> Result<(), Error> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+    if label.is_empty() || !label.chars().all(allowed) || label.contains("..") {
+        return Err(Error::InvalidArgument(format!(
+            "invalid label {:?}, use reverse-DNS characters [A-Za-z0-9._-] without \"..\"",
+            label
+        )));
+    }
+    Ok(())
+}
+
+pub fn plist_path(label: &str) -> Result<PathBuf, Error> {
+    check_label(label)?;
+    Ok(Path::new(LAUNCH_DAEMONS).join(format!("{}.plist", label)))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl Service {
+    pub fn log_path(&self, suffix: &str) -> PathBuf {
+        self.log_dir.join(format!("{}{}", self.label, suffix))
+    }
+
+    fn arguments(&self) -> Vec<String> {
+        let mut args = vec![
+            self.program.display().to_string(),
+            String::from("daemon"),
+            String::from("--interval"),
+            self.interval_ms.to_string(),
+            String::from("--history"),
+            self.history.to_string(),
+        ];
+        if let Some(socket) = &self.socket {
+            args.push(String::from("--socket"));
+            args.push(socket.display().to_string());
+        }
+        args
+    }
+
+    /// The plist as launchd reads it. Runs as root, powermetrics needs it.
+    pub fn plist(&self) -> String {
+        let string = |key: &str, value: &str| {
+            format!(
+                "\t<key>{}</key>\n\t<string>{}</string>\n",
+                key,
+                escape(value)
+            )
+        };
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ",
+            "\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+            "<plist version=\"1.0\">\n<dict>\n",
+        ));
+        out += &string("Label", &self.label);
+        out += "\t<key>ProgramArguments</key>\n\t<array>\n";
+        for arg in self.arguments() {
+            out += &format!("\t\t<string>{}</string>\n", escape(&arg));
+        }
+        out += "\t</array>\n";
+        out += &string("UserName", "root");
+        out += "\t<key>RunAtLoad</key>\n\t<true/>\n";
+        out += "\t<key>KeepAlive</key>\n\t<true/>\n";
+        out += &format!(
+            "\t<key>ThrottleInterval</key>\n\t<integer>{}</integer>\n",
+            THROTTLE_S
+        );
+        out += &string(
+            "StandardOutPath",
+            &self.log_path(".log").display().to_string(),
+        );
+        out += &string(
+            "StandardErrorPath",
+            &self.log_path(".err.log").display().to_string(),
+        );
+        out += "</dict>\n</plist>\n";
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(socket: Option<&str>) -> Service {
+        Service {
+            label: String::from(DEFAULT_LABEL),
+            program: PathBuf::from("/opt/homebrew/bin/apple-smi"),
+            socket: socket.map(PathBuf::from),
+            interval_ms: 2000,
+            history: 600,
+            log_dir: PathBuf::from(DEFAULT_LOG_DIR),
+        }
+    }
+
+    #[test]
+    fn plist_matches_launchd_layout() {
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>com.github.zlicdt.apple-smi</string>
+	<key>ProgramArguments</key>
+	<array>
+		<string>/opt/homebrew/bin/apple-smi</string>
+		<string>daemon</string>
+		<string>--interval</string>
+		<string>2000</string>
+		<string>--history</string>
+		<string>600</string>
+		<string>--socket</string>
+		<string>/var/run/smi.sock</string>
+	</array>
+	<key>UserName</key>
+	<string>root</string>
+	<key>RunAtLoad</key>
+	<true/>
+	<key>KeepAlive</key>
+	<true/>
+	<key>ThrottleInterval</key>
+	<integer>10</integer>
+	<key>StandardOutPath</key>
+	<string>/var/log/com.github.zlicdt.apple-smi.log</string>
+	<key>StandardErrorPath</key>
+	<string>/var/log/com.github.zlicdt.apple-smi.err.log</string>
+</dict>
+</plist>
+"#;
+        assert_eq!(service(Some("/var/run/smi.sock")).plist(), expected);
+    }
+
+    #[test]
+    fn arguments_skip_default_socket() {
+        assert_eq!(
+            service(None).arguments(),
+            [
+                "/opt/homebrew/bin/apple-smi",
+                "daemon",
+                "--interval",
+                "2000",
+                "--history",
+                "600"
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_and_names_logs_by_label() {
+        assert_eq!(escape("R&D <tools>"), "R&amp;D &lt;tools&gt;");
+        let mut svc = service(None);
+        svc.label = String::from("org.example.smi");
+        svc.program = PathBuf::from("/Applications/R&D <tools>/apple-smi");
+        svc.log_dir = PathBuf::from("/Library/Logs");
+        let plist = svc.plist();
+        assert!(plist.contains("\t<string>org.example.smi</string>\n"));
+        assert!(plist.contains("\t\t<string>/Applications/R&amp;D &lt;tools&gt;/apple-smi</string>\n"));
+        assert!(plist.contains("\t<string>/Library/Logs/org.example.smi.log</string>\n"));
+        assert!(!plist.contains("--socket"));
+    }
+
+    #[test]
+    fn labels_stay_reverse_dns() {
+        assert_eq!(
+            plist_path("org.example.smi").unwrap(),
+            Path::new("/Library/LaunchDaemons/org.example.smi.plist")
+        );
+        assert!(check_label("com.github.zlicdt.apple-smi_2").is_ok());
+        for bad in ["", "..", "a..b", "../../etc/x", "a/b", "a b", "lab\u{e9}l"] {
+            assert!(
+                matches!(check_label(bad), Err(Error::InvalidArgument(_))),
+                "{:?}",
+                bad
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * lib.rs
+ * The library behind the apple-smi CLI.
+ */
+//! GPU, sensor and process monitoring for Macs, the library behind `apple-smi`.
+//!
+//! [`Collector`] gathers a [`Snapshot`]: what GPUs there are, what they are doing,
+//! which processes use them and what the SMC says about temperatures, fans and power.
+//!
+//! ```no_run
+//! let mut collector = apple_smi::Collector::new()?;
+//! let snap = collector.snapshot()?;
+//! for (gpu, m) in snap.gpus.iter().zip(&snap.metrics) {
+//!     println!("{}: {:?}% {:?} W", gpu.name, m.utilization_pct, m.power_w);
+//! }
+//! # Ok::<(), apple_smi::Error>(())
+//! ```
+//!
+//! Only the items re-exported here and the exit codes in [`error`] are covered by
+//! semver. The backend modules are only public with the `cli` feature, which the
+//! `apple-smi` binary needs; library users should leave it off.
+//!
+//! Cargo features, all on by default:
+//! - `cli`: the backend modules, for the binary
+//! - `powermetrics`: GPU frequency, P-state, residency and processes (needs root at runtime)
+//! - `ioreport`: GPU power from IOReport energy counters
+//! - `metal`: Metal device properties and the utilization fallback, pulls in objc2
+// Without the CLI a good part of the backends has no caller
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
+
+mod api;
+mod cache;
+mod smcsim;
+
+pub mod error;
+
+// Public to the CLI, private otherwise
+macro_rules! backend {
+    ($($(#[$attr:meta])* $name:ident;)*) => {$(
+        $(#[$attr])*
+        #[cfg(feature = "cli")]
+        #[doc(hidden)]
+        pub mod $name;
+        $(#[$attr])*
+        #[cfg(not(feature = "cli"))]
+        mod $name;
+    )*};
+}
+
+backend! {
+    collect;
+    devices;
+    ioreg;
+    #[cfg(feature = "ioreport")]
+    ioreport;
+    launchd;
+    mtlapi;
+    pwrmtcs;
+    smc;
+    syspf;
+    thermal;
+    utils;
+}
+
+// Only the CLI sets fan speeds
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod fan;
+
+pub use api::{
+    Collector, Fan, GpuInfo, GpuMetrics, MetricSource, Process, REPLAY_ENV, Sensors, Snapshot,
+    SourceInfo, SystemInfo,
+};
+pub use collect::StatusKind;
+pub use error::{CmdError, Error, KernReturn, ParseError, SmcError};
@@ -6,17 +6,29 @@
  * The 'entry'.
  */
 use anyhow::Result;
+use apple_smi::{error, launchd, syspf};
 use chrono::Local;
 use clap::{Arg, ArgAction, Command};
-mod ioreg;
-mod ioreport;
-mod mtlapi;
-mod pwrmtcs;
+use std::path::PathBuf;
+// Backends live in the library (lib.rs), these are the CLI views on top of them
+mod bench;
+mod daemon;
+mod doctor;
+mod events;
+mod fanctl;
+mod health;
 mod render;
-mod smc;
-mod syspf;
-mod utils;
-fn main() -> Result<()> {
+mod service;
+
+// Errors keep their typed cause so the exit code can tell them apart, see error.rs
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(error::exit_code(&e));
+    }
+}
+
+fn run() -> Result<()> {
     /*
      * For argument, display help & version
      * clap will auto-provide -h/--help and -V/--version
@@ -24,6 +36,18 @@ fn main() -> Result<()> {
     let matches = Command::new("apple-smi")
         .about("Apple Silicon System Management Interface")
         .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("refresh")
+                .long("refresh")
+                .help("Re-run system_profiler instead of using the cached GPU / OS info.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-daemon")
+                .long("no-daemon")
+                .help("Sample in this process even if 'apple-smi daemon' is running.")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("list-gpus")
                 .short('L')
@@ -31,13 +55,389 @@ fn main() -> Result<()> {
                 .help("Display a list of GPUs connected to the system.")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("list-displays")
+                .long("list-displays")
+                .help("Display a list of displays connected to each GPU.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("query")
+                .short('q')
+                .long("query")
+                .help("Display GPU or system info.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("display")
+                .short('d')
+                .long("display")
+                .value_name("TYPE")
+                .requires("query")
+                .help("Display only selected information: MEMORY, METAL, DISPLAY, UTILIZATION, POWER, PERFORMANCE, FAN. Flags can be combined with comma e.g. MEMORY,POWER."),
+        )
+        .arg(
+            Arg::new("query-gpu")
+                .long("query-gpu")
+                .value_name("FIELDS")
+                .requires("format")
+                .conflicts_with("query")
+                .help("Information about GPU, e.g. --query-gpu=index,pstate,clocks_event_reasons.active. Fields: index, name, pstate, utilization.gpu, clocks.gr, clocks_event_reasons.{active,gpu_idle,sw_power_cap,sw_thermal_slowdown,hw_thermal_slowdown,thermal_pressure,low_power_mode,power_source}."),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .requires("query-gpu")
+                .help("Output format for --query-gpu: csv, optionally with noheader and/or nounits, e.g. csv,noheader."),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .requires("query")
+                .help("Produce JSON output (with -q).")
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(
+            Command::new("smc")
+                .about("Browse raw SMC keys.")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Output as JSON.")
+                        .global(true)
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List keys with type, size and attributes.")
+                        .arg(Arg::new("pattern").help("Glob filter, e.g. 'T*' or 'F?Ac'.")),
+                )
+                .subcommand(
+                    Command::new("read")
+                        .about("Read a single key.")
+                        .arg(Arg::new("key").required(true).help("4-char SMC key.")),
+                )
+                .subcommand(
+                    Command::new("dump")
+                        .about("Dump keys with raw bytes and decoded values.")
+                        .arg(Arg::new("pattern").help("Glob filter, e.g. 'T*' or 'F?Ac'.")),
+                ),
+        )
+        .subcommand(
+            Command::new("fan")
+                .about("Control fan speed.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("set")
+                        .about("Pin a fan to a fixed RPM until interrupted, or return it to auto (root).")
+                        .arg(
+                            Arg::new("index")
+                                .required(true)
+                                .value_parser(clap::value_parser!(u8))
+                                .help("Fan index as shown by 'apple-smi -q -d FAN'."),
+                        )
+                        .arg(Arg::new("target").required(true).help("RPM, or 'auto'.")),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Run a Metal FP32 FMA and memory copy benchmark while sampling clocks, power and temperature.")
+                .arg(
+                    Arg::new("duration")
+                        .short('t')
+                        .long("duration")
+                        .value_name("SECS")
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(u64).range(2..))
+                        .help("Total run time, split between the two kernels."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Output as JSON.")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("health")
+                .about("Check GPU liveness, recoveries, thermals, fans and SMC. Exits with Nagios codes (0 OK, 1 WARNING, 2 CRITICAL, 3 UNKNOWN).")
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("MS")
+                        .default_value("2000")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("How long a GPU may take to complete an empty command buffer."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Output as JSON.")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("events")
+                .about("Stream GPU recovery events (like nvidia-smi -e) until interrupted.")
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("MS")
+                        .default_value("1000")
+                        .value_parser(clap::value_parser!(u64).range(100..))
+                        .help("Polling interval."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Output one JSON object per event.")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Keep sampling in the background and serve snapshots, history and events over a Unix socket.")
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Socket to listen on [default: $APPLE_SMI_SOCKET or /var/run/apple-smi.sock]."),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("MS")
+                        .default_value("1000")
+                        .value_parser(clap::value_parser!(u64).range(200..))
+                        .help("Sampling interval."),
+                )
+                .arg(
+                    Arg::new("history")
+                        .long("history")
+                        .value_name("N")
+                        .default_value("600")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("How many snapshots to keep for 'history' requests."),
+                ),
+        )
+        .subcommand(
+            Command::new("service")
+                .about("Install, remove or check 'apple-smi daemon' as a launchd LaunchDaemon.")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("label")
+                        .long("label")
+                        .value_name("LABEL")
+                        .default_value(launchd::DEFAULT_LABEL)
+                        .global(true)
+                        .help("launchd label, also names the plist and log files. [A-Za-z0-9._-] only."),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .global(true)
+                        .help("Print what would be installed or removed, change nothing.")
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(
+                    Command::new("install")
+                        .about("Write the plist to /Library/LaunchDaemons and load it (root).")
+                        .arg(
+                            Arg::new("program")
+                                .long("program")
+                                .value_name("PATH")
+                                .help("apple-smi binary to run [default: this one]."),
+                        )
+                        .arg(
+                            Arg::new("socket")
+                                .long("socket")
+                                .value_name("PATH")
+                                .help("Socket for the daemon to listen on [default: the daemon's]."),
+                        )
+                        .arg(
+                            Arg::new("interval")
+                                .short('i')
+                                .long("interval")
+                                .value_name("MS")
+                                .default_value("1000")
+                                .value_parser(clap::value_parser!(u64).range(200..))
+                                .help("Sampling interval."),
+                        )
+                        .arg(
+                            Arg::new("history")
+                                .long("history")
+                                .value_name("N")
+                                .default_value("600")
+                                .value_parser(clap::value_parser!(usize))
+                                .help("How many snapshots the daemon keeps."),
+                        )
+                        .arg(
+                            Arg::new("log-dir")
+                                .long("log-dir")
+                                .value_name("DIR")
+                                .default_value(launchd::DEFAULT_LOG_DIR)
+                                .help("Where stdout and stderr go, as <label>.log and <label>.err.log."),
+                        ),
+                )
+                .subcommand(
+                    Command::new("uninstall").about("Unload the daemon and remove its plist (root)."),
+                )
+                .subcommand(
+                    Command::new("status")
+                        .about("Show whether the plist is installed, launchd runs it and its socket answers.")
+                        .arg(
+                            Arg::new("socket")
+                                .long("socket")
+                                .value_name("PATH")
+                                .help("Socket to check [default: $APPLE_SMI_SOCKET or /var/run/apple-smi.sock]."),
+                        )
+                        .arg(
+                            Arg::new("json")
+                                .long("json")
+                                .help("Output as JSON.")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check which data sources work here, what needs root and how to fix the rest.")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Output as JSON.")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .get_matches();
 
+    if matches.get_flag("refresh") {
+        syspf::force_refresh();
+    }
+
+    if matches.get_flag("no-daemon") {
+        daemon::disable();
+    }
+
+    if let Some(("daemon", sub)) = matches.subcommand() {
+        let socket = sub
+            .get_one::<String>("socket")
+            .map(PathBuf::from)
+            .unwrap_or_else(daemon::socket_path);
+        return daemon::run(
+            &socket,
+            *sub.get_one::<u64>("interval").unwrap(),
+            (*sub.get_one::<usize>("history").unwrap()).max(1),
+        );
+    }
+
+    if let Some(("service", sub)) = matches.subcommand() {
+        let label = sub.get_one::<String>("label").unwrap();
+        let dry_run = sub.get_flag("dry-run");
+        return match sub.subcommand() {
+            Some(("install", m)) => {
+                let program = match m.get_one::<String>("program") {
+                    Some(p) => PathBuf::from(p),
+                    None => std::env::current_exe()?,
+                };
+                let svc = launchd::Service {
+                    label: label.clone(),
+                    program,
+                    socket: m.get_one::<String>("socket").map(PathBuf::from),
+                    interval_ms: *m.get_one::<u64>("interval").unwrap(),
+                    history: (*m.get_one::<usize>("history").unwrap()).max(1),
+                    log_dir: PathBuf::from(m.get_one::<String>("log-dir").unwrap()),
+                };
+                service::install(&svc, dry_run)
+            }
+            Some(("uninstall", _)) => service::uninstall(label, dry_run),
+            Some(("status", m)) => {
+                let socket = m
+                    .get_one::<String>("socket")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(daemon::socket_path);
+                service::status(label, &socket, m.get_flag("json"))
+            }
+            _ => unreachable!("subcommand_required"),
+        };
+    }
+
+    if let Some(("fan", sub)) = matches.subcommand() {
+        return match sub.subcommand() {
+            Some(("set", m)) => {
+                let index = *m.get_one::<u8>("index").unwrap();
+                let target = fanctl::FanTarget::parse(m.get_one::<String>("target").unwrap())?;
+                fanctl::run_set(index, target)
+            }
+            _ => unreachable!("subcommand_required"),
+        };
+    }
+
+    if let Some(("doctor", sub)) = matches.subcommand() {
+        return doctor::run(sub.get_flag("json"));
+    }
+
+    if let Some(("health", sub)) = matches.subcommand() {
+        let code = health::run(
+            *sub.get_one::<u64>("timeout").unwrap(),
+            sub.get_flag("json"),
+        );
+        std::process::exit(code);
+    }
+
+    if let Some(("events", sub)) = matches.subcommand() {
+        return events::run(
+            *sub.get_one::<u64>("interval").unwrap(),
+            sub.get_flag("json"),
+        );
+    }
+
+    if let Some(("bench", sub)) = matches.subcommand() {
+        return bench::run(
+            *sub.get_one::<u64>("duration").unwrap(),
+            sub.get_flag("json"),
+        );
+    }
+
+    if let Some(("smc", sub)) = matches.subcommand() {
+        let json = sub.get_flag("json");
+        let view = match sub.subcommand() {
+            Some(("list", m)) => {
+                render::SmcView::List(m.get_one::<String>("pattern").map(|s| s.as_str()))
+            }
+            Some(("read", m)) => render::SmcView::Read(m.get_one::<String>("key").unwrap()),
+            Some(("dump", m)) => {
+                render::SmcView::Dump(m.get_one::<String>("pattern").map(|s| s.as_str()))
+            }
+            _ => unreachable!("subcommand_required"),
+        };
+        return render::smc_keys(view, json);
+    }
+
     if matches.get_flag("list-gpus") {
         render::list_gpus()?;
         return Ok(());
     }
 
+    if matches.get_flag("list-displays") {
+        return render::list_displays();
+    }
+
+    if let Some(fields) = matches.get_one::<String>("query-gpu") {
+        return render::query_gpu(fields, matches.get_one::<String>("format").unwrap());
+    }
+
+    if matches.get_flag("query") {
+        return render::query(
+            matches.get_one::<String>("display").map(|s| s.as_str()),
+            matches.get_flag("json"),
+        );
+    }
+
     // Local time
     println!("{}", Local::now().format("%a %b %e %T %Y"));
     render::render()
@@ -3,38 +3,464 @@
  * apple-smi: Apple Silicon System Management Interface
  * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
  * mtlapi.rs
- * Check is the gpu initialized via Metal API.
+ * Probe GPU liveness via Metal API, read device properties
+ * and sample GPU utilization through the counter-set API.
  */
+#[cfg(feature = "metal")]
 #[link(name = "CoreGraphics", kind = "framework")]
 unsafe extern "C" {}
 
+#[cfg(feature = "metal")]
+use objc2::rc::Retained;
+#[cfg(feature = "metal")]
+use objc2::runtime::{NSObjectProtocol, ProtocolObject};
+#[cfg(feature = "metal")]
+use objc2::sel;
+#[cfg(feature = "metal")]
+use objc2_foundation::NSRange;
+#[cfg(feature = "metal")]
 use objc2_metal::{
-    MTLCommandBuffer, MTLCommandBufferStatus, MTLCommandQueue, MTLCreateSystemDefaultDevice,
-    MTLDevice,
+    MTLBlitPassDescriptor, MTLCommandBuffer, MTLCommandBufferStatus, MTLCommandEncoder,
+    MTLCommandQueue, MTLCopyAllDevices, MTLCounterSampleBuffer, MTLCounterSampleBufferDescriptor,
+    MTLCounterSamplingPoint, MTLCounterSet, MTLCreateSystemDefaultDevice, MTLDevice, MTLGPUFamily,
+    MTLStorageMode, MTLTimestamp,
 };
+#[cfg(feature = "metal")]
+use std::ptr::NonNull;
+#[cfg(feature = "metal")]
+use std::thread;
+#[cfg(feature = "metal")]
+use std::time::{Duration, Instant};
 
-pub fn gpu_initialized_probe() -> bool {
-    // Get default Metal device
-    let device = match MTLCreateSystemDefaultDevice() {
-        Some(d) => d,
-        None => return false,
+#[derive(Debug, Clone)]
+pub struct MetalDeviceInfo {
+    pub name: String,
+    pub registry_id: u64,
+    // e.g. "applegpu_g16g", needs macOS 14+
+    pub architecture: Option<String>,
+    pub has_unified_memory: bool,
+    pub is_low_power: bool, // integrated GPU on dual-GPU Intel Macs
+    pub is_removable: bool, // eGPU
+    pub is_headless: bool,
+    // Bytes
+    pub recommended_max_working_set_size: u64,
+    pub current_allocated_size: u64,
+    pub max_buffer_length: u64,
+    pub max_threadgroup_memory_length: u64,
+    // e.g. ["Apple9", "Mac2", "Common3", "Metal3"]
+    pub gpu_families: Vec<String>,
+    // e.g. ["timestamp"], discrete GPUs may also have "stageutilization"
+    pub counter_sets: Vec<String>,
+}
+
+#[cfg(feature = "metal")]
+const FAMILIES: [(MTLGPUFamily, &str); 16] = [
+    (MTLGPUFamily::Apple1, "Apple1"),
+    (MTLGPUFamily::Apple2, "Apple2"),
+    (MTLGPUFamily::Apple3, "Apple3"),
+    (MTLGPUFamily::Apple4, "Apple4"),
+    (MTLGPUFamily::Apple5, "Apple5"),
+    (MTLGPUFamily::Apple6, "Apple6"),
+    (MTLGPUFamily::Apple7, "Apple7"),
+    (MTLGPUFamily::Apple8, "Apple8"),
+    (MTLGPUFamily::Apple9, "Apple9"),
+    (MTLGPUFamily::Apple10, "Apple10"),
+    (MTLGPUFamily::Mac2, "Mac2"),
+    (MTLGPUFamily::Common1, "Common1"),
+    (MTLGPUFamily::Common2, "Common2"),
+    (MTLGPUFamily::Common3, "Common3"),
+    (MTLGPUFamily::Metal3, "Metal3"),
+    (MTLGPUFamily::Metal4, "Metal4"),
+];
+
+#[cfg(feature = "metal")]
+pub type Device = Retained<ProtocolObject<dyn MTLDevice>>;
+
+/// Every Metal device (built-in, discrete, eGPU), not just the system default one.
+#[cfg(feature = "metal")]
+pub fn all_devices() -> Vec<Device> {
+    // No default device means no Metal at all (e.g. a VM), don't bother copying the list
+    if MTLCreateSystemDefaultDevice().is_none() {
+        return Vec::new();
+    }
+    MTLCopyAllDevices().iter().collect()
+}
+
+#[cfg(feature = "metal")]
+pub fn device_info_of(device: &ProtocolObject<dyn MTLDevice>) -> MetalDeviceInfo {
+    // architecture is macOS 14+, older systems would throw on the selector
+    let architecture = if device.respondsToSelector(sel!(architecture)) {
+        Some(device.architecture().name().to_string())
+    } else {
+        None
     };
+    let gpu_families = FAMILIES
+        .iter()
+        .filter(|(f, _)| device.supportsFamily(*f))
+        .map(|(_, n)| n.to_string())
+        .collect();
+
+    MetalDeviceInfo {
+        name: device.name().to_string(),
+        registry_id: device.registryID(),
+        architecture,
+        has_unified_memory: device.hasUnifiedMemory(),
+        is_low_power: device.isLowPower(),
+        is_removable: device.isRemovable(),
+        is_headless: device.isHeadless(),
+        recommended_max_working_set_size: device.recommendedMaxWorkingSetSize(),
+        current_allocated_size: device.currentAllocatedSize() as u64,
+        max_buffer_length: device.maxBufferLength() as u64,
+        max_threadgroup_memory_length: device.maxThreadgroupMemoryLength() as u64,
+        gpu_families,
+        counter_sets: counter_set_names(device),
+    }
+}
+
+/// Info for every device in `all_devices` order.
+#[cfg(feature = "metal")]
+pub fn device_infos() -> Vec<MetalDeviceInfo> {
+    all_devices().iter().map(|d| device_info_of(d)).collect()
+}
 
+/// Outcome of pushing an empty command buffer through a device.
+#[cfg(feature = "metal")]
+#[derive(Debug, Clone)]
+pub enum Liveness {
+    Completed(Duration),
+    Failed(String),
+    TimedOut,
+}
+
+/// Commit an empty command buffer and poll it instead of blocking forever on a hung GPU.
+#[cfg(feature = "metal")]
+pub fn probe_liveness(device: &ProtocolObject<dyn MTLDevice>, timeout: Duration) -> Liveness {
     let queue = match device.newCommandQueue() {
         Some(q) => q,
-        None => return false,
+        None => return Liveness::Failed(String::from("can't create command queue")),
     };
 
     // Command buffer
     let cb = match queue.commandBuffer() {
         Some(cb) => cb,
-        None => return false,
+        None => return Liveness::Failed(String::from("can't create command buffer")),
     };
 
+    let start = Instant::now();
     cb.commit();
-    cb.waitUntilCompleted();
+    loop {
+        match cb.status() {
+            MTLCommandBufferStatus::Completed => return Liveness::Completed(start.elapsed()),
+            MTLCommandBufferStatus::Error => {
+                let msg = cb
+                    .error()
+                    .map(|e| e.localizedDescription().to_string())
+                    .unwrap_or_else(|| String::from("command buffer error"));
+                return Liveness::Failed(msg);
+            }
+            _ if start.elapsed() >= timeout => return Liveness::TimedOut,
+            _ => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+}
+
+#[cfg(feature = "metal")]
+fn counter_set_names(device: &ProtocolObject<dyn MTLDevice>) -> Vec<String> {
+    device
+        .counterSets()
+        .map(|sets| sets.iter().map(|c| c.name().to_string()).collect())
+        .unwrap_or_default()
+}
+
+// MTLCounterErrorValue, written for samples the GPU couldn't take
+const COUNTER_ERROR: u64 = u64::MAX;
+
+// MTLCommonCounterSetStageUtilization and MTLCommonCounterSetTimestamp
+pub const STAGE_UTILIZATION: &str = "stageutilization";
+pub const TIMESTAMP: &str = "timestamp";
+
+/// GPU busy figure from Metal counters, measured or estimated depending on `counter_set`.
+#[derive(Debug, Clone)]
+pub struct MetalUtilization {
+    pub registry_id: u64,
+    // percentage points, same scale as powermetrics residency
+    pub busy_pct: f64,
+    // STAGE_UTILIZATION (cycle counts) or TIMESTAMP (queueing delay estimate)
+    pub counter_set: &'static str,
+    // GPU/CPU timestamp correlation taken by sampleTimestamps
+    pub cpu_timestamp: u64,
+    pub gpu_timestamp: u64,
+    // CPU nanoseconds per GPU timestamp tick
+    pub ns_per_tick: f64,
+    // Probes that returned valid samples
+    pub probes: usize,
+}
+
+impl MetalUtilization {
+    /// `utilization_source` value: "metal" when measured, "metal-estimate" from timestamps.
+    pub fn source(&self) -> &'static str {
+        match self.counter_set {
+            STAGE_UTILIZATION => "metal",
+            _ => "metal-estimate",
+        }
+    }
+}
+
+#[cfg(feature = "metal")]
+fn sample_timestamps(device: &ProtocolObject<dyn MTLDevice>) -> (MTLTimestamp, MTLTimestamp) {
+    let (mut cpu, mut gpu): (MTLTimestamp, MTLTimestamp) = (0, 0);
+    unsafe {
+        device.sampleTimestamps_gpuTimestamp(NonNull::from(&mut cpu), NonNull::from(&mut gpu))
+    };
+    (cpu, gpu)
+}
+
+// What a probe run left behind: one counter row per probe, in submission order
+#[cfg(feature = "metal")]
+struct ProbeRun {
+    rows: Vec<Vec<u64>>,
+    // GPU time at each submission
+    submitted: Vec<u64>,
+    first: (MTLTimestamp, MTLTimestamp),
+    ns_per_tick: f64,
+    interval_ns: f64,
+}
+
+/*
+ * Submit an empty blit pass every `window / probes` and sample `set` at the start
+ * of each encoder. Both ends of the window also correlate the CPU and GPU clocks.
+ */
+#[cfg(feature = "metal")]
+fn run_probes(
+    device: &ProtocolObject<dyn MTLDevice>,
+    set: &ProtocolObject<dyn MTLCounterSet>,
+    window_ms: u64,
+    probes: usize,
+) -> Option<ProbeRun> {
+    let desc = MTLCounterSampleBufferDescriptor::new();
+    desc.setCounterSet(Some(set));
+    desc.setStorageMode(MTLStorageMode::Shared);
+    unsafe { desc.setSampleCount(probes) };
+    let samples = device
+        .newCounterSampleBufferWithDescriptor_error(&desc)
+        .ok()?;
+    let queue = device.newCommandQueue()?;
+
+    let interval = Duration::from_millis(window_ms) / probes as u32;
+    let first = sample_timestamps(device);
+    let mut submitted = Vec::with_capacity(probes);
+    let mut buffers = Vec::with_capacity(probes);
+    for i in 0..probes {
+        let pass = MTLBlitPassDescriptor::new();
+        let attachment = unsafe { pass.sampleBufferAttachments().objectAtIndexedSubscript(0) };
+        attachment.setSampleBuffer(Some(&samples));
+        unsafe {
+            attachment.setStartOfEncoderSampleIndex(i);
+            // MTLCounterDontSample
+            attachment.setEndOfEncoderSampleIndex(usize::MAX);
+        }
+        let cb = queue.commandBuffer()?;
+        cb.blitCommandEncoderWithDescriptor(&pass)?.endEncoding();
+        submitted.push(sample_timestamps(device).1);
+        cb.commit();
+        buffers.push(cb);
+        thread::sleep(interval);
+    }
+    for cb in &buffers {
+        cb.waitUntilCompleted();
+    }
+    let last = sample_timestamps(device);
+
+    // Correlate both clocks across the window to convert GPU ticks to nanoseconds
+    let ns_per_tick = match last.1.checked_sub(first.1) {
+        Some(d) if d > 0 => (last.0 - first.0) as f64 / d as f64,
+        _ => return None,
+    };
+    let data = unsafe { samples.resolveCounterRange(NSRange::new(0, probes)) }?.to_vec();
+    let words: Vec<u64> = data
+        .chunks_exact(8)
+        .map(|b| u64::from_ne_bytes(b.try_into().unwrap_or_default()))
+        .collect();
+    let width = words.len() / probes;
+    if width == 0 {
+        return None;
+    }
+    Some(ProbeRun {
+        rows: words.chunks_exact(width).map(<[u64]>::to_vec).collect(),
+        submitted,
+        first,
+        ns_per_tick,
+        interval_ns: interval.as_nanos() as f64,
+    })
+}
+
+/*
+ * MTLCounterResultStageUtilization rows: total, vertex, tessellation, post-tessellation
+ * vertex, fragment and render target cycles. Busy share is the stage cycles over the
+ * total cycles between the first and last valid row. Stages overlap, so it is capped.
+ */
+#[cfg_attr(not(feature = "metal"), allow(dead_code))]
+fn stage_busy(rows: &[Vec<u64>]) -> Option<(f64, usize)> {
+    let valid: Vec<&[u64]> = rows
+        .iter()
+        .map(Vec::as_slice)
+        .filter(|r| r.len() >= 6 && !r.contains(&COUNTER_ERROR))
+        .collect();
+    let (first, last) = (valid.first()?, valid.last()?);
+    let total = last[0].checked_sub(first[0]).filter(|t| *t > 0)?;
+    let busy: u64 = (1..6).map(|i| last[i].saturating_sub(first[i])).sum();
+    Some(((busy as f64 / total as f64).min(1.0) * 100.0, valid.len()))
+}
+
+/*
+ * No public API tells how busy the GPU is, but the timestamp counter set tells when
+ * our own work actually started. Compare each probe's start of encoder with the GPU
+ * time at submission. An idle GPU picks it up within microseconds, a busy one only
+ * after the running work yields, so the mean queueing delay approximates busy time.
+ */
+#[cfg_attr(not(feature = "metal"), allow(dead_code))]
+fn queue_busy(
+    starts: &[u64],
+    submitted: &[u64],
+    ns_per_tick: f64,
+    interval_ns: f64,
+) -> Option<(f64, usize)> {
+    let delays: Vec<f64> = starts
+        .iter()
+        .zip(submitted)
+        .filter(|(start, _)| **start != COUNTER_ERROR && **start != 0)
+        .map(|(start, sub)| {
+            let delay = start.saturating_sub(*sub) as f64 * ns_per_tick;
+            delay.min(interval_ns) / interval_ns
+        })
+        .collect();
+    if delays.is_empty() {
+        return None;
+    }
+    Some((delays.iter().sum::<f64>() / delays.len() as f64 * 100.0, delays.len()))
+}
+
+/// Measure from the stage-utilization counters when the device has them, otherwise
+/// estimate from timestamp queueing delay.
+#[cfg(feature = "metal")]
+pub fn sample_utilization(
+    device: &ProtocolObject<dyn MTLDevice>,
+    window_ms: u64,
+    probes: usize,
+) -> Option<MetalUtilization> {
+    if probes == 0 || !device.supportsCounterSampling(MTLCounterSamplingPoint::AtStageBoundary) {
+        return None;
+    }
+    let sets = device.counterSets()?;
+    let find = |name: &str| sets.iter().find(|c| c.name().to_string() == name);
+
+    // One window per device either way, so a failed stage run doesn't retry with timestamps
+    let ((busy_pct, valid), counter_set, run) = match find(STAGE_UTILIZATION) {
+        // Stage cycles need two rows to take a difference
+        Some(set) if probes >= 2 => {
+            let run = run_probes(device, &set, window_ms, probes)?;
+            (stage_busy(&run.rows)?, STAGE_UTILIZATION, run)
+        }
+        _ => {
+            let set = find(TIMESTAMP)?;
+            let run = run_probes(device, &set, window_ms, probes)?;
+            let starts: Vec<u64> = run.rows.iter().map(|r| r[0]).collect();
+            let busy = queue_busy(&starts, &run.submitted, run.ns_per_tick, run.interval_ns)?;
+            (busy, TIMESTAMP, run)
+        }
+    };
+
+    Some(MetalUtilization {
+        registry_id: device.registryID(),
+        busy_pct,
+        counter_set,
+        cpu_timestamp: run.first.0,
+        gpu_timestamp: run.first.1,
+        ns_per_tick: run.ns_per_tick,
+        probes: valid,
+    })
+}
+
+/// `sample_utilization` for every device except those in `skip` (IORegistry IDs),
+/// leaving out devices whose window would end past `deadline`.
+#[cfg(feature = "metal")]
+pub fn sample_utilizations(
+    skip: &[u64],
+    window_ms: u64,
+    probes: usize,
+    deadline: Instant,
+) -> Vec<MetalUtilization> {
+    let window = Duration::from_millis(window_ms);
+    all_devices()
+        .into_iter()
+        .filter(|d| !skip.contains(&d.registryID()))
+        .take_while(|_| Instant::now() + window < deadline)
+        .filter_map(|d| sample_utilization(&d, window_ms, probes))
+        .collect()
+}
+
+// Built without Metal: no devices, so nothing to sample
+#[cfg(not(feature = "metal"))]
+pub fn device_infos() -> Vec<MetalDeviceInfo> {
+    Vec::new()
+}
+
+#[cfg(not(feature = "metal"))]
+pub fn sample_utilizations(
+    _skip: &[u64],
+    _window_ms: u64,
+    _probes: usize,
+    _deadline: std::time::Instant,
+) -> Vec<MetalUtilization> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_cycles_over_total_cycles() {
+        // total, vertex, tessellation, post-tessellation, fragment, render target
+        let rows = vec![
+            vec![1000, 10, 0, 0, 20, 5],
+            vec![COUNTER_ERROR; 6],
+            vec![3000, 210, 0, 0, 520, 105],
+        ];
+        let (pct, valid) = stage_busy(&rows).unwrap();
+        assert_eq!(valid, 2);
+        assert!((pct - 40.0).abs() < 1e-9);
+        // Overlapping stages can't exceed 100%
+        let rows = vec![vec![0; 6], vec![100, 90, 0, 0, 90, 90]];
+        assert_eq!(stage_busy(&rows).unwrap().0, 100.0);
+        // One valid row has nothing to difference
+        assert!(stage_busy(&rows[..1]).is_none());
+    }
+
+    #[test]
+    fn queueing_delay_estimate() {
+        // 1 ns ticks, 100 ns apart: started right away, half way, and past the interval
+        let submitted = [0, 100, 200, 300];
+        let starts = [1, 150, 900, 0];
+        let (pct, valid) = queue_busy(&starts, &submitted, 1.0, 100.0).unwrap();
+        assert_eq!(valid, 3);
+        assert!((pct - (0.01 + 0.5 + 1.0) / 3.0 * 100.0).abs() < 1e-9);
+        assert!(queue_busy(&[0, COUNTER_ERROR], &[0, 1], 1.0, 100.0).is_none());
+    }
 
-    // Return status
-    let ok = matches!(cb.status(), MTLCommandBufferStatus::Completed) && cb.error().is_none();
-    ok
+    #[test]
+    fn only_stage_counters_count_as_measured() {
+        let mut u = MetalUtilization {
+            registry_id: 1,
+            busy_pct: 0.0,
+            counter_set: STAGE_UTILIZATION,
+            cpu_timestamp: 0,
+            gpu_timestamp: 0,
+            ns_per_tick: 1.0,
+            probes: 2,
+        };
+        assert_eq!(u.source(), "metal");
+        u.counter_set = TIMESTAMP;
+        assert_eq!(u.source(), "metal-estimate");
+    }
 }
@@ -6,9 +6,12 @@
  * Fetch data by running powermetrics output and parse that.
  */
 
+#[cfg(feature = "powermetrics")]
+use crate::utils::{CMD_TIMEOUT, output_timeout};
 use anyhow::Result;
+#[cfg(feature = "powermetrics")]
 use std::process::Command;
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct GpuMetrics {
     // MHz
     pub gpu_hw_freq: Option<u32>,
@@ -31,16 +34,50 @@ pub struct ProcGpu {
     // pub gpu_ms_per_s: f64,
 }
 
+// One 200 ms sample of the given samplers
+#[cfg(feature = "powermetrics")]
+fn powermetrics(samplers: &str, procs: bool) -> Result<String> {
+    // Run it directly, a timeout kill on `sh -c` would leave powermetrics behind
+    let mut cmd = Command::new("powermetrics");
+    cmd.args(["--samplers", samplers, "-i", "200", "-n", "1"]);
+    if procs {
+        cmd.arg("--show-process-gpu");
+    }
+    let output = output_timeout(&mut cmd, CMD_TIMEOUT)?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(feature = "powermetrics")]
 pub fn run_pwrmtcs() -> Result<GpuMetrics> {
-    let cmd = "powermetrics -s gpu_power -i 200 -n 1 | grep -E '^GPU (HW active frequency|HW active residency|SW state|Power):'";
-    let output = Command::new("sh").args(["-c", cmd]).output()?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_gpu(&powermetrics("gpu_power", false)?)
+}
 
+#[cfg(feature = "powermetrics")]
+pub fn run_pwrmtcs_procs() -> Result<Vec<ProcGpu>> {
+    Ok(parse_procs(&powermetrics("tasks", true)?))
+}
+
+/// GPU metrics and/or per-process GPU users from a single powermetrics run.
+#[cfg(feature = "powermetrics")]
+pub fn run_pwrmtcs_sample(gpu: bool, procs: bool) -> Result<(Option<GpuMetrics>, Option<Vec<ProcGpu>>)> {
+    let samplers = match (gpu, procs) {
+        (true, true) => "gpu_power,tasks",
+        (false, true) => "tasks",
+        _ => "gpu_power",
+    };
+    let stdout = powermetrics(samplers, procs)?;
+    let metrics = gpu.then(|| parse_gpu(&stdout)).transpose()?;
+    Ok((metrics, procs.then(|| parse_procs(&stdout))))
+}
+
+#[cfg_attr(not(feature = "powermetrics"), allow(dead_code))]
+fn parse_gpu(stdout: &str) -> Result<GpuMetrics> {
     let mut gpu_hw_freq: Option<u32> = None;
     let mut gpu_hw_residency: Option<f64> = None;
     let mut max_sw_state: Option<(usize, f64)> = None; // track (idx, value)
     // let mut gpu_pwr: Option<u32> = None;
 
+    // Only the "GPU ...:" lines matter, everything else falls through
     for line in stdout.lines() {
         if let Some(rest) = line.strip_prefix("GPU HW active frequency:") {
             if let Some(freq_str) = rest.trim().split_whitespace().next() {
@@ -65,10 +102,10 @@ pub fn run_pwrmtcs() -> Result<GpuMetrics> {
                     }
                 }
             }
-        }/* else if let Some(rest) = line.strip_prefix("GPU Power:") {
-            if let Some(pwr_str) = rest.trim().split_whitespace().next() {
-                gpu_pwr = Some(pwr_str.parse()?);
-            }
+        } /* else if let Some(rest) = line.strip_prefix("GPU Power:") {
+        if let Some(pwr_str) = rest.trim().split_whitespace().next() {
+        gpu_pwr = Some(pwr_str.parse()?);
+        }
         } */
     }
 
@@ -80,16 +117,23 @@ pub fn run_pwrmtcs() -> Result<GpuMetrics> {
     })
 }
 
-pub fn run_pwrmtcs_procs() -> Result<Vec<ProcGpu>> {
-    let cmd = "powermetrics --samplers tasks --show-process-gpu -n 1 -i 200";
-    let output = Command::new("sh").args(["-c", cmd]).output()?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
+// Rows of the "*** Running tasks ***" section, the whole text if there is none
+#[cfg_attr(not(feature = "powermetrics"), allow(dead_code))]
+fn parse_procs(stdout: &str) -> Vec<ProcGpu> {
+    let tasks = match stdout.find("*** Running tasks ***") {
+        Some(at) => {
+            let rest = &stdout[at..];
+            let body = rest.find('\n').map_or("", |nl| &rest[nl..]);
+            // The next "*** ... ***" header ends it
+            body.find("\n***").map_or(body, |end| &body[..end])
+        }
+        None => stdout,
+    };
     let mut procs = Vec::new();
 
     const NUMERIC_COLS: usize = 8; // ID, CPU ms/s, User%, deadline1, deadline2, wakeup1, wakeup2, GPU ms/s
 
-    for line in stdout.lines() {
+    for line in tasks.lines() {
         let tokens: Vec<&str> = line.split_whitespace().collect();
         // Expect: name (one or more tokens) + 8 numeric columns.
         if tokens.len() < NUMERIC_COLS + 1 {
@@ -116,5 +160,52 @@ pub fn run_pwrmtcs_procs() -> Result<Vec<ProcGpu>> {
         });
     }
 
-    Ok(procs)
+    procs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `--samplers gpu_power,tasks --show-process-gpu`, trimmed
+    const BOTH: &str = "\
+Machine model: Mac16,10
+
+*** Sampled system activity (Mon Oct 19 10:00:00 2026 +0800) (204.12ms elapsed) ***
+
+*** Running tasks ***
+
+Name                          ID     CPU ms/s  User%  Deadlines (<2 ms, 2-5 ms)  Wakeups (Intr, Pkg idle)  GPU ms/s
+WindowServer                  412    35.21     42.10  10.00   0.00   120.00  0.00   12.34
+Google Chrome Helper (GPU)    1234   20.10     80.00  0.00    0.00   50.00   0.00   5.67
+ALL_TASKS                     -2     100.00    60.00  10.00   0.00   500.00  0.00   18.01
+
+**** GPU usage ****
+
+GPU HW active frequency: 1398 MHz
+GPU HW active residency:  12.34% (338 MHz: 0% 618 MHz: 1.2% 796 MHz: 0% 924 MHz: 3.1% 1380 MHz: 8%)
+GPU SW requested state: (P1 :   0% P2 :  12%)
+GPU SW state: (SW_P1 :   2% SW_P2 :   0% SW_P3 :  10% SW_P4 :   0%)
+GPU idle residency:  87.66%
+GPU Power: 120 mW
+";
+
+    #[test]
+    fn one_run_feeds_both_parsers() {
+        let m = parse_gpu(BOTH).unwrap();
+        assert_eq!(m.gpu_hw_freq, Some(1398));
+        assert_eq!(m.gpu_hw_residency, Some(12.34));
+        assert_eq!(m.gpu_sw_state, Some(3));
+        let procs: Vec<_> = parse_procs(BOTH)
+            .into_iter()
+            .map(|p| (p.name, p.pid))
+            .collect();
+        assert_eq!(
+            procs,
+            [
+                (String::from("WindowServer"), 412),
+                (String::from("Google Chrome Helper (GPU)"), 1234)
+            ]
+        );
+    }
 }
@@ -5,30 +5,62 @@
  * render.rs
  * Render the output.
  */
-use crate::ioreg;
-use crate::pwrmtcs;
-use crate::smc;
-use crate::syspf;
-use crate::utils;
+use crate::daemon;
+use apple_smi::collect::{self, Provenance, SourceStatus};
+use apple_smi::devices;
+use apple_smi::error::{CmdError, Error};
+use apple_smi::smc;
+use apple_smi::syspf;
+use apple_smi::thermal;
+use apple_smi::utils;
+mod fields;
+mod keys;
+mod query;
 mod ui;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use chrono::Local;
+use query::Section;
 
-pub fn render() -> Result<()> {
-    let (gpu_json, os_json) = syspf::run_syspf()?;
-    let root: syspf::Root = serde_json::from_str(&gpu_json)?;
-    let os_ver: syspf::SysProf = serde_json::from_str(&os_json)?;
-    let pwrmtcs_outs: pwrmtcs::GpuMetrics = if utils::is_root() {
-        pwrmtcs::run_pwrmtcs()?
-    } else {
-        pwrmtcs::GpuMetrics {
-            gpu_hw_freq: None,
-            gpu_hw_residency: None,
-            gpu_sw_state: None,
-            // gpu_pwr: None,
+// system_profiler is the GPU list itself, nothing to show without it
+fn require_syspf(snap: &collect::Collected) -> Result<(&syspf::Root, &syspf::SysProf)> {
+    if let Some((root, os)) = snap.syspf.get() {
+        return Ok((root, os));
+    }
+    Err(match &snap.syspf.status {
+        SourceStatus::Failed { message, exit_code } => Error::Source {
+            backend: "system_profiler",
+            message: message.clone(),
+            exit_code: *exit_code,
         }
-    };
-    let ioreg_outs = ioreg::run_ioreg()?;
-    let smc_outs = smc::read_smc_snapshot()?;
+        .into(),
+        SourceStatus::TimedOut(timeout) => CmdError::Timeout {
+            program: String::from("system_profiler"),
+            timeout: *timeout,
+        }
+        .into(),
+        other => anyhow!("system_profiler {}", other),
+    })
+}
+
+pub fn render() -> Result<()> {
+    let snap = daemon::collect(collect::Plan {
+        powermetrics: true,
+        gpu_power: true,
+        processes: true,
+        adapter: true,
+        thermal: false,
+        metal_utilization: true,
+        displays: true,
+        serial: false,
+    });
+    let (root, os_ver) = require_syspf(&snap)?;
+    let pwrmtcs_outs = snap.powermetrics.value.clone().unwrap_or_default();
+    let smc_outs = snap.smc.value.clone().unwrap_or_default();
+    let no_accels = Vec::new();
+    let accels = snap.accels.get().unwrap_or(&no_accels);
+    let no_metal = Vec::new();
+    let metal = snap.metal.get().unwrap_or(&no_metal);
+    let cards = devices::match_cards(&root.gpus, metal, accels);
     let os_label = os_ver
         .system
         .get(0)
@@ -38,21 +70,460 @@ pub fn render() -> Result<()> {
 
     ui::print_div_str(0);
     ui::print_header_line(os_label, metal_ver);
+    ui::print_hardware_line(os_ver.hardware.first());
+    ui::print_power_line(&smc_outs.power, snap.adapter.get());
     ui::print_div_str(1);
     ui::print_title();
     ui::print_div_str(2);
-    for (i, g) in root.gpus.iter().enumerate() {
-        ui::print_card(i, g, &pwrmtcs_outs, &ioreg_outs, &smc_outs);
+    let sampled = snap.metal_utilization.get().map_or(&[][..], |v| v);
+    for (i, c) in cards.iter().enumerate() {
+        ui::print_card(
+            i,
+            c,
+            &pwrmtcs_outs,
+            &smc_outs,
+            snap.gpu_power.get().copied(),
+            sampled,
+        );
     }
     ui::print_empty_line();
     ui::print_tprocess_header();
-    ui::print_processes();
+    ui::print_processes(snap.processes.get());
+    ui::print_notes(&snap.provenance());
+    Ok(())
+}
+
+// Which source each metric came from: {"used_mib": {"source": "ioreg", "status": "ok", ...}}
+fn provenance(metrics: &[(&[&'static str], Provenance)]) -> Section {
+    let mut section = Section::new();
+    for (keys, p) in metrics {
+        for key in *keys {
+            section = section.field(key, p);
+        }
+    }
+    section
+}
+
+/// `-q [-d TYPES] [--json]`: detailed per-GPU report.
+pub fn query(display: Option<&str>, json_out: bool) -> Result<()> {
+    let sections = query::parse_display(display)?;
+    let want = |s: &str| sections.contains(&s);
+
+    let snap = daemon::collect(collect::Plan {
+        powermetrics: true,
+        gpu_power: want("POWER"),
+        processes: false,
+        adapter: want("POWER"),
+        thermal: want("PERFORMANCE"),
+        metal_utilization: want("UTILIZATION") || want("PERFORMANCE"),
+        displays: want("DISPLAY"),
+        serial: true,
+    });
+    let (root, os_ver) = require_syspf(&snap)?;
+    let p = snap.powermetrics.value.clone().unwrap_or_default();
+    let s = snap.smc.value.clone().unwrap_or_default();
+    let gpu_pwr = snap.gpu_power.get().copied();
+    let no_accels = Vec::new();
+    let accels = snap.accels.get().unwrap_or(&no_accels);
+    let no_metal = Vec::new();
+    let metal = snap.metal.get().unwrap_or(&no_metal);
+    let cards = devices::match_cards(&root.gpus, metal, accels);
+    let thermal = snap.thermal.value.clone().unwrap_or_default();
+    let sampled = snap.metal_utilization.get().map_or(&[][..], |v| v);
+
+    let mut report = Section::new()
+        .field(
+            "timestamp",
+            Local::now().format("%a %b %e %T %Y").to_string(),
+        )
+        .field("apple_smi_version", utils::project_version())
+        .field(
+            "macos_version",
+            os_ver.system.first().map(|s| s.os_version_label()),
+        )
+        .field("attached_gpus", root.gpus.len());
+    if let Some(hw) = os_ver.hardware.first() {
+        let (cpu, p_cores, e_cores) = hw.cpu_cores();
+        report = report.section(
+            "hardware",
+            Section::new()
+                .field("model_name", &hw.machine_name)
+                .field("model_identifier", &hw.machine_model)
+                .field("chip", &hw.chip_type)
+                .field("memory_mib", hw.memory_mib())
+                .field("serial_number", snap.serial.get().cloned().flatten())
+                .field("cpu_cores", cpu)
+                .field("performance_cores", p_cores)
+                .field("efficiency_cores", e_cores),
+        );
+    }
+
+    if want("POWER") {
+        let adapter = snap.adapter.get();
+        let pw = &s.power;
+        report = report.section(
+            "system_power",
+            Section::new()
+                .field("system_total_w", query::round2(pw.system_total))
+                .field("dc_in_w", query::round2(pw.dc_in))
+                .field("battery_w", query::round2(pw.battery))
+                .field("battery_rate_w", query::round2(pw.battery_rate()))
+                .field(
+                    "battery_current_ma",
+                    pw.battery_current_ma.map(|x| x as i64),
+                )
+                .field(
+                    "battery_voltage_mv",
+                    pw.battery_voltage_mv.map(|x| x as i64),
+                )
+                .field(
+                    "adapter_connected",
+                    adapter.and_then(|a| a.external_connected),
+                )
+                .field("adapter_rated_w", adapter.and_then(|a| a.watts))
+                .section(
+                    "provenance",
+                    provenance(&[
+                        (
+                            &[
+                                "system_total_w",
+                                "dc_in_w",
+                                "battery_w",
+                                "battery_rate_w",
+                                "battery_current_ma",
+                                "battery_voltage_mv",
+                            ],
+                            snap.smc.provenance(),
+                        ),
+                        (
+                            &["adapter_connected", "adapter_rated_w"],
+                            snap.adapter.provenance(),
+                        ),
+                    ]),
+                ),
+        );
+    }
+
+    if want("FAN") {
+        // Read-only, `fan set` changes them
+        let whole = |v: f32| v.round() as i64;
+        let fans = s
+            .fans
+            .iter()
+            .map(|f| {
+                Section::new()
+                    .field("index", f.index)
+                    .field("current_rpm", whole(f.rpm))
+                    .field("min_rpm", f.min_rpm.map(whole))
+                    .field("max_rpm", f.max_rpm.map(whole))
+                    .field("target_rpm", f.target_rpm.map(whole))
+                    .field("mode", f.mode_label())
+                    .field("speed_pct", f.percent().map(whole))
+            })
+            .collect();
+        report = report.section(
+            "fans",
+            Section::new()
+                .field("fan_count", snap.smc.get().map(|s| s.fans.len()))
+                .list("fan", fans)
+                .section(
+                    "provenance",
+                    provenance(&[(&["fan_count", "fan"], snap.smc.provenance())]),
+                ),
+        );
+    }
+
+    let mut gpus = Vec::new();
+    for c in &cards {
+        let g = c.gpu;
+        let metal = c.metal;
+        let v = c.accel.map(|a| &a.vram);
+        let mut gpu = Section::new()
+            .field("product_name", &g.name)
+            .field("bus", g.bus_label())
+            .field("metal_family", g.metal_lable())
+            .field("gpu_core_count", c.core_count())
+            .field(
+                "display_mode",
+                if g.display_mode() {
+                    "Enabled"
+                } else {
+                    "Disabled"
+                },
+            )
+            .field(
+                "display_active",
+                if g.display_active() {
+                    "Enabled"
+                } else {
+                    "Disabled"
+                },
+            )
+            .field(
+                "registry_id",
+                c.accel
+                    .and_then(|a| a.registry_id)
+                    .map(|id| format!("0x{:x}", id)),
+            );
+        if want("MEMORY") {
+            const MIB: u64 = 1024 * 1024;
+            let total = metal.map(|m| m.recommended_max_working_set_size / MIB);
+            let used = v.and_then(|v| v.inuse_vram);
+            gpu = gpu.section(
+                "fb_memory_usage",
+                Section::new()
+                    // Metal's recommended working set is the closest thing to a VRAM size
+                    .field("total_mib", total)
+                    .field("allocated_mib", v.and_then(|v| v.alloc_vram).or(total))
+                    .field("used_mib", used)
+                    .section(
+                        "provenance",
+                        provenance(&[
+                            (&["total_mib"], snap.metal.provenance()),
+                            (&["allocated_mib", "used_mib"], snap.accels.provenance()),
+                        ]),
+                    ),
+            );
+        }
+        if want("METAL") {
+            gpu = gpu
+                .section_or_na(
+                    "accelerator",
+                    c.accel.map(|a| {
+                        Section::new()
+                            .field("class", &a.class_name)
+                            .field("model", &a.model)
+                    }),
+                )
+                .section_or_na(
+                    "metal_device",
+                    metal.map(|m| {
+                        Section::new()
+                            .field("name", &m.name)
+                            .field("registry_id", format!("0x{:x}", m.registry_id))
+                            .field("architecture", &m.architecture)
+                            .field("unified_memory", m.has_unified_memory)
+                            .field("low_power", m.is_low_power)
+                            .field("removable", m.is_removable)
+                            .field("headless", m.is_headless)
+                            .field(
+                                "recommended_max_working_set_mib",
+                                m.recommended_max_working_set_size / (1024 * 1024),
+                            )
+                            .field(
+                                "current_allocated_mib",
+                                m.current_allocated_size / (1024 * 1024),
+                            )
+                            .field("max_buffer_length_mib", m.max_buffer_length / (1024 * 1024))
+                            .field(
+                                "max_threadgroup_memory_length",
+                                m.max_threadgroup_memory_length,
+                            )
+                            .field("gpu_families", m.gpu_families.join(", "))
+                            .field("counter_sets", m.counter_sets.join(", "))
+                    }),
+                );
+        }
+        if want("DISPLAY") {
+            let displays = g
+                .displays
+                .iter()
+                .map(|d| {
+                    Section::new()
+                        .field("name", &d.name)
+                        .field("connection", d.connection_label())
+                        .field("resolution", d.resolution_label())
+                        .field("pixels", &d.pixels)
+                        .field("refresh_rate_hz", d.refresh_hz())
+                        .field("main", d.is_main())
+                        .field("mirror", d.is_mirror())
+                        .field("online", d.is_online())
+                })
+                .collect();
+            gpu = gpu.list("display", displays);
+        }
+        let util = if want("UTILIZATION") || want("PERFORMANCE") {
+            c.utilization(&p, sampled)
+        } else {
+            None
+        };
+        if want("UTILIZATION") {
+            let probe = util.as_ref().and_then(|u| u.metal.as_ref());
+            let mut section = Section::new().field("gpu_pct", util.as_ref().map(|u| u.pct.trunc()));
+            let mut sources = provenance(&[(
+                &["gpu_pct"],
+                snap.utilization_provenance(util.as_ref().map(|u| u.source)),
+            )]);
+            if let Some(m) = probe {
+                section = section.section(
+                    "metal_timestamps",
+                    Section::new()
+                        .field("cpu_timestamp", m.cpu_timestamp)
+                        .field("gpu_timestamp", m.gpu_timestamp)
+                        .field("ns_per_gpu_tick", (m.ns_per_tick * 1e6).round() / 1e6)
+                        .field("probes", m.probes)
+                        .field("counter_set", m.counter_set),
+                );
+                sources = sources.field("metal_timestamps", snap.metal_utilization.provenance());
+            }
+            gpu = gpu.section("utilization", section.section("provenance", sources));
+        }
+        if want("POWER") {
+            gpu = gpu.section(
+                "power_readings",
+                Section::new()
+                    .field("power_draw_w", query::round2(gpu_pwr))
+                    // Share of wall power, useful to normalize GPU power on laptops
+                    .field(
+                        "share_of_system_pct",
+                        match (gpu_pwr, s.power.system_total) {
+                            (Some(g), Some(t)) if t > 0.0 => query::round2(Some(g / t * 100.0)),
+                            _ => None,
+                        },
+                    )
+                    .section(
+                        "provenance",
+                        provenance(&[(
+                            &["power_draw_w", "share_of_system_pct"],
+                            snap.gpu_power.provenance(),
+                        )]),
+                    ),
+            );
+        }
+        if want("PERFORMANCE") {
+            let reasons = thermal::EventReasons::from_state(&thermal, util.as_ref().map(|u| u.pct));
+            let active = |v: Option<bool>| v.map(|a| if a { "Active" } else { "Not Active" });
+            gpu = gpu
+                .field(
+                    "performance_state",
+                    p.gpu_sw_state.map(|i| format!("P{}", i)),
+                )
+                .section(
+                    "clocks_event_reasons",
+                    Section::new()
+                        .field("active_mask", format!("0x{:016X}", reasons.active_mask()))
+                        .field("gpu_idle", active(reasons.gpu_idle))
+                        .field("sw_power_cap", active(reasons.sw_power_cap))
+                        .field("sw_thermal_slowdown", active(reasons.sw_thermal_slowdown))
+                        .field("hw_thermal_slowdown", active(reasons.hw_thermal_slowdown))
+                        .field("thermal_pressure", thermal.pressure.map(|t| t.label()))
+                        .field("low_power_mode", thermal.low_power_mode)
+                        .field("power_source", &thermal.power_source)
+                        .section(
+                            "provenance",
+                            provenance(&[
+                                (
+                                    &["gpu_idle"],
+                                    snap.utilization_provenance(util.as_ref().map(|u| u.source)),
+                                ),
+                                (
+                                    &[
+                                        "sw_power_cap",
+                                        "sw_thermal_slowdown",
+                                        "hw_thermal_slowdown",
+                                        "thermal_pressure",
+                                        "low_power_mode",
+                                        "power_source",
+                                    ],
+                                    snap.thermal.provenance(),
+                                ),
+                            ]),
+                        ),
+                )
+                .section(
+                    "provenance",
+                    provenance(&[(&["performance_state"], snap.powermetrics.provenance())]),
+                );
+        }
+        gpus.push(gpu);
+    }
+    report = report.list("gpu", gpus);
+    // Every source in a "provenance" above is a key here, with status and error if it degraded
+    let sources = snap
+        .provenance()
+        .into_iter()
+        .fold(Section::new(), |sources, (k, p)| sources.field(k, p));
+    report = report.section("sources", sources);
+
+    if json_out {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        query::print_report(&report);
+    }
+    Ok(())
+}
+
+/// `--query-gpu=FIELDS --format=csv`: one CSV line per GPU.
+pub fn query_gpu(fields: &str, format: &str) -> Result<()> {
+    let fields = fields::parse_fields(fields)?;
+    let format = fields::parse_format(format)?;
+    let reasons = fields.iter().any(|f| f.is_reason());
+    let needs_util = fields.iter().any(|f| {
+        matches!(
+            f.name,
+            "utilization.gpu" | "clocks_event_reasons.active" | "clocks_event_reasons.gpu_idle"
+        )
+    });
+
+    let snap = daemon::collect(collect::Plan {
+        powermetrics: true,
+        gpu_power: false,
+        processes: false,
+        adapter: false,
+        thermal: reasons,
+        metal_utilization: needs_util,
+        displays: false,
+        serial: false,
+    });
+    let (root, _) = require_syspf(&snap)?;
+    let p = snap.powermetrics.value.clone().unwrap_or_default();
+    let no_accels = Vec::new();
+    let accels = snap.accels.get().unwrap_or(&no_accels);
+    let no_metal = Vec::new();
+    let metal = snap.metal.get().unwrap_or(&no_metal);
+    let cards = devices::match_cards(&root.gpus, metal, accels);
+    let thermal = snap.thermal.value.clone().unwrap_or_default();
+    let sampled = snap.metal_utilization.get().map_or(&[][..], |v| v);
+
+    let mut rows = Vec::new();
+    for (i, c) in cards.iter().enumerate() {
+        let util = if needs_util {
+            c.utilization(&p, sampled)
+        } else {
+            None
+        };
+        let er = thermal::EventReasons::from_state(&thermal, util.as_ref().map(|u| u.pct));
+        let active =
+            |v: Option<bool>| v.map(|a| String::from(if a { "Active" } else { "Not Active" }));
+        let row = fields
+            .iter()
+            .map(|f| match f.name {
+                "index" => Some(i.to_string()),
+                "name" => Some(c.gpu.name.clone()),
+                "pstate" => p.gpu_sw_state.map(|s| format!("P{}", s)),
+                "utilization.gpu" => util.as_ref().map(|u| format!("{:.0}", u.pct.trunc())),
+                "clocks.gr" => p.gpu_hw_freq.map(|f| f.to_string()),
+                "clocks_event_reasons.active" => Some(format!("0x{:016X}", er.active_mask())),
+                "clocks_event_reasons.gpu_idle" => active(er.gpu_idle),
+                "clocks_event_reasons.sw_power_cap" => active(er.sw_power_cap),
+                "clocks_event_reasons.sw_thermal_slowdown" => active(er.sw_thermal_slowdown),
+                "clocks_event_reasons.hw_thermal_slowdown" => active(er.hw_thermal_slowdown),
+                "clocks_event_reasons.thermal_pressure" => {
+                    thermal.pressure.map(|t| t.label().to_string())
+                }
+                "clocks_event_reasons.low_power_mode" => active(thermal.low_power_mode),
+                "clocks_event_reasons.power_source" => thermal.power_source.clone(),
+                other => unreachable!("unknown field {}", other),
+            })
+            .collect();
+        rows.push(row);
+    }
+    fields::print_csv(&fields, &rows, &format);
     Ok(())
 }
 
 pub fn list_gpus() -> Result<()> {
-    let (gpu_json, _) = syspf::run_syspf()?;
-    let root: syspf::Root = serde_json::from_str(&gpu_json)?;
+    let root: syspf::Root = syspf::parse(&syspf::run_syspf(false)?)?;
     // Outs like GPU 0: Apple M4 [Built-in] (Metal 4)
     for (idx, gpu) in root.gpus.iter().enumerate() {
         println!(
@@ -65,4 +536,88 @@ pub fn list_gpus() -> Result<()> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn list_displays() -> Result<()> {
+    let root: syspf::Root = syspf::parse(&syspf::run_syspf(true)?)?;
+    // GPU 0: Apple M4
+    //     Display 0: DELL U2720Q [DisplayPort] 1920 x 1080 @ 60Hz (3840 x 2160), main, online
+    for (idx, gpu) in root.gpus.iter().enumerate() {
+        println!("GPU {}: {}", idx, gpu.name);
+        if gpu.displays.is_empty() {
+            println!("    No displays connected.");
+        }
+        for (i, d) in gpu.displays.iter().enumerate() {
+            let mut flags = Vec::new();
+            if d.is_main() {
+                flags.push("main");
+            }
+            if d.is_mirror() {
+                flags.push("mirror");
+            }
+            flags.push(if d.is_online() { "online" } else { "offline" });
+            let refresh = d
+                .refresh_hz()
+                .map(|hz| format!(" @ {}Hz", hz))
+                .unwrap_or_default();
+            let pixels = if d.pixels.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", d.pixels)
+            };
+            println!(
+                "    Display {}: {} [{}] {}{}{}, {}",
+                i,
+                d.name,
+                d.connection_label(),
+                d.resolution_label(),
+                refresh,
+                pixels,
+                flags.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+pub enum SmcView<'a> {
+    List(Option<&'a str>),
+    Read(&'a str),
+    Dump(Option<&'a str>),
+}
+
+pub fn smc_keys(view: SmcView, json: bool) -> Result<()> {
+    let mut conn = smc::SMC::new()?;
+    let (pattern, with_value) = match view {
+        SmcView::List(p) => (p, false),
+        SmcView::Dump(p) => (p, true),
+        SmcView::Read(key) => {
+            // A malformed key comes back as SmcError::BadKey
+            let entry = conn.read_entry(key, true)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entry)?);
+            } else {
+                keys::print_entries(&[entry], true);
+            }
+            return Ok(());
+        }
+    };
+
+    let mut entries = Vec::new();
+    for key in conn.list_keys()? {
+        if pattern.is_some_and(|p| !utils::glob_match(p, &key)) {
+            continue;
+        }
+        // Some keys are listed but refuse key-info, skip them like read_all_keys does
+        if let Ok(e) = conn.read_entry(&key, with_value) {
+            entries.push(e);
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        keys::print_entries(&entries, with_value);
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * fields.rs
+ * Construct the --query-gpu CSV output, like `nvidia-smi --query-gpu=... --format=csv`.
+ */
+
+use anyhow::Result;
+use apple_smi::error::Error;
+
+// Valid --query-gpu fields and the unit their values are in
+pub const FIELDS: [(&str, Option<&str>); 13] = [
+    ("index", None),
+    ("name", None),
+    ("pstate", None),
+    ("utilization.gpu", Some("%")),
+    ("clocks.gr", Some("MHz")),
+    ("clocks_event_reasons.active", None),
+    ("clocks_event_reasons.gpu_idle", None),
+    ("clocks_event_reasons.sw_power_cap", None),
+    ("clocks_event_reasons.sw_thermal_slowdown", None),
+    ("clocks_event_reasons.hw_thermal_slowdown", None),
+    // Not in nvidia-smi, the Mac side of the reasons above
+    ("clocks_event_reasons.thermal_pressure", None),
+    ("clocks_event_reasons.low_power_mode", None),
+    ("clocks_event_reasons.power_source", None),
+];
+
+// nvidia-smi's older name for clocks_event_reasons, still accepted
+const LEGACY_PREFIX: &str = "clocks_throttle_reasons.";
+
+/// A requested field: the name as typed (for the header) and the canonical one.
+pub struct Field {
+    pub typed: String,
+    pub name: &'static str,
+    pub unit: Option<&'static str>,
+}
+
+impl Field {
+    pub fn is_reason(&self) -> bool {
+        self.name.starts_with("clocks_event_reasons.")
+    }
+}
+
+/// Parse "index,name,clocks_event_reasons.active" into fields.
+pub fn parse_fields(arg: &str) -> Result<Vec<Field>> {
+    let mut out = Vec::new();
+    for typed in arg.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let lower = typed.to_ascii_lowercase();
+        let canonical = match lower.strip_prefix(LEGACY_PREFIX) {
+            Some(rest) => format!("clocks_event_reasons.{}", rest),
+            None => lower,
+        };
+        match FIELDS.iter().find(|(name, _)| *name == canonical) {
+            Some((name, unit)) => out.push(Field {
+                typed: typed.to_string(),
+                name,
+                unit: *unit,
+            }),
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "Field \"{}\" is not a valid field to query, valid fields: {}",
+                    typed,
+                    FIELDS.map(|(name, _)| name).join(", ")
+                ))
+                .into());
+            }
+        }
+    }
+    if out.is_empty() {
+        return Err(
+            Error::InvalidArgument(String::from("--query-gpu needs at least one field")).into(),
+        );
+    }
+    Ok(out)
+}
+
+pub struct Format {
+    pub header: bool,
+    pub units: bool,
+}
+
+/// Parse "csv,noheader,nounits"; csv is the only format, and it is required.
+pub fn parse_format(arg: &str) -> Result<Format> {
+    let mut csv = false;
+    let mut format = Format {
+        header: true,
+        units: true,
+    };
+    for part in arg.split(',').map(|p| p.trim()) {
+        match part {
+            "csv" => csv = true,
+            "noheader" => format.header = false,
+            "nounits" => format.units = false,
+            other => {
+                return Err(Error::InvalidArgument(format!(
+                    "\"{}\" is not a valid format option, use csv[,noheader][,nounits]",
+                    other
+                ))
+                .into());
+            }
+        }
+    }
+    if !csv {
+        return Err(Error::InvalidArgument(String::from("--format must include csv")).into());
+    }
+    Ok(format)
+}
+
+/// Print the header (unless noheader) and one line per row, N/A as "[N/A]".
+pub fn print_csv(fields: &[Field], rows: &[Vec<Option<String>>], format: &Format) {
+    if format.header {
+        let header: Vec<String> = fields
+            .iter()
+            .map(|f| match f.unit.filter(|_| format.units) {
+                Some(u) => format!("{} [{}]", f.typed, u),
+                None => f.typed.clone(),
+            })
+            .collect();
+        println!("{}", header.join(", "));
+    }
+    for row in rows {
+        let cells: Vec<String> = fields
+            .iter()
+            .zip(row)
+            .map(|(f, v)| match (v, f.unit.filter(|_| format.units)) {
+                (Some(v), Some(u)) => format!("{} {}", v, u),
+                (Some(v), None) => v.clone(),
+                (None, _) => String::from("[N/A]"),
+            })
+            .collect();
+        println!("{}", cells.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fields_and_legacy_names() {
+        let f =
+            parse_fields("index, clocks_throttle_reasons.HW_Thermal_Slowdown,clocks.gr").unwrap();
+        let names: Vec<_> = f.iter().map(|f| f.name).collect();
+        assert_eq!(
+            names,
+            [
+                "index",
+                "clocks_event_reasons.hw_thermal_slowdown",
+                "clocks.gr"
+            ]
+        );
+        assert_eq!(f[1].typed, "clocks_throttle_reasons.HW_Thermal_Slowdown");
+        assert!(f[1].is_reason() && !f[2].is_reason());
+        assert_eq!(f[2].unit, Some("MHz"));
+        assert!(parse_fields("index,temperature.gpu").is_err());
+        assert!(parse_fields(",").is_err());
+    }
+
+    #[test]
+    fn parses_format() {
+        let f = parse_format("csv,noheader,nounits").unwrap();
+        assert!(!f.header && !f.units);
+        let f = parse_format("csv").unwrap();
+        assert!(f.header && f.units);
+        assert!(parse_format("noheader").is_err());
+        assert!(parse_format("csv,json").is_err());
+    }
+}
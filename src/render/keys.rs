@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * keys.rs
+ * Construct SMC key browser output.
+ */
+
+use apple_smi::smc::KeyEntry;
+
+/*
+ * One key per line, e.g.
+ * "TG0P  [flt ]     4  0x80  00 00 2a 42  42.5"
+ * The dump format is also what the SMC simulator loads, so keep it stable.
+ */
+pub fn print_entries(entries: &[KeyEntry], with_value: bool) {
+    if with_value {
+        println!("KEY   TYPE    SIZE  ATTR  BYTES  VALUE");
+    } else {
+        println!("KEY   TYPE    SIZE  ATTR");
+    }
+    for e in entries {
+        let mut line = format!(
+            "{:<4}  [{}]  {:>4}  0x{:02x}",
+            e.key, e.data_type, e.data_size, e.attributes
+        );
+        if with_value {
+            let hex = match e.bytes {
+                Some(_) => e.hex(),
+                None => String::from("-"),
+            };
+            line.push_str(&format!("  {}  {}", hex, e.value.as_deref().unwrap_or("-")));
+        }
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * query.rs
+ * Construct the detailed (-q) output, like `nvidia-smi -q`.
+ */
+
+use anyhow::Result;
+use apple_smi::error::Error;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{Map, Value, json};
+
+// Valid -d/--display values, in output order
+pub const SECTIONS: [&str; 7] = [
+    "MEMORY",
+    "METAL",
+    "DISPLAY",
+    "UTILIZATION",
+    "POWER",
+    "PERFORMANCE",
+    "FAN",
+];
+
+/// Parse "-d POWER,MEMORY" into the selected sections; no -d means everything.
+pub fn parse_display(arg: Option<&str>) -> Result<Vec<&'static str>> {
+    let Some(arg) = arg else {
+        return Ok(SECTIONS.to_vec());
+    };
+    let mut out = Vec::new();
+    for part in arg.split(',').map(|p| p.trim().to_ascii_uppercase()) {
+        match SECTIONS.iter().find(|s| **s == part) {
+            Some(s) => out.push(*s),
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "Unknown display type '{}', valid types: {}",
+                    part,
+                    SECTIONS.join(", ")
+                ))
+                .into());
+            }
+        }
+    }
+    Ok(out)
+}
+
+/*
+ * JSON keys carry their unit as a suffix ("power_draw_w") so the JSON stays
+ * numeric; the text view moves the unit behind the value ("Power Draw : 1.20 W").
+ */
+const UNITS: [(&str, &str); 12] = [
+    ("_mhz", "MHz"),
+    ("_hz", "Hz"),
+    ("_mib", "MiB"),
+    ("_rpm", "RPM"),
+    ("_pct", "%"),
+    ("_mw", "mW"),
+    ("_ma", "mA"),
+    ("_mv", "mV"),
+    ("_ms", "ms"),
+    ("_w", "W"),
+    ("_c", "C"),
+    ("_s", "s"),
+];
+
+const ACRONYMS: [(&str, &str); 13] = [
+    ("gpu", "GPU"),
+    ("gpus", "GPUs"),
+    ("cpu", "CPU"),
+    ("dc", "DC"),
+    ("smi", "SMI"),
+    ("fb", "FB"),
+    ("id", "ID"),
+    ("macos", "macOS"),
+    ("os", "OS"),
+    ("sw", "SW"),
+    ("hw", "HW"),
+    ("smc", "SMC"),
+    ("ioreport", "IOReport"),
+];
+
+fn label(key: &str) -> (String, Option<&'static str>) {
+    let (base, unit) = match UNITS.iter().find(|(suffix, _)| key.ends_with(suffix)) {
+        Some((suffix, unit)) => (&key[..key.len() - suffix.len()], Some(*unit)),
+        None => (key, None),
+    };
+    let words: Vec<String> = base
+        .split('_')
+        .map(|w| {
+            if let Some((_, a)) = ACRONYMS.iter().find(|(k, _)| *k == w) {
+                a.to_string()
+            } else {
+                let mut c = w.chars();
+                match c.next() {
+                    Some(f) => f.to_ascii_uppercase().to_string() + c.as_str(),
+                    None => String::new(),
+                }
+            }
+        })
+        .collect();
+    (words.join(" "), unit)
+}
+
+fn scalar(v: &Value, unit: Option<&str>) -> String {
+    let s = match v {
+        Value::Null => return String::from("N/A"),
+        Value::Bool(true) => String::from("Yes"),
+        Value::Bool(false) => String::from("No"),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => i.to_string(),
+            (None, Some(f)) => format!("{:.2}", f),
+            _ => n.to_string(),
+        },
+        Value::String(s) => s.clone(),
+        _ => String::new(),
+    };
+    match unit {
+        Some(u) => format!("{} {}", s, u),
+        None => s,
+    }
+}
+
+// Colons line up at this column regardless of nesting, same as nvidia-smi -q
+const KEY_WIDTH: usize = 42;
+
+// A serialized `Provenance` on one line: "ioreport ok, 200 ms window", "powermetrics skipped: needs root"
+fn provenance_line(map: &Map<String, Value>) -> Option<String> {
+    let source = map.get("source")?.as_str()?;
+    let status = map.get("status")?.as_str()?.replace('_', " ");
+    let mut line = format!("{} {}", source, status);
+    if let Some(ms) = map.get("window_ms").and_then(Value::as_u64) {
+        line += &format!(", {} ms window", ms);
+    }
+    if let Some(e) = map.get("error").and_then(Value::as_str) {
+        line += &format!(": {}", e);
+    }
+    Some(line)
+}
+
+/// One part of the -q report; text and JSON keep the keys in the order they were added.
+#[derive(Debug, Default)]
+pub struct Section(Vec<(&'static str, Entry)>);
+
+#[derive(Debug)]
+enum Entry {
+    Value(Value),
+    Section(Section),
+    List(Vec<Section>),
+}
+
+impl Section {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, key: &'static str, value: impl Serialize) -> Self {
+        self.0.push((key, Entry::Value(json!(value))));
+        self
+    }
+
+    pub fn section(mut self, key: &'static str, section: Section) -> Self {
+        self.0.push((key, Entry::Section(section)));
+        self
+    }
+
+    // N/A without the device behind it
+    pub fn section_or_na(self, key: &'static str, section: Option<Section>) -> Self {
+        match section {
+            Some(s) => self.section(key, s),
+            None => self.field(key, Value::Null),
+        }
+    }
+
+    pub fn list(mut self, key: &'static str, sections: Vec<Section>) -> Self {
+        self.0.push((key, Entry::List(sections)));
+        self
+    }
+}
+
+// As a map in insertion order, a serde_json::Value would sort it
+impl Serialize for Section {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, entry) in &self.0 {
+            match entry {
+                Entry::Value(v) => map.serialize_entry(key, v)?,
+                Entry::Section(s) => map.serialize_entry(key, s)?,
+                Entry::List(l) => map.serialize_entry(key, l)?,
+            }
+        }
+        map.end()
+    }
+}
+
+fn print_line(indent: &str, name: &str, value: &str) {
+    println!(
+        "{}{:<w$}: {}",
+        indent,
+        name,
+        value,
+        w = KEY_WIDTH.saturating_sub(indent.len())
+    );
+}
+
+fn print_value(indent: &str, depth: usize, key: &str, v: &Value) {
+    let (name, unit) = label(key);
+    match v {
+        Value::Object(inner) => match provenance_line(inner) {
+            Some(line) => print_line(indent, &name, &line),
+            None => {
+                println!("{}{}", indent, name);
+                for (k, v) in inner {
+                    print_value(&"    ".repeat(depth + 1), depth + 1, k, v);
+                }
+            }
+        },
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                print_line(indent, &format!("{} {}", name, i), &scalar(item, unit));
+            }
+        }
+        other => print_line(indent, &name, &scalar(other, unit)),
+    }
+}
+
+fn print_section(section: &Section, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for (key, entry) in &section.0 {
+        match entry {
+            Entry::Value(v) => print_value(&indent, depth, key, v),
+            Entry::Section(inner) => {
+                println!("{}{}", indent, label(key).0);
+                print_section(inner, depth + 1);
+            }
+            Entry::List(items) => {
+                for (i, inner) in items.iter().enumerate() {
+                    println!("{}{} {}", indent, label(key).0, i);
+                    print_section(inner, depth + 1);
+                }
+            }
+        }
+    }
+}
+
+pub fn print_report(report: &Section) {
+    println!();
+    println!("==============APPLE-SMI LOG==============");
+    println!();
+    print_section(report, 0);
+    println!();
+}
+
+/// Round for display/JSON, f32 readings otherwise leak digits like 18.700000762939453.
+pub fn round2(v: Option<f32>) -> Option<f64> {
+    v.map(|x| (x as f64 * 100.0).round() / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_keep_their_order() {
+        let report = Section::new()
+            .field("timestamp", "Mon Oct 19 03:02:05 2026")
+            .field("attached_gpus", 1)
+            .list(
+                "gpu",
+                vec![
+                    Section::new()
+                        .field("product_name", "Apple M4")
+                        .section("fb_memory_usage", Section::new().field("total_mib", 10923))
+                        .section_or_na("accelerator", None),
+                ],
+            );
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            concat!(
+                r#"{"timestamp":"Mon Oct 19 03:02:05 2026","attached_gpus":1,"gpu":[{"#,
+                r#""product_name":"Apple M4","fb_memory_usage":{"total_mib":10923},"accelerator":null}]}"#
+            )
+        );
+    }
+}
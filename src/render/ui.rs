@@ -6,13 +6,14 @@
  * Construct output text.
  */
 
-use crate::ioreg;
-use crate::ioreport;
-use crate::mtlapi;
-use crate::pwrmtcs;
-use crate::smc;
-use crate::syspf;
-use crate::utils;
+use apple_smi::collect::{Provenance, StatusKind};
+use apple_smi::devices;
+use apple_smi::ioreg;
+use apple_smi::mtlapi;
+use apple_smi::pwrmtcs;
+use apple_smi::smc;
+use apple_smi::syspf;
+use apple_smi::utils;
 fn pad(s: &str, width: usize) -> String {
     if s.len() >= width {
         s[..width].to_string()
@@ -76,6 +77,59 @@ pub fn print_header_line(os_version: &str, metal_version: &str) {
     println!("{}", line);
 }
 
+/// Model, chip and memory from SPHardwareDataType; skipped when system_profiler has none.
+pub fn print_hardware_line(hw: Option<&syspf::Hardware>) {
+    let Some(hw) = hw else {
+        return;
+    };
+    const SEGMENTS: [usize; 3] = [36, 30, 23];
+    let container: [String; 3] = [
+        format!(" {} ({})", hw.machine_name, hw.machine_model),
+        format!("Chip: {}", hw.chip_label()),
+        format!("Memory: {}", hw.physical_memory),
+    ];
+
+    let mut line = String::from("|");
+    for col in 0..3 {
+        line.push_str(&pad(container[col].as_str(), SEGMENTS[col]));
+    }
+    line.push('|');
+    println!("{}", line);
+}
+
+/// Whole-machine power below the version banner; skipped when SMC has none of it.
+pub fn print_power_line(pw: &smc::SystemPower, adapter: Option<&ioreg::AdapterInfo>) {
+    if pw.system_total.is_none() && pw.dc_in.is_none() && pw.battery_rate().is_none() {
+        return;
+    }
+    const SEGMENTS: [usize; 3] = [36, 30, 23];
+    let watts = |v: Option<f32>| match v {
+        Some(w) => format!("{:.1}W", w),
+        None => String::from("N/A"),
+    };
+    let dc_in = match adapter.and_then(|a| a.watts) {
+        Some(rated) => format!("DC-In: {} / {}W", watts(pw.dc_in), rated),
+        None => format!("DC-In: {}", watts(pw.dc_in)),
+    };
+    let battery = match pw.battery_rate() {
+        Some(w) => format!("Battery: {:+.1}W", w),
+        None => String::from("Battery: N/A"),
+    };
+    let container: [String; 3] = [
+        format!(" System Power: {}", watts(pw.system_total)),
+        dc_in,
+        battery,
+    ];
+
+    let mut line = String::from("|");
+    for col in 0..3 {
+        line.push_str(&pad(container[col].as_str(), SEGMENTS[col]));
+    }
+    line.push('|');
+
+    println!("{}", line);
+}
+
 pub fn print_title() {
     // Columns must align with type 1 divider: segments 41, 24, 22.
     const SEGMENTS: [[usize; 3]; 3] = [[41, 25, 23], [41, 25, 23], [41, 25, 23]];
@@ -83,7 +137,7 @@ pub fn print_title() {
         [
             String::from(" GPU  Name                     Frequency"), // leading space per requirement
             String::from("| Bus-Id          Disp.A"),
-            String::from("|"),
+            String::from("|               Disp.M"),
         ],
         [
             String::from(" Fan  Temp   Perf              Pwr:Usage"),
@@ -107,40 +161,57 @@ pub fn print_title() {
 
 pub fn print_card(
     i: usize,
-    g: &syspf::GpuEntry,
+    c: &devices::Card,
     p: &pwrmtcs::GpuMetrics,
-    v: &ioreg::VramInfo,
     s: &smc::SmcSnapshot,
+    gpu_power: Option<f32>,
+    sampled: &[mtlapi::MetalUtilization],
 ) {
+    let g = c.gpu;
+    let m = c.metal;
     let name: &str = g.name.as_str();
     let bus: &str = g.bus_label();
     let freq = match p.gpu_hw_freq {
         Some(v) => format!("{:>4}", v), // right-align to 4 chars, fill leading spaces as needed
         None => String::from(" N/A"),
     };
-    let status = match mtlapi::gpu_initialized_probe() {
-        true => "On",
-        false => "Off",
-    };
-    let disp_a = format!("{:>3}", status);
-    let pwr = match ioreport::sample_gpu_power_once(200) {
-        Ok(Some(w)) => format!("{:>6.0}", w * 1000.0),
-        _ => format!("{:>6}", "N/A"),
+    let on_off = |b: bool| if b { "On" } else { "Off" };
+    let disp_a = format!("{:>3}", on_off(g.display_active()));
+    let disp_m = format!("{:>3}", on_off(g.display_mode()));
+    let pwr = match gpu_power {
+        Some(w) => format!("{:>6.0}", w * 1000.0),
+        None => format!("{:>6}", "N/A"),
     };
     let gpu_sw_state = match p.gpu_sw_state {
         Some(idx) => format!("P{}", idx),
         None => String::from("N/A"),
     };
-    let vram_status = match (v.inuse_vram, v.alloc_vram) {
-        (Some(inuse), Some(alloc)) => format!("{:>22}", format!("{}MiB / {}MiB", inuse, alloc)),
-        _ => String::from("N/A"),
+    // Without ioreg fall back to Metal: our own allocations against the working set
+    let v = c.accel.map(|a| &a.vram);
+    let vram_status = match (
+        v.and_then(|v| v.inuse_vram),
+        v.and_then(|v| v.alloc_vram),
+        m,
+    ) {
+        (Some(inuse), Some(alloc), _) => {
+            format!("{:>22}", format!("{}MiB / {}MiB", inuse, alloc))
+        }
+        // Without ioreg's in-use figure only the size is known
+        (_, _, Some(m)) => format!(
+            "{:>22}",
+            format!(
+                "N/A / {}MiB",
+                m.recommended_max_working_set_size / (1024 * 1024)
+            )
+        ),
+        _ => format!("{:>22}", "N/A"),
     };
-    let gpu_residency = match p.gpu_hw_residency {
-        Some(v) => format!("{:>7}", format!("{:.0}%", v.trunc())),
+    let gpu_residency = match c.utilization(p, sampled) {
+        Some(u) => format!("{:>7}", format!("{:.0}%", u.pct.trunc())),
         None => format!("{:>7}", "N/A"),
     };
-    let fan_speed = match s.fans.iter().map(|f| f.rpm).reduce(|a, b| a + b) {
-        Some(v) => format!("{:>4.0}", v),
+    let fan_speed = match s.fan_percent() {
+        Some(v) => format!("{:>3.0}%", v),
         None => String::from("N/A "),
     };
     let gpu_temp = match s.gpu_temp_avg {
@@ -150,9 +221,9 @@ pub fn print_card(
     const SEGMENTS: [[usize; 3]; 3] = [[32, 30, 27], [31, 12, 46], [41, 25, 23]];
     let container: [[String; 3]; 3] = [
         [
-            format!("   {}  {}", i, name),       // leading space per requirement
-            format!("{} MHz |   {}", freq, bus), // TODO: display status from syspf display list
-            format!("{} |", disp_a),
+            format!("   {}  {}", i, name), // leading space per requirement
+            format!("{} MHz |   {}", freq, bus),
+            format!("{} |{:>21}", disp_a, disp_m),
         ],
         // TODO: Fill real data by powermetrics
         [
@@ -202,10 +273,10 @@ pub fn print_tprocess_header() {
     print_div_str(3);
 }
 
-pub fn print_processes() {
-    let procs = match pwrmtcs::run_pwrmtcs_procs() {
-        Ok(p) => p,
-        Err(_) => {
+pub fn print_processes(procs: Option<&Vec<pwrmtcs::ProcGpu>>) {
+    let procs = match procs {
+        Some(p) => p,
+        None => {
             println!("|{:^89}|", "N/A");
             print_div_str(3);
             return;
@@ -234,3 +305,33 @@ pub fn print_processes() {
     }
     print_div_str(0);
 }
+
+// Table columns that go N/A when a source is missing
+fn affected_columns(key: &str) -> &'static str {
+    match key {
+        "powermetrics" => "Frequency, Perf",
+        "ioreg" => "Memory-Usage, GPU-Util",
+        "smc" => "Fan, Temp, System Power",
+        "metal" => "Memory-Usage fallback",
+        "metal_utilization" => "GPU-Util fallback",
+        "ioreport" => "Pwr:Usage",
+        "adapter" => "DC-In rating",
+        "processes" => "Processes",
+        _ => "",
+    }
+}
+
+/// Footnotes explaining every N/A that comes from a degraded source.
+pub fn print_notes(sources: &[(&str, Provenance)]) {
+    let degraded: Vec<_> = sources
+        .iter()
+        .filter(|(_, p)| p.status != StatusKind::Ok)
+        .collect();
+    if degraded.is_empty() {
+        return;
+    }
+    println!("Notes:");
+    for (key, p) in degraded {
+        println!("  * {}: {}", affected_columns(key), p);
+    }
+}
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * service.rs
+ * Install `apple-smi daemon` as a launchd LaunchDaemon so it survives reboots.
+ */
+use crate::daemon;
+use anyhow::{Context, Result};
+use apple_smi::error::{CmdError, Error};
+use apple_smi::launchd::{LAUNCH_DAEMONS, Service, plist_path};
+use apple_smi::utils::{self, CMD_TIMEOUT, output_timeout};
+use serde_json::json;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn require_root(what: &str) -> Result<()> {
+    if !utils::is_root() {
+        return Err(Error::PermissionDenied(format!(
+            "{} a LaunchDaemon requires root, try sudo (or --dry-run)",
+            what
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn launchctl(args: &[&str]) -> Result<Output, CmdError> {
+    output_timeout(Command::new("launchctl").args(args), CMD_TIMEOUT)
+}
+
+// Failing is fine when it just wasn't loaded
+fn bootout(label: &str) -> bool {
+    launchctl(&["bootout", &format!("system/{}", label)]).is_ok_and(|o| o.status.success())
+}
+
+/// `service install`: write the plist and load it, replacing an older one.
+pub fn install(svc: &Service, dry_run: bool) -> Result<()> {
+    let path = plist_path(&svc.label)?;
+    if dry_run {
+        print!("{}", svc.plist());
+        return Ok(());
+    }
+    require_root("installing")?;
+    if !svc.program.is_absolute() {
+        return Err(Error::InvalidArgument(format!(
+            "launchd needs an absolute program path, got {}",
+            svc.program.display()
+        ))
+        .into());
+    }
+    if path.exists() {
+        bootout(&svc.label);
+    }
+    std::fs::create_dir_all(&svc.log_dir)
+        .with_context(|| format!("creating {}", svc.log_dir.display()))?;
+    std::fs::write(&path, svc.plist()).with_context(|| format!("writing {}", path.display()))?;
+
+    let path_arg = path.display().to_string();
+    let out = launchctl(&["bootstrap", "system", &path_arg])?;
+    if !out.status.success() {
+        return Err(CmdError::Exit {
+            program: String::from("launchctl bootstrap"),
+            status: out.status,
+        }
+        .into());
+    }
+    println!("Installed {} ({})", svc.label, path.display());
+    println!(
+        "Logs: {}, {}",
+        svc.log_path(".log").display(),
+        svc.log_path(".err.log").display()
+    );
+    Ok(())
+}
+
+/// `service uninstall`: unload the daemon and remove its plist.
+pub fn uninstall(label: &str, dry_run: bool) -> Result<()> {
+    let path = plist_path(label)?;
+    if dry_run {
+        println!("launchctl bootout system/{}", label);
+        println!("rm {}", path.display());
+        return Ok(());
+    }
+    require_root("removing")?;
+    let was_loaded = bootout(label);
+    if !path.exists() {
+        if was_loaded {
+            println!("Unloaded {}, it had no plist in {}", label, LAUNCH_DAEMONS);
+            return Ok(());
+        }
+        return Err(Error::InvalidArgument(format!("{} is not installed", label)).into());
+    }
+    std::fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+    println!("Uninstalled {} ({})", label, path.display());
+    Ok(())
+}
+
+// `launchctl print` says "state = running" and "pid = 123" among a lot else
+fn launchd_state(label: &str) -> Option<(String, Option<u32>)> {
+    let out = launchctl(&["print", &format!("system/{}", label)]).ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&out.stdout);
+    let field = |name: &str| {
+        text.lines()
+            .filter_map(|l| l.trim().strip_prefix(name))
+            .find_map(|rest| rest.trim_start().strip_prefix('='))
+            .map(|v| v.trim().to_string())
+    };
+    let state = field("state").unwrap_or_else(|| String::from("loaded"));
+    Some((state, field("pid").and_then(|p| p.parse().ok())))
+}
+
+/// `service status`: is the plist there, is launchd running it, does the socket answer.
+pub fn status(label: &str, socket: &Path, json_out: bool) -> Result<()> {
+    let path = plist_path(label)?;
+    let installed = path.exists();
+    let launchd = launchd_state(label);
+    let answer = daemon::status(socket);
+
+    if json_out {
+        let report = json!({
+            "label": label,
+            "plist": path,
+            "installed": installed,
+            "loaded": launchd.is_some(),
+            "state": launchd.as_ref().map(|l| &l.0),
+            "pid": launchd.as_ref().and_then(|l| l.1),
+            "socket": socket,
+            "daemon": answer,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("Label     : {}", label);
+    println!(
+        "Plist     : {} ({})",
+        path.display(),
+        if installed {
+            "installed"
+        } else {
+            "not installed"
+        }
+    );
+    match &launchd {
+        Some((state, Some(pid))) => println!("launchd   : {}, pid {}", state, pid),
+        Some((state, None)) => println!("launchd   : {}", state),
+        None => println!("launchd   : not loaded"),
+    }
+    match &answer {
+        Some(s) => println!(
+            "Socket    : {} (answering, up {} s, {} snapshots every {} ms)",
+            socket.display(),
+            s["uptime_s"],
+            s["history"],
+            s["interval_ms"]
+        ),
+        None => println!("Socket    : {} (not answering)", socket.display()),
+    }
+    Ok(())
+}
@@ -3,98 +3,87 @@
  * apple-smi: Apple Silicon System Management Interface
  * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
  * smc.rs
- * Read SMC data via IOKit FFI.
+ * Read SMC data via IOKit FFI, or from a simulated SMC.
 */
-use anyhow::{Result, anyhow};
-use libc::{c_char, c_void};
+use crate::cache;
+use crate::error::SmcError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
-use std::ffi::{CStr, CString};
-use std::mem::size_of;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct FanReading {
     pub index: u8,
     pub rpm: f32,
-    pub key: String,      // e.g. "F0Ac"
-    pub encoding: String, // e.g. "fpe2"
+    pub key: String,             // e.g. "F0Ac"
+    pub encoding: String,        // e.g. "fpe2"
+    pub min_rpm: Option<f32>,    // F?Mn
+    pub max_rpm: Option<f32>,    // F?Mx
+    pub target_rpm: Option<f32>, // F?Tg
+    pub mode: Option<u8>,        // F?Md, 0 = auto, 1 = manual
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct SmcSnapshot {
-    pub gpu_temp_avg: Option<f32>,
-    pub fans: Vec<FanReading>,
-}
-
-// -------------------- IOKit / SMC FFI --------------------
-
-#[link(name = "IOKit", kind = "framework")]
-unsafe extern "C" {
-    fn mach_task_self() -> u32;
-
-    fn IOServiceMatching(name: *const c_char) -> *mut c_void;
-    fn IOServiceGetMatchingServices(master: u32, matching: *mut c_void, iter: *mut u32) -> i32;
-    fn IOIteratorNext(iter: u32) -> u32;
-    fn IORegistryEntryGetName(entry: u32, name: *mut c_char) -> i32;
-    fn IOObjectRelease(obj: u32) -> i32;
-
-    fn IOServiceOpen(service: u32, owning_task: u32, r#type: u32, connect: *mut u32) -> i32;
-    fn IOServiceClose(connect: u32) -> i32;
+impl FanReading {
+    /// Current speed as percent of max, which is what nvidia-smi's Fan column means.
+    pub fn percent(&self) -> Option<f32> {
+        match self.max_rpm {
+            Some(max) if max > 0.0 => Some((self.rpm / max * 100.0).clamp(0.0, 100.0)),
+            _ => None,
+        }
+    }
 
-    fn IOConnectCallStructMethod(
-        conn: u32,
-        selector: u32,
-        input: *const c_void,
-        input_cnt: usize,
-        output: *mut c_void,
-        output_cnt: *mut usize,
-    ) -> i32;
+    pub fn mode_label(&self) -> &'static str {
+        match self.mode {
+            Some(0) => "Auto",
+            Some(1) => "Manual",
+            Some(_) => "Unknown",
+            None => "N/A",
+        }
+    }
 }
 
-struct IOServiceIter {
-    iter: u32,
+/// Whole-machine power from SMC. Watts unless noted.
+#[derive(Debug, Default, Clone)]
+pub struct SystemPower {
+    pub system_total: Option<f32>,       // PSTR
+    pub dc_in: Option<f32>,              // PDTR
+    pub battery: Option<f32>,            // PPBR
+    pub battery_current_ma: Option<f32>, // B0AC, negative while discharging
+    pub battery_voltage_mv: Option<f32>, // B0AV
 }
-impl IOServiceIter {
-    fn new(class_name: &str) -> Result<Self> {
-        let cname = CString::new(class_name)?;
-        let matching = unsafe { IOServiceMatching(cname.as_ptr()) };
-        if matching.is_null() {
-            return Err(anyhow!("IOServiceMatching returned null"));
-        }
 
-        let mut iter: u32 = 0;
-        let kr = unsafe { IOServiceGetMatchingServices(0, matching, &mut iter) };
-        if kr != 0 {
-            return Err(anyhow!("IOServiceGetMatchingServices: {}", kr));
-        }
-        Ok(Self { iter })
+impl SystemPower {
+    /// Battery charge (+) / discharge (-) rate, from current and voltage.
+    pub fn battery_rate(&self) -> Option<f32> {
+        Some(self.battery_current_ma? * self.battery_voltage_mv? / 1e6)
     }
 }
-impl Iterator for IOServiceIter {
-    type Item = (u32, String); // (io_service_t, name)
-    fn next(&mut self) -> Option<Self::Item> {
-        let obj = unsafe { IOIteratorNext(self.iter) };
-        if obj == 0 {
-            return None;
-        }
-        let mut buf = [0 as c_char; 128];
-        let kr = unsafe { IORegistryEntryGetName(obj, buf.as_mut_ptr()) };
-        let name = if kr == 0 {
-            unsafe { CStr::from_ptr(buf.as_ptr()) }
-                .to_string_lossy()
-                .to_string()
-        } else {
-            String::new()
-        };
-        Some((obj, name))
-    }
+
+#[derive(Debug, Default, Clone)]
+pub struct SmcSnapshot {
+    pub gpu_temp_avg: Option<f32>,
+    pub fans: Vec<FanReading>,
+    pub power: SystemPower,
 }
-impl Drop for IOServiceIter {
-    fn drop(&mut self) {
-        unsafe { IOObjectRelease(self.iter) };
+
+impl SmcSnapshot {
+    /// Average percent-of-max over all fans that report a max speed.
+    pub fn fan_percent(&self) -> Option<f32> {
+        avg(&self
+            .fans
+            .iter()
+            .filter_map(|f| f.percent())
+            .collect::<Vec<_>>())
     }
 }
 
+#[cfg(target_os = "macos")]
+mod iokit;
+#[cfg(target_os = "macos")]
+pub use iokit::IoKitConn;
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct KeyDataVer {
@@ -116,7 +105,7 @@ pub struct PLimitData {
 }
 
 #[repr(C)]
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct KeyInfo {
     pub data_size: u32,
     pub data_type: u32, // FourCC
@@ -145,81 +134,99 @@ pub struct SensorVal {
     pub data: Vec<u8>,
 }
 
+/// One AppleSMC struct method call. Implemented by the real IOKit connection
+/// and by `smcsim::SimSmc`, so everything above this layer runs off-Mac too.
+/// Implementations return the raw output and leave `result` checking to `SMC`.
+pub trait SmcConn {
+    fn call_raw(&self, input: &KeyData) -> Result<KeyData, SmcError>;
+}
+
+// SMC result codes (data "result" byte), shared with the simulator
+pub const SMC_OK: u8 = 0;
+pub const SMC_KEY_NOT_FOUND: u8 = 132;
+pub const SMC_KEY_NOT_READABLE: u8 = 133;
+pub const SMC_KEY_NOT_WRITABLE: u8 = 134;
+pub const SMC_KEY_SIZE_MISMATCH: u8 = 135;
+pub const SMC_BAD_COMMAND: u8 = 130;
+pub const SMC_INDEX_RANGE: u8 = 184;
+
+// data8 commands
+pub const CMD_READ: u8 = 5;
+pub const CMD_WRITE: u8 = 6;
+pub const CMD_KEY_BY_INDEX: u8 = 8;
+pub const CMD_KEY_INFO: u8 = 9;
+
+// With the `sim` feature, APPLE_SMI_SMC_DUMP points `SMC::new` at a `smc dump` file.
+#[cfg(feature = "sim")]
+pub const SIM_ENV: &str = "APPLE_SMI_SMC_DUMP";
+
 #[allow(clippy::upper_case_acronyms)]
 pub struct SMC {
-    conn: u32,
+    conn: Box<dyn SmcConn>,
     cache: HashMap<u32, KeyInfo>,
+    // Machine id and dir of the on-disk key cache, None disables it (e.g. for the simulator)
+    key_cache: Option<(String, PathBuf)>,
 }
 
 impl SMC {
     pub fn new() -> Result<Self> {
-        let mut conn = 0u32;
-
-        // Same idea as macmon: match "AppleSMC" and open the "AppleSMCKeysEndpoint" entry.
-        for (service, name) in IOServiceIter::new("AppleSMC")? {
-            if name == "AppleSMCKeysEndpoint" {
-                let kr = unsafe { IOServiceOpen(service, mach_task_self(), 0, &mut conn) };
-                unsafe { IOObjectRelease(service) };
-                if kr != 0 {
-                    return Err(anyhow!("IOServiceOpen: {}", kr));
-                }
-                break;
-            } else {
-                unsafe { IOObjectRelease(service) };
-            }
+        // Release builds never swap the real SMC for a file behind root's back
+        #[cfg(feature = "sim")]
+        if let Some(path) = std::env::var_os(SIM_ENV) {
+            return Self::from_dump(std::path::Path::new(&path));
         }
+        Self::open()
+    }
 
-        if conn == 0 {
-            return Err(anyhow!("AppleSMCKeysEndpoint not found / open failed"));
-        }
+    /// The simulator, loaded from a `smc dump` file.
+    pub fn from_dump(path: &std::path::Path) -> Result<Self> {
+        Ok(Self::with_conn(Box::new(crate::smcsim::SimSmc::load(path)?)))
+    }
 
-        Ok(Self {
-            conn,
-            cache: HashMap::new(),
-        })
+    #[cfg(target_os = "macos")]
+    fn open() -> Result<Self> {
+        let mut smc = Self::with_conn(Box::new(IoKitConn::open()?));
+        smc.key_cache = cache::machine_id().map(|id| (id, cache::cache_dir()));
+        Ok(smc)
     }
 
-    fn read_call(&self, input: &KeyData) -> Result<KeyData> {
-        let mut out = KeyData::default();
-        let mut out_len = size_of::<KeyData>();
-
-        // selector=2 is what macmon uses for AppleSMCKeysEndpoint struct method calls.
-        let kr = unsafe {
-            IOConnectCallStructMethod(
-                self.conn,
-                2,
-                input as *const _ as *const c_void,
-                size_of::<KeyData>(),
-                &mut out as *mut _ as *mut c_void,
-                &mut out_len,
-            )
-        };
+    // No AppleSMC off-Mac, only the simulator
+    #[cfg(not(target_os = "macos"))]
+    fn open() -> Result<Self> {
+        Err(SmcError::ServiceNotFound.into())
+    }
 
-        if kr != 0 {
-            return Err(anyhow!("IOConnectCallStructMethod: {}", kr));
-        }
-        if out.result == 132 {
-            return Err(anyhow!("SMC key not found"));
+    pub fn with_conn(conn: Box<dyn SmcConn>) -> Self {
+        Self {
+            conn,
+            cache: HashMap::new(),
+            key_cache: None,
         }
-        if out.result != 0 {
-            return Err(anyhow!("SMC error: {}", out.result));
+    }
+
+    fn call(&self, input: &KeyData) -> Result<KeyData, SmcError> {
+        let out = self.conn.call_raw(input)?;
+        let key = || fourcc_u32_to_str(input.key);
+        match out.result {
+            SMC_OK => Ok(out),
+            SMC_KEY_NOT_FOUND => Err(SmcError::KeyNotFound(key())),
+            code => Err(SmcError::Result { key: key(), code }),
         }
-        Ok(out)
     }
 
-    pub fn key_by_index(&self, index: u32) -> Result<String> {
+    pub fn key_by_index(&self, index: u32) -> Result<String, SmcError> {
         let indata = KeyData {
-            data8: 8,
+            data8: CMD_KEY_BY_INDEX,
             data32: index,
             ..Default::default()
         };
-        let out = self.read_call(&indata)?;
-        Ok(std::str::from_utf8(&out.key.to_be_bytes())?.to_string())
+        let out = self.call(&indata)?;
+        Ok(fourcc_u32_to_str(out.key))
     }
 
-    pub fn read_key_info(&mut self, key: &str) -> Result<KeyInfo> {
+    pub fn read_key_info(&mut self, key: &str) -> Result<KeyInfo, SmcError> {
         if key.len() != 4 {
-            return Err(anyhow!("SMC key must be 4 chars"));
+            return Err(SmcError::BadKey(key.to_string()));
         }
         let k = fourcc_str_to_u32(key);
         if let Some(ki) = self.cache.get(&k) {
@@ -227,29 +234,37 @@ impl SMC {
         }
 
         let indata = KeyData {
-            data8: 9,
+            data8: CMD_KEY_INFO,
             key: k,
             ..Default::default()
         };
-        let out = self.read_call(&indata)?;
+        let out = self.call(&indata)?;
         self.cache.insert(k, out.key_info);
         Ok(out.key_info)
     }
 
-    pub fn read_val(&mut self, key: &str) -> Result<SensorVal> {
+    // Writes size their buffer from KeyInfo, so never trust a cached one there
+    fn live_key_info(&mut self, key: &str) -> Result<KeyInfo, SmcError> {
+        if key.len() == 4 {
+            self.cache.remove(&fourcc_str_to_u32(key));
+        }
+        self.read_key_info(key)
+    }
+
+    pub fn read_val(&mut self, key: &str) -> Result<SensorVal, SmcError> {
         let name = key.to_string();
         let key_info = self.read_key_info(key)?;
         let k = fourcc_str_to_u32(key);
 
         let indata = KeyData {
-            data8: 5,
+            data8: CMD_READ,
             key: k,
             key_info,
             ..Default::default()
         };
-        let out = self.read_call(&indata)?;
+        let out = self.call(&indata)?;
 
-        let unit = std::str::from_utf8(&key_info.data_type.to_be_bytes())?.to_string();
+        let unit = fourcc_u32_to_str(key_info.data_type);
         let n = key_info.data_size as usize;
         Ok(SensorVal {
             name,
@@ -258,9 +273,87 @@ impl SMC {
         })
     }
 
-    pub fn read_all_keys(&mut self) -> Result<Vec<String>> {
+    /// Write raw bytes to a key. `data` must match the key's size exactly.
+    pub fn write_val(&mut self, key: &str, data: &[u8]) -> Result<(), SmcError> {
+        let key_info = self.live_key_info(key)?;
+        if data.len() != key_info.data_size as usize || data.len() > 32 {
+            return Err(SmcError::WrongSize {
+                key: key.to_string(),
+                expected: key_info.data_size,
+                got: data.len(),
+            });
+        }
+        let mut bytes = [0u8; 32];
+        bytes[..data.len()].copy_from_slice(data);
+
+        let indata = KeyData {
+            data8: CMD_WRITE,
+            key: fourcc_str_to_u32(key),
+            key_info,
+            bytes,
+            ..Default::default()
+        };
+        self.call(&indata)?;
+        Ok(())
+    }
+
+    /// Write a number using the key's own encoding (see `encode_numeric`).
+    pub fn write_num(&mut self, key: &str, value: f32) -> Result<(), SmcError> {
+        let ki = self.live_key_info(key)?;
+        let unit = fourcc_u32_to_str(ki.data_type);
+        let data = encode_numeric(&unit, value).ok_or_else(|| SmcError::Encode {
+            key: key.to_string(),
+            unit: unit.clone(),
+        })?;
+        self.write_val(key, &data)
+    }
+
+    pub fn key_count(&mut self) -> Result<u32, SmcError> {
         let val = self.read_val("#KEY")?;
-        let count = u32::from_be_bytes(val.data[0..4].try_into()?);
+        let raw: [u8; 4] =
+            val.data
+                .get(0..4)
+                .and_then(|b| b.try_into().ok())
+                .ok_or(SmcError::WrongSize {
+                    key: String::from("#KEY"),
+                    expected: 4,
+                    got: val.data.len(),
+                })?;
+        Ok(u32::from_be_bytes(raw))
+    }
+
+    /// Read everything we know about one key: info, raw bytes and a decoded value.
+    /// Keys whose value can't be read (e.g. write-only) still return their info.
+    pub fn read_entry(&mut self, key: &str, with_value: bool) -> Result<KeyEntry, SmcError> {
+        let ki = self.read_key_info(key)?;
+        let data_type = fourcc_u32_to_str(ki.data_type);
+        let (bytes, value) = match with_value.then(|| self.read_val(key)) {
+            Some(Ok(v)) => {
+                let value = decode_display(&v);
+                (Some(v.data), value)
+            }
+            _ => (None, None),
+        };
+        Ok(KeyEntry {
+            key: key.to_string(),
+            data_type,
+            data_size: ki.data_size,
+            attributes: ki.data_attributes,
+            bytes,
+            value,
+        })
+    }
+
+    /// Enumerate every key name the SMC reports via `#KEY` + key-by-index.
+    pub fn list_keys(&mut self) -> Result<Vec<String>, SmcError> {
+        let count = self.key_count()?;
+        Ok((0..count)
+            .filter_map(|i| self.key_by_index(i).ok())
+            .collect())
+    }
+
+    pub fn read_all_keys(&mut self) -> Result<Vec<String>, SmcError> {
+        let count = self.key_count()?;
 
         let mut keys = Vec::with_capacity(count as usize);
         for i in 0..count {
@@ -274,11 +367,98 @@ impl SMC {
         }
         Ok(keys)
     }
+
+    /*
+     * Same key list as read_all_keys, but served from the on-disk cache when the
+     * machine model, OS build and #KEY count all match. A hit costs one SMC call
+     * and also seeds the key-info cache, so callers only pay for values they read.
+     */
+    pub fn known_keys(&mut self) -> Result<Vec<String>, SmcError> {
+        let count = self.key_count()?;
+        let (id, dir) = match &self.key_cache {
+            Some((id, dir)) => (id.clone(), dir.clone()),
+            None => return self.read_all_keys(),
+        };
+
+        if let Some(c) = cache::load_from::<KeyCache>(&dir, KEY_CACHE_FILE)
+            && c.machine == id
+            && c.key_count == count
+        {
+            let mut keys = Vec::with_capacity(c.keys.len());
+            for k in c.keys {
+                self.cache.insert(fourcc_str_to_u32(&k.key), k.info);
+                keys.push(k.key);
+            }
+            return Ok(keys);
+        }
+
+        let keys = self.read_all_keys()?;
+        let entry = KeyCache {
+            machine: id,
+            key_count: count,
+            keys: keys
+                .iter()
+                .filter_map(|k| {
+                    let info = *self.cache.get(&fourcc_str_to_u32(k))?;
+                    Some(CachedKey {
+                        key: k.clone(),
+                        info,
+                    })
+                })
+                .collect(),
+        };
+        // A read-only cache dir shouldn't break the snapshot
+        let _ = cache::store_in(&dir, KEY_CACHE_FILE, &entry);
+        Ok(keys)
+    }
+}
+
+const KEY_CACHE_FILE: &str = "smc-keys.json";
+
+#[derive(Serialize, Deserialize)]
+struct CachedKey {
+    key: String,
+    info: KeyInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyCache {
+    machine: String,
+    key_count: u32,
+    keys: Vec<CachedKey>,
 }
 
-impl Drop for SMC {
-    fn drop(&mut self) {
-        unsafe { IOServiceClose(self.conn) };
+/// One SMC key as shown by `apple-smi smc`.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyEntry {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub data_type: String, // FourCC, e.g. "flt "
+    #[serde(rename = "size")]
+    pub data_size: u32,
+    pub attributes: u8,
+    #[serde(serialize_with = "ser_hex")]
+    pub bytes: Option<Vec<u8>>,
+    pub value: Option<String>,
+}
+
+impl KeyEntry {
+    pub fn hex(&self) -> String {
+        match &self.bytes {
+            Some(b) => b
+                .iter()
+                .map(|x| format!("{:02x}", x))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => String::new(),
+        }
+    }
+}
+
+fn ser_hex<S: serde::Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+    match bytes {
+        Some(b) => s.serialize_str(&b.iter().map(|x| format!("{:02x}", x)).collect::<String>()),
+        None => s.serialize_none(),
     }
 }
 
@@ -288,11 +468,15 @@ fn fourcc_str_to_u32(s: &str) -> u32 {
     s.bytes().fold(0u32, |acc, b| (acc << 8) | (b as u32))
 }
 
+fn fourcc_u32_to_str(v: u32) -> String {
+    String::from_utf8_lossy(&v.to_be_bytes()).into_owned()
+}
+
 /// Try decode common SMC numeric encodings into f32.
 /// - "flt " : 4 bytes little-endian f32 (what macmon uses for temps on macOS 14+)
 /// - "fpe2" : 2 bytes big-endian fixed point (value = raw / 4.0) often used for fan RPM
 /// - "sp78" : 2 bytes big-endian signed fixed point (value = raw / 256.0) common temp encoding on older Macs
-fn decode_numeric(v: &SensorVal) -> Option<f32> {
+pub fn decode_numeric(v: &SensorVal) -> Option<f32> {
     match (v.unit.as_str(), v.data.as_slice()) {
         ("flt ", d) if d.len() >= 4 => {
             let raw: [u8; 4] = d[0..4].try_into().ok()?;
@@ -312,6 +496,58 @@ fn decode_numeric(v: &SensorVal) -> Option<f32> {
     }
 }
 
+/// Inverse of `decode_numeric`, plus the small unsigned ints used by mode/flag keys.
+pub fn encode_numeric(unit: &str, value: f32) -> Option<Vec<u8>> {
+    match unit {
+        "flt " => Some(value.to_le_bytes().to_vec()),
+        "fpe2" => Some(((value * 4.0).round() as u16).to_be_bytes().to_vec()),
+        "sp78" => Some(((value * 256.0).round() as i16).to_be_bytes().to_vec()),
+        "ui8 " | "flag" => Some(vec![value as u8]),
+        "ui16" => Some((value as u16).to_be_bytes().to_vec()),
+        "ui32" => Some((value as u32).to_be_bytes().to_vec()),
+        _ => None,
+    }
+}
+
+/// Best-effort human readable value for any key type we recognize.
+/// Integer types are big-endian like `#KEY`; floats follow `decode_numeric`.
+pub fn decode_display(v: &SensorVal) -> Option<String> {
+    if let Some(x) = decode_numeric(v) {
+        return Some(format!("{}", x));
+    }
+    match v.unit.as_str() {
+        "flag" => decode_integer(v).map(|x| (x != 0).to_string()),
+        "ch8*" => Some(
+            String::from_utf8_lossy(&v.data)
+                .trim_end_matches('\0')
+                .to_string(),
+        ),
+        _ => decode_integer(v).map(|x| x.to_string()),
+    }
+}
+
+/// Big-endian integer types ("ui8 ".."si64", "flag").
+pub fn decode_integer(v: &SensorVal) -> Option<i64> {
+    let d = v.data.as_slice();
+    let be = |n: usize| -> Option<u64> {
+        if d.len() < n {
+            return None;
+        }
+        Some(d[..n].iter().fold(0u64, |acc, b| (acc << 8) | (*b as u64)))
+    };
+    match v.unit.as_str() {
+        "ui8 " | "flag" => be(1).map(|x| x as i64),
+        "ui16" => be(2).map(|x| x as i64),
+        "ui32" => be(4).map(|x| x as i64),
+        "ui64" => be(8).map(|x| x as i64),
+        "si8 " => be(1).map(|x| x as u8 as i8 as i64),
+        "si16" => be(2).map(|x| x as u16 as i16 as i64),
+        "si32" => be(4).map(|x| x as u32 as i32 as i64),
+        "si64" => be(8).map(|x| x as i64),
+        _ => None,
+    }
+}
+
 fn avg(vals: &[f32]) -> Option<f32> {
     if vals.is_empty() {
         return None;
@@ -337,11 +573,56 @@ fn fan_index_from_key(k: &str) -> Option<u8> {
     }
 }
 
-/// Read CPU/GPU temps (Tp/Te/Tg) + fan RPM (F?Ac) via SMC.
+// Inverse of fan_index_from_key: build "F?xx" keys for a fan index.
+pub fn fan_key(idx: u8, suffix: &str) -> String {
+    let c = match idx {
+        0..=9 => (b'0' + idx) as char,
+        _ => (b'A' + idx - 10) as char,
+    };
+    format!("F{}{}", c, suffix)
+}
+
+/// Fill min/max/target/mode for a fan discovered from its F?Ac key.
+pub fn read_fan_details(smc: &mut SMC, fan: &mut FanReading) {
+    let mut num = |suffix: &str| {
+        smc.read_val(&fan_key(fan.index, suffix))
+            .ok()
+            .and_then(|v| decode_numeric(&v))
+    };
+    fan.min_rpm = num("Mn");
+    fan.max_rpm = num("Mx");
+    fan.target_rpm = num("Tg");
+    fan.mode = smc
+        .read_val(&fan_key(fan.index, "Md"))
+        .ok()
+        .and_then(|v| v.data.first().copied());
+}
+
+/// Power keys are fixed names, read them directly instead of scanning the key list.
+pub fn read_system_power(smc: &mut SMC) -> SystemPower {
+    let mut num = |key: &str| {
+        let v = smc.read_val(key).ok()?;
+        decode_numeric(&v).or_else(|| decode_integer(&v).map(|x| x as f32))
+    };
+    SystemPower {
+        system_total: num("PSTR"),
+        dc_in: num("PDTR"),
+        battery: num("PPBR"),
+        battery_current_ma: num("B0AC"),
+        battery_voltage_mv: num("B0AV"),
+    }
+}
+
+/// Read CPU/GPU temps (Tp/Te/Tg) + fan RPM (F?Ac) and per-fan min/max/target/mode via SMC.
 /// This is intentionally "multi-machine" friendly: it discovers keys at runtime instead of hardcoding a model list.
 pub fn read_smc_snapshot() -> Result<SmcSnapshot> {
     let mut smc = SMC::new()?;
-    let keys = smc.read_all_keys().unwrap_or_default();
+    read_snapshot_from(&mut smc)
+}
+
+/// Same as `read_smc_snapshot` on an already opened (real or simulated) SMC.
+pub fn read_snapshot_from(smc: &mut SMC) -> Result<SmcSnapshot> {
+    let keys = smc.known_keys().unwrap_or_default();
 
     // macmon filters temps by: data_size==4 && data_type=="flt " and key prefix Tp/Te/Tg
     // We'll keep that as the primary path, but decode a few common alternatives too.
@@ -364,6 +645,10 @@ pub fn read_smc_snapshot() -> Result<SmcSnapshot> {
                             rpm,
                             key: k.clone(),
                             encoding: v.unit.clone(),
+                            min_rpm: None,
+                            max_rpm: None,
+                            target_rpm: None,
+                            mode: None,
                         },
                     );
                 }
@@ -407,8 +692,187 @@ pub fn read_smc_snapshot() -> Result<SmcSnapshot> {
 
     let gpu_avg = avg(&gpu_temps);
 
+    // FNum is the authoritative fan count when present; drop stray F?Ac keys beyond it
+    if let Some(n) = smc
+        .read_val("FNum")
+        .ok()
+        .and_then(|v| v.data.first().copied())
+    {
+        fans_map.retain(|idx, _| *idx < n);
+    }
+    for fan in fans_map.values_mut() {
+        read_fan_details(smc, fan);
+    }
+
     Ok(SmcSnapshot {
         gpu_temp_avg: gpu_avg,
         fans: fans_map.into_values().collect(),
+        power: read_system_power(smc),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> SmcSnapshot {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/smc")
+            .join(name);
+        let mut smc = SMC::from_dump(&path).unwrap();
+        read_snapshot_from(&mut smc).unwrap()
+    }
+
+    const DUMP: &str = "\
+KEY   TYPE    SIZE  ATTR  BYTES  VALUE
+F0Ac  [fpe2]     2  0x80  0f a0  1000
+F0Tg  [flt ]     4  0xc0  00 00 7a 44  1000
+";
+
+    // A simulated SMC with the disk cache on, in a fresh dir per test
+    fn cached(test: &str) -> (SMC, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("apple-smi-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut smc = SMC::with_conn(Box::new(crate::smcsim::SimSmc::parse(DUMP).unwrap()));
+        smc.key_cache = Some((String::from("Mac16,10/25C56"), dir.clone()));
+        (smc, dir)
+    }
+
+    fn seed(dir: &std::path::Path, machine: &str, key_count: u32) {
+        let info = KeyInfo {
+            data_size: 1,
+            data_type: fourcc_str_to_u32("ui8 "),
+            data_attributes: 0xc0,
+        };
+        let entry = KeyCache {
+            machine: machine.to_string(),
+            key_count,
+            keys: vec![CachedKey {
+                key: String::from("F0Tg"),
+                info,
+            }],
+        };
+        cache::store_in(dir, KEY_CACHE_FILE, &entry).unwrap();
+    }
+
+    fn stored(dir: &std::path::Path) -> KeyCache {
+        cache::load_from(dir, KEY_CACHE_FILE).unwrap()
+    }
+
+    #[test]
+    fn key_cache_miss_scans_and_stores() {
+        let (mut smc, dir) = cached("miss");
+        assert_eq!(smc.known_keys().unwrap(), ["#KEY", "F0Ac", "F0Tg"]);
+        let c = stored(&dir);
+        assert_eq!((c.machine.as_str(), c.key_count), ("Mac16,10/25C56", 3));
+        assert_eq!(c.keys[1].key, "F0Ac");
+        assert_eq!(c.keys[1].info.data_size, 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn key_cache_hit_skips_the_scan() {
+        let (mut smc, dir) = cached("hit");
+        seed(&dir, "Mac16,10/25C56", 3);
+        assert_eq!(smc.known_keys().unwrap(), ["F0Tg"]);
+        // Reads go by the cached info, writes look the key up again
+        assert_eq!(smc.read_key_info("F0Tg").unwrap().data_size, 1);
+        smc.write_num("F0Tg", 1200.0).unwrap();
+        assert_eq!(smc.read_key_info("F0Tg").unwrap().data_size, 4);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn key_cache_rebuilt_for_other_machine_or_count() {
+        for (test, machine, count) in [
+            ("machine", "Mac14,9/25C56", 3),
+            ("count", "Mac16,10/25C56", 4),
+        ] {
+            let (mut smc, dir) = cached(test);
+            seed(&dir, machine, count);
+            assert_eq!(smc.known_keys().unwrap(), ["#KEY", "F0Ac", "F0Tg"], "{}", test);
+            let c = stored(&dir);
+            assert_eq!((c.machine.as_str(), c.key_count), ("Mac16,10/25C56", 3));
+            assert_eq!(c.keys.len(), 3);
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+
+    fn val(unit: &str, data: &[u8]) -> SensorVal {
+        SensorVal {
+            name: String::from("TEST"),
+            unit: unit.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn decodes_numeric_types() {
+        assert_eq!(
+            decode_numeric(&val("flt ", &42.5f32.to_le_bytes())),
+            Some(42.5)
+        );
+        assert_eq!(decode_numeric(&val("fpe2", &[0x0f, 0xa0])), Some(1000.0));
+        assert_eq!(decode_numeric(&val("sp78", &[0x1d, 0x80])), Some(29.5));
+        assert_eq!(decode_numeric(&val("flt ", &[0, 0])), None);
+        assert_eq!(decode_numeric(&val("ui8 ", &[1])), None);
+        assert_eq!(decode_integer(&val("si16", &[0xfa, 0x38])), Some(-1480));
+        assert_eq!(decode_integer(&val("ui32", &[0, 0, 0, 0x1e])), Some(30));
+        assert_eq!(decode_display(&val("flag", &[1])).as_deref(), Some("true"));
+        assert_eq!(
+            decode_display(&val("ch8*", b"M4\0\0")).as_deref(),
+            Some("M4")
+        );
+        for unit in ["flt ", "fpe2", "sp78"] {
+            let bytes = encode_numeric(unit, 37.75).unwrap();
+            assert_eq!(decode_numeric(&val(unit, &bytes)), Some(37.75), "{}", unit);
+        }
+    }
+
+    #[test]
+    fn fan_keys_round_trip() {
+        for idx in [0, 9, 10, 15] {
+            assert_eq!(fan_index_from_key(&fan_key(idx, "Ac")), Some(idx));
+        }
+        assert_eq!(fan_key(11, "Mx"), "FBMx");
+        assert_eq!(fan_index_from_key("F0Mx"), None);
+        assert_eq!(fan_index_from_key("FNum"), None);
+    }
+
+    #[test]
+    fn reads_one_fan_desktop() {
+        let snap = fixture("Mac16,10-M4.txt");
+        assert_eq!(snap.gpu_temp_avg, Some(37.90625));
+        assert_eq!(snap.fans.len(), 1);
+        let fan = &snap.fans[0];
+        assert_eq!((fan.index, fan.rpm, fan.key.as_str()), (0, 1002.0, "F0Ac"));
+        assert_eq!((fan.min_rpm, fan.max_rpm), (Some(1000.0), Some(4900.0)));
+        assert_eq!((fan.target_rpm, fan.mode), (Some(1002.0), Some(0)));
+        assert_eq!(snap.power.system_total, Some(9.8));
+        assert_eq!(snap.power.dc_in, Some(10.6));
+        assert_eq!(snap.power.battery, None);
+    }
+
+    #[test]
+    fn reads_two_fans_on_ac() {
+        let snap = fixture("Mac14,9-M2Pro.txt");
+        // No Tg keys on this one
+        assert_eq!(snap.gpu_temp_avg, None);
+        let maxes: Vec<_> = snap.fans.iter().map(|f| (f.index, f.max_rpm)).collect();
+        assert_eq!(maxes, [(0, Some(5779.0)), (1, Some(6241.0))]);
+        assert_eq!(snap.fan_percent(), Some(0.0));
+        assert_eq!(snap.power.battery, Some(3.1));
+        assert_eq!(snap.power.battery_current_ma, Some(212.0));
+        assert_eq!(snap.power.battery_voltage_mv, Some(13010.0));
+    }
+
+    #[test]
+    fn reads_fanless_on_battery() {
+        let snap = fixture("MacBookAir10,1-M1.txt");
+        assert!(snap.fans.is_empty());
+        assert_eq!(snap.fan_percent(), None);
+        assert_eq!(snap.gpu_temp_avg, Some(36.328125));
+        assert_eq!(snap.power.battery, Some(-6.2));
+        assert_eq!(snap.power.battery_current_ma, Some(-1480.0));
+    }
+}
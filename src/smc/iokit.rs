@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * smc/iokit.rs
+ * The AppleSMC IOKit connection, macOS only.
+ */
+use super::{KeyData, SmcConn};
+use crate::error::{KernReturn, SmcError};
+use libc::{c_char, c_void};
+use std::ffi::{CStr, CString};
+use std::mem::size_of;
+
+#[link(name = "IOKit", kind = "framework")]
+unsafe extern "C" {
+    fn mach_task_self() -> u32;
+
+    fn IOServiceMatching(name: *const c_char) -> *mut c_void;
+    fn IOServiceGetMatchingServices(master: u32, matching: *mut c_void, iter: *mut u32) -> i32;
+    fn IOIteratorNext(iter: u32) -> u32;
+    fn IORegistryEntryGetName(entry: u32, name: *mut c_char) -> i32;
+    fn IOObjectRelease(obj: u32) -> i32;
+
+    fn IOServiceOpen(service: u32, owning_task: u32, r#type: u32, connect: *mut u32) -> i32;
+    fn IOServiceClose(connect: u32) -> i32;
+
+    fn IOConnectCallStructMethod(
+        conn: u32,
+        selector: u32,
+        input: *const c_void,
+        input_cnt: usize,
+        output: *mut c_void,
+        output_cnt: *mut usize,
+    ) -> i32;
+}
+
+struct IOServiceIter {
+    iter: u32,
+}
+impl IOServiceIter {
+    fn new(class_name: &str) -> Result<Self, SmcError> {
+        let cname = CString::new(class_name).map_err(|_| SmcError::ServiceNotFound)?;
+        let matching = unsafe { IOServiceMatching(cname.as_ptr()) };
+        if matching.is_null() {
+            return Err(SmcError::ServiceNotFound);
+        }
+
+        let mut iter: u32 = 0;
+        let kr = unsafe { IOServiceGetMatchingServices(0, matching, &mut iter) };
+        if kr != 0 {
+            return Err(SmcError::Kern {
+                call: "IOServiceGetMatchingServices",
+                kr: KernReturn(kr),
+            });
+        }
+        Ok(Self { iter })
+    }
+}
+impl Iterator for IOServiceIter {
+    type Item = (u32, String); // (io_service_t, name)
+    fn next(&mut self) -> Option<Self::Item> {
+        let obj = unsafe { IOIteratorNext(self.iter) };
+        if obj == 0 {
+            return None;
+        }
+        let mut buf = [0 as c_char; 128];
+        let kr = unsafe { IORegistryEntryGetName(obj, buf.as_mut_ptr()) };
+        let name = if kr == 0 {
+            unsafe { CStr::from_ptr(buf.as_ptr()) }
+                .to_string_lossy()
+                .to_string()
+        } else {
+            String::new()
+        };
+        Some((obj, name))
+    }
+}
+impl Drop for IOServiceIter {
+    fn drop(&mut self) {
+        unsafe { IOObjectRelease(self.iter) };
+    }
+}
+
+/// The real "AppleSMCKeysEndpoint" connection.
+pub struct IoKitConn {
+    conn: u32,
+}
+
+impl IoKitConn {
+    pub fn open() -> Result<Self, SmcError> {
+        let mut conn = 0u32;
+
+        // Same idea as macmon: match "AppleSMC" and open the "AppleSMCKeysEndpoint" entry.
+        for (service, name) in IOServiceIter::new("AppleSMC")? {
+            if name == "AppleSMCKeysEndpoint" {
+                let kr = unsafe { IOServiceOpen(service, mach_task_self(), 0, &mut conn) };
+                unsafe { IOObjectRelease(service) };
+                if kr != 0 {
+                    return Err(SmcError::Kern {
+                        call: "IOServiceOpen",
+                        kr: KernReturn(kr),
+                    });
+                }
+                break;
+            } else {
+                unsafe { IOObjectRelease(service) };
+            }
+        }
+
+        if conn == 0 {
+            return Err(SmcError::ServiceNotFound);
+        }
+        Ok(Self { conn })
+    }
+}
+
+impl SmcConn for IoKitConn {
+    fn call_raw(&self, input: &KeyData) -> Result<KeyData, SmcError> {
+        let mut out = KeyData::default();
+        let mut out_len = size_of::<KeyData>();
+
+        // selector=2 is what macmon uses for AppleSMCKeysEndpoint struct method calls.
+        // Reads and writes share it, the command lives in data8 (5 = read, 6 = write).
+        let kr = unsafe {
+            IOConnectCallStructMethod(
+                self.conn,
+                2,
+                input as *const _ as *const c_void,
+                size_of::<KeyData>(),
+                &mut out as *mut _ as *mut c_void,
+                &mut out_len,
+            )
+        };
+
+        if kr != 0 {
+            return Err(SmcError::Kern {
+                call: "IOConnectCallStructMethod",
+                kr: KernReturn(kr),
+            });
+        }
+        Ok(out)
+    }
+}
+
+impl Drop for IoKitConn {
+    fn drop(&mut self) {
+        unsafe { IOServiceClose(self.conn) };
+    }
+}
@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * smcsim.rs
+ * In-memory SMC that answers struct calls from a `smc dump` file.
+ */
+use crate::error::{ParseError, SmcError};
+use crate::smc::{
+    CMD_KEY_BY_INDEX, CMD_KEY_INFO, CMD_READ, CMD_WRITE, KeyData, KeyInfo, SMC_BAD_COMMAND,
+    SMC_INDEX_RANGE, SMC_KEY_NOT_FOUND, SMC_KEY_NOT_READABLE, SMC_KEY_NOT_WRITABLE,
+    SMC_KEY_SIZE_MISMATCH, SmcConn,
+};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::path::Path;
+
+// SMC attribute bits
+const ATTR_READ: u8 = 0x80;
+const ATTR_WRITE: u8 = 0x40;
+
+#[derive(Debug, Clone)]
+pub struct SimKey {
+    pub key: u32,
+    pub info: KeyInfo,
+    // None for keys the dump couldn't read, those answer "not readable"
+    pub bytes: Option<Vec<u8>>,
+}
+
+pub struct SimSmc {
+    keys: RefCell<Vec<SimKey>>,
+}
+
+fn fourcc(s: &str) -> u32 {
+    s.bytes().fold(0u32, |acc, b| (acc << 8) | (b as u32))
+}
+
+fn is_fan_mode(key: u32) -> bool {
+    let b = key.to_be_bytes();
+    b[0] == b'F' && &b[2..] == b"Md"
+}
+
+impl SimSmc {
+    pub fn new(keys: Vec<SimKey>) -> Self {
+        Self {
+            keys: RefCell::new(keys),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading SMC dump {}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    /*
+     * Parse `apple-smi smc dump` text output:
+     * "TG0P  [flt ]     4  0x80  00 00 2a 42  42.5"
+     * The decoded value column is ignored, bytes are the source of truth.
+     */
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for (no, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with("KEY ") {
+                continue;
+            }
+            let err = || {
+                ParseError::new(
+                    "SMC dump",
+                    no + 1,
+                    line,
+                    "expected KEY [type] size attr bytes",
+                )
+            };
+            let key = line.get(0..4).ok_or_else(err)?;
+            let rest = &line[4..];
+            let open = rest.find('[').ok_or_else(err)?;
+            let close = open + rest[open..].find(']').ok_or_else(err)?;
+            let data_type = &rest[open + 1..close];
+            if data_type.len() != 4 {
+                return Err(err().into());
+            }
+
+            let mut tokens = rest[close + 1..].split_whitespace();
+            let size: u32 = tokens
+                .next()
+                .and_then(|t| t.parse().ok())
+                .filter(|n| *n <= 32)
+                .ok_or_else(err)?;
+            let attr = tokens
+                .next()
+                .and_then(|t| t.strip_prefix("0x"))
+                .and_then(|t| u8::from_str_radix(t, 16).ok())
+                .ok_or_else(err)?;
+
+            let mut bytes = Vec::with_capacity(size as usize);
+            let mut unreadable = false;
+            for _ in 0..size {
+                match tokens.next() {
+                    Some("-") => {
+                        unreadable = true;
+                        break;
+                    }
+                    Some(t) => bytes.push(u8::from_str_radix(t, 16).map_err(|_| err())?),
+                    None => return Err(err().into()),
+                }
+            }
+            // Zero sized keys print "-" too
+            if size == 0 && tokens.next() == Some("-") {
+                unreadable = true;
+            }
+
+            keys.push(SimKey {
+                key: fourcc(key),
+                info: KeyInfo {
+                    data_size: size,
+                    data_type: fourcc(data_type),
+                    data_attributes: attr,
+                },
+                bytes: (!unreadable).then_some(bytes),
+            });
+        }
+        // "#KEY" is always answered from the live key count, but keep its place in the index
+        if !keys.iter().any(|k| k.key == fourcc("#KEY")) {
+            keys.insert(
+                0,
+                SimKey {
+                    key: fourcc("#KEY"),
+                    info: KeyInfo {
+                        data_size: 4,
+                        data_type: fourcc("ui32"),
+                        data_attributes: ATTR_READ,
+                    },
+                    bytes: None,
+                },
+            );
+        }
+        Ok(Self::new(keys))
+    }
+
+    fn key_count(&self) -> u32 {
+        self.keys.borrow().len() as u32
+    }
+}
+
+impl SmcConn for SimSmc {
+    fn call_raw(&self, input: &KeyData) -> Result<KeyData, SmcError> {
+        let mut out = KeyData {
+            key: input.key,
+            ..Default::default()
+        };
+
+        if input.key == fourcc("#KEY") && input.data8 == CMD_READ {
+            out.bytes[..4].copy_from_slice(&self.key_count().to_be_bytes());
+            return Ok(out);
+        }
+
+        let mut keys = self.keys.borrow_mut();
+        // Like Apple Silicon firmware, F?Md only takes writes once Ftst is set
+        let locked = keys
+            .iter()
+            .find(|e| e.key == fourcc("Ftst"))
+            .is_some_and(|e| {
+                e.bytes
+                    .as_deref()
+                    .is_some_and(|b| b.iter().all(|&x| x == 0))
+            });
+        match input.data8 {
+            CMD_KEY_BY_INDEX => match keys.get(input.data32 as usize) {
+                Some(e) => out.key = e.key,
+                None => out.result = SMC_INDEX_RANGE,
+            },
+            CMD_KEY_INFO => match keys.iter().find(|e| e.key == input.key) {
+                Some(e) => out.key_info = e.info,
+                None => out.result = SMC_KEY_NOT_FOUND,
+            },
+            CMD_READ => match keys.iter().find(|e| e.key == input.key) {
+                Some(SimKey {
+                    info,
+                    bytes: Some(b),
+                    ..
+                }) if info.data_attributes & ATTR_READ != 0 => {
+                    out.key_info = *info;
+                    out.bytes[..b.len()].copy_from_slice(b);
+                }
+                Some(_) => out.result = SMC_KEY_NOT_READABLE,
+                None => out.result = SMC_KEY_NOT_FOUND,
+            },
+            CMD_WRITE => match keys.iter_mut().find(|e| e.key == input.key) {
+                Some(e) if e.info.data_attributes & ATTR_WRITE == 0 => {
+                    out.result = SMC_KEY_NOT_WRITABLE
+                }
+                Some(e) if input.key_info.data_size != e.info.data_size => {
+                    out.result = SMC_KEY_SIZE_MISMATCH
+                }
+                Some(e) if is_fan_mode(e.key) && locked => out.result = SMC_KEY_NOT_WRITABLE,
+                Some(e) => {
+                    let n = e.info.data_size as usize;
+                    e.bytes = Some(input.bytes[..n].to_vec());
+                }
+                None => out.result = SMC_KEY_NOT_FOUND,
+            },
+            _ => out.result = SMC_BAD_COMMAND,
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smc::SMC;
+
+    const DUMP: &str = "\
+KEY   TYPE    SIZE  ATTR  BYTES  VALUE
+F0Ac  [fpe2]     2  0x80  0f a0  1000
+F0Tg  [flt ]     4  0xc0  00 00 7a 44  1000
+Tg0X  [flt ]     4  0x00  -
+";
+
+    fn smc(text: &str) -> SMC {
+        SMC::with_conn(Box::new(SimSmc::parse(text).unwrap()))
+    }
+
+    #[test]
+    fn enumerates_keys_with_count_first() {
+        let mut smc = smc(DUMP);
+        assert_eq!(smc.key_count().unwrap(), 4);
+        assert_eq!(smc.list_keys().unwrap(), ["#KEY", "F0Ac", "F0Tg", "Tg0X"]);
+        // Unreadable keys are listed but skipped by read_all_keys
+        assert_eq!(smc.read_all_keys().unwrap(), ["#KEY", "F0Ac", "F0Tg"]);
+    }
+
+    #[test]
+    fn reads_and_writes_by_attributes() {
+        let mut smc = smc(DUMP);
+        assert_eq!(smc.read_val("F0Ac").unwrap().data, [0x0f, 0xa0]);
+        assert!(matches!(
+            smc.read_val("Tg0X"),
+            Err(SmcError::Result {
+                code: SMC_KEY_NOT_READABLE,
+                ..
+            })
+        ));
+        assert!(matches!(
+            smc.read_val("ZZZZ"),
+            Err(SmcError::KeyNotFound(_))
+        ));
+
+        smc.write_num("F0Tg", 2500.0).unwrap();
+        assert_eq!(smc.read_val("F0Tg").unwrap().data, 2500f32.to_le_bytes());
+        assert!(matches!(
+            smc.write_num("F0Ac", 2500.0),
+            Err(SmcError::Result {
+                code: SMC_KEY_NOT_WRITABLE,
+                ..
+            })
+        ));
+        assert!(matches!(
+            smc.write_val("F0Tg", &[0, 0]),
+            Err(SmcError::WrongSize { expected: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn fan_mode_is_locked_until_ftst() {
+        let mut smc = smc("\
+Ftst  [ui8 ]     1  0xc0  00  0
+F0Md  [ui8 ]     1  0xc0  00  0
+");
+        assert!(smc.write_num("F0Md", 1.0).is_err());
+        smc.write_num("Ftst", 1.0).unwrap();
+        smc.write_num("F0Md", 1.0).unwrap();
+        assert_eq!(smc.read_val("F0Md").unwrap().data, [1]);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for (text, line) in [
+            ("F0Ac  [fpe2]     2  0x80  0f\n", 1),
+            ("KEY   TYPE\nF0Ac  [fp]     2  0x80  0f a0\n", 2),
+            ("F0Ac  [fpe2]     2  80  0f a0\n", 1),
+            ("F0Ac  fpe2     2  0x80  0f a0\n", 1),
+        ] {
+            let e = SimSmc::parse(text).err().unwrap();
+            let e = e.downcast_ref::<ParseError>().unwrap();
+            assert_eq!(e.line, line, "{}", text);
+        }
+    }
+}
@@ -5,9 +5,16 @@
  * syspf.rs
  * Fetch data by running system_profiler output JSON and parse that.
  */
-use anyhow::{Context, Ok, Result};
-use serde::Deserialize;
+use crate::error::{CmdError, ParseError};
+use crate::utils::{CMD_TIMEOUT, output_timeout};
+use anyhow::{Ok, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const DISPLAYS: &str = "SPDisplaysDataType";
 
 #[derive(Deserialize)]
 pub struct Root {
@@ -24,10 +31,76 @@ pub struct GpuEntry {
     // pub sppci_model: String,
     #[serde(default)]
     pub sppci_bus: String,
-    // #[serde(default)]
-    // pub sppci_cores: String,
+    // Apple Silicon only, e.g. "10"
+    #[serde(default)]
+    pub sppci_cores: String,
     #[serde(default)]
     pub spdisplays_mtlgpufamilysupport: String,
+    // Displays driven by this GPU
+    #[serde(rename = "spdisplays_ndrvs", default)]
+    pub displays: Vec<Display>,
+}
+
+#[derive(Deserialize)]
+pub struct Display {
+    #[serde(rename = "_name", default)]
+    pub name: String,
+    // "3840 x 2160", the panel's native pixels
+    #[serde(rename = "_spdisplays_pixels", default)]
+    pub pixels: String,
+    // "1920 x 1080 @ 60.00Hz", what's in use (points on Retina)
+    #[serde(rename = "_spdisplays_resolution", default)]
+    pub resolution: String,
+    // The flags below are "spdisplays_yes" / "spdisplays_on" or absent
+    #[serde(rename = "spdisplays_main", default)]
+    pub main: String,
+    #[serde(rename = "spdisplays_mirror", default)]
+    pub mirror: String,
+    #[serde(rename = "spdisplays_online", default)]
+    pub online: String,
+    // "spdisplays_internal", "spdisplays_displayport", "spdisplays_hdmi", ...
+    #[serde(rename = "spdisplays_connection_type", default)]
+    pub connection_type: String,
+}
+
+impl Display {
+    pub fn is_main(&self) -> bool {
+        self.main == "spdisplays_yes"
+    }
+
+    pub fn is_mirror(&self) -> bool {
+        self.mirror == "spdisplays_on"
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online == "spdisplays_yes"
+    }
+
+    pub fn connection_label(&self) -> String {
+        match self.connection_type.strip_prefix("spdisplays_") {
+            Some("internal") => String::from("Internal"),
+            Some("displayport") => String::from("DisplayPort"),
+            Some("hdmi") => String::from("HDMI"),
+            Some(other) => other.to_string(),
+            None if self.connection_type.is_empty() => String::from("N/A"),
+            None => self.connection_type.clone(),
+        }
+    }
+
+    /// "1920 x 1080" out of "1920 x 1080 @ 60.00Hz"
+    pub fn resolution_label(&self) -> &str {
+        self.resolution.split('@').next().unwrap_or("").trim()
+    }
+
+    pub fn refresh_hz(&self) -> Option<f64> {
+        self.resolution
+            .split('@')
+            .nth(1)?
+            .trim()
+            .trim_end_matches("Hz")
+            .parse()
+            .ok()
+    }
 }
 
 impl GpuEntry {
@@ -38,6 +111,20 @@ impl GpuEntry {
         }
     }
 
+    /// nvidia-smi's Display Mode: a display is attached to this GPU.
+    pub fn display_mode(&self) -> bool {
+        !self.displays.is_empty()
+    }
+
+    /// nvidia-smi's Display Active: a display is initialized (online) on this GPU.
+    pub fn display_active(&self) -> bool {
+        self.displays.iter().any(|d| d.is_online())
+    }
+
+    pub fn core_count(&self) -> Option<u32> {
+        self.sppci_cores.trim().parse().ok()
+    }
+
     pub fn metal_lable(&self) -> &str {
         match self
             .spdisplays_mtlgpufamilysupport
@@ -53,6 +140,8 @@ impl GpuEntry {
 pub struct SysProf {
     #[serde(rename = "SPSoftwareDataType", default)]
     pub system: Vec<SysVersion>,
+    #[serde(rename = "SPHardwareDataType", default)]
+    pub hardware: Vec<Hardware>,
 }
 #[derive(Deserialize)]
 pub struct SysVersion {
@@ -69,29 +158,182 @@ impl SysVersion {
     }
 }
 
-pub fn run_syspf() -> Result<(String, String)> {
-    let gpu_out = Command::new("system_profiler")
-        .args(["-json", "SPDisplaysDataType"])
-        .output()
-        .context("is this macOS?")?;
-
-    let os_out = Command::new("system_profiler")
-        .args(["-json", "SPSoftwareDataType"])
-        .output()
-        .context("is this macOS?")?;
-
-    anyhow::ensure!(
-        gpu_out.status.success(),
-        "system_profiler exited with status {}",
-        gpu_out.status
-    );
-    anyhow::ensure!(
-        os_out.status.success(),
-        "system_profiler exited with status {}",
-        os_out.status
-    );
-
-    let s_gpu = String::from_utf8(gpu_out.stdout)?;
-    let s_os = String::from_utf8(os_out.stdout)?;
-    Ok((s_gpu, s_os))
+#[derive(Deserialize)]
+pub struct Hardware {
+    #[serde(default)]
+    pub chip_type: String, // e.g. "Apple M4 Pro"
+    #[serde(default)]
+    pub machine_model: String, // e.g. "Mac16,10"
+    #[serde(default)]
+    pub machine_name: String, // e.g. "Mac mini"
+    #[serde(default)]
+    pub physical_memory: String, // e.g. "16 GB"
+    // "proc 10:4:6", total:performance:efficiency
+    #[serde(default)]
+    pub number_processors: String,
+}
+
+impl Hardware {
+    /// (total, performance, efficiency) CPU cores.
+    pub fn cpu_cores(&self) -> (Option<u32>, Option<u32>, Option<u32>) {
+        let mut it = self
+            .number_processors
+            .trim_start_matches("proc ")
+            .split(':')
+            .map(|n| n.trim().parse().ok());
+        (
+            it.next().flatten(),
+            it.next().flatten(),
+            it.next().flatten(),
+        )
+    }
+
+    /// "Apple M4 (4P+6E)"
+    pub fn chip_label(&self) -> String {
+        match self.cpu_cores() {
+            (_, Some(p), Some(e)) => format!("{} ({}P+{}E)", self.chip_type, p, e),
+            _ => self.chip_type.clone(),
+        }
+    }
+
+    /// "16 GB" as MiB
+    pub fn memory_mib(&self) -> Option<u64> {
+        let mut it = self.physical_memory.split_whitespace();
+        let n: u64 = it.next()?.parse().ok()?;
+        match it.next()? {
+            "TB" => Some(n * 1024 * 1024),
+            "GB" => Some(n * 1024),
+            "MB" => Some(n),
+            _ => None,
+        }
+    }
+}
+
+const CACHE_NAME: &str = "syspf.json";
+static REFRESH: AtomicBool = AtomicBool::new(false);
+
+/// Ignore the cached system_profiler output for this run (`--refresh`).
+pub fn force_refresh() {
+    REFRESH.store(true, Ordering::Relaxed);
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyspfCache {
+    key: String,
+    json: Value,
+}
+
+/*
+ * GPU, OS and hardware data only change with a reboot (OS update, eGPU), so key
+ * the cache on model/OS build and boot session. None means we can't tell, then
+ * don't cache at all.
+ */
+fn cache_key() -> Option<String> {
+    let boot = crate::utils::sysctl_string("kern.bootsessionuuid")?;
+    Some(format!("{}/{}", crate::cache::machine_id()?, boot))
+}
+
+fn query_syspf(types: &[&str]) -> Result<Value> {
+    let out = output_timeout(
+        Command::new("system_profiler").arg("-json").args(types),
+        CMD_TIMEOUT,
+    )?;
+    if !out.status.success() {
+        return Err(CmdError::Exit {
+            program: String::from("system_profiler"),
+            status: out.status,
+        }
+        .into());
+    }
+
+    let json = String::from_utf8(out.stdout)?;
+    Ok(serde_json::from_str(&json)
+        .map_err(|e| ParseError::json("system_profiler JSON", &json, &e))?)
+}
+
+// Displays come and go, and the serial number has no business on disk
+fn strip_live(json: &mut Value) {
+    let gpus = json.get_mut(DISPLAYS).and_then(Value::as_array_mut);
+    for gpu in gpus.into_iter().flatten() {
+        if let Some(gpu) = gpu.as_object_mut() {
+            gpu.remove("spdisplays_ndrvs");
+        }
+    }
+    let hardware = json
+        .get_mut("SPHardwareDataType")
+        .and_then(Value::as_array_mut);
+    for hw in hardware.into_iter().flatten() {
+        if let Some(hw) = hw.as_object_mut() {
+            hw.remove("serial_number");
+        }
+    }
+}
+
+/// Parse system_profiler JSON into `Root` or `SysProf`.
+pub fn parse<T: DeserializeOwned>(json: &str) -> Result<T, ParseError> {
+    serde_json::from_str(json).map_err(|e| ParseError::json("system_profiler JSON", json, &e))
+}
+
+/*
+ * system_profiler JSON for GPUs, software and hardware. Only the static part is
+ * cached; with `displays` the display list is queried live on a cache hit.
+ */
+pub fn run_syspf(displays: bool) -> Result<String> {
+    let key = cache_key();
+    let cached = (!REFRESH.load(Ordering::Relaxed))
+        .then(|| crate::cache::load::<SyspfCache>(CACHE_NAME))
+        .flatten()
+        .filter(|c| key.as_deref() == Some(c.key.as_str()));
+    let json = match cached {
+        Some(mut c) => {
+            if displays {
+                // SPDisplaysDataType alone, its GPU entries carry the displays
+                let live = query_syspf(&[DISPLAYS])?;
+                c.json[DISPLAYS] = live[DISPLAYS].clone();
+            }
+            c.json
+        }
+        None => {
+            // One run for all data types, system_profiler's startup is most of its cost
+            let json = query_syspf(&[DISPLAYS, "SPSoftwareDataType", "SPHardwareDataType"])?;
+            if let Some(key) = key {
+                let mut entry = SyspfCache {
+                    key,
+                    json: json.clone(),
+                };
+                strip_live(&mut entry.json);
+                // A read-only cache dir only costs speed
+                let _ = crate::cache::store(CACHE_NAME, &entry);
+            }
+            json
+        }
+    };
+    // Root and SysProf each pick their own keys out of the same object
+    Ok(json.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_leaves_out_displays_and_serial() {
+        let mut json: Value = serde_json::from_str(
+            r#"{
+  "SPDisplaysDataType": [{"_name": "Apple M4", "sppci_cores": "10",
+    "spdisplays_ndrvs": [{"_name": "Color LCD", "spdisplays_online": "spdisplays_yes"}]}],
+  "SPHardwareDataType": [{"machine_model": "Mac16,10", "serial_number": "C02XX0TESTXX"}]
+}"#,
+        )
+        .unwrap();
+        strip_live(&mut json);
+        let text = json.to_string();
+        assert!(!text.contains("Color LCD") && !text.contains("C02XX0TESTXX"));
+
+        let root: Root = parse(&text).unwrap();
+        assert_eq!(root.gpus[0].core_count(), Some(10));
+        assert!(root.gpus[0].displays.is_empty());
+        let sys: SysProf = parse(&text).unwrap();
+        assert_eq!(sys.hardware[0].machine_model, "Mac16,10");
+    }
 }
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: MIT
+/*
+ * apple-smi: Apple Silicon System Management Interface
+ * Copyright (C) 2026 zlicdt@ReSpringClipsNeko
+ * thermal.rs
+ * Thermal pressure, Low Power Mode and power source, i.e. why clocks drop.
+ */
+use crate::utils::{CMD_TIMEOUT, output_timeout};
+use anyhow::{Result, anyhow};
+#[cfg(target_os = "macos")]
+use libc::{c_char, c_int};
+#[cfg(target_os = "macos")]
+use std::ffi::CString;
+use std::process::Command;
+
+// libnotify, part of libSystem
+#[cfg(target_os = "macos")]
+unsafe extern "C" {
+    fn notify_register_check(name: *const c_char, out_token: *mut c_int) -> u32;
+    fn notify_get_state(token: c_int, state64: *mut u64) -> u32;
+    fn notify_cancel(token: c_int) -> u32;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThermalPressure {
+    Nominal,
+    Moderate,
+    Heavy,
+    Trapping,
+    Sleeping,
+}
+
+impl ThermalPressure {
+    // Values of kOSThermalNotificationPressureLevelName's state
+    fn from_level(level: u64) -> Option<Self> {
+        match level {
+            0 => Some(Self::Nominal),
+            1 => Some(Self::Moderate),
+            2 => Some(Self::Heavy),
+            3 => Some(Self::Trapping),
+            4 => Some(Self::Sleeping),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Nominal => "Nominal",
+            Self::Moderate => "Moderate",
+            Self::Heavy => "Heavy",
+            Self::Trapping => "Trapping",
+            Self::Sleeping => "Sleeping",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ThermalState {
+    pub pressure: Option<ThermalPressure>,
+    pub low_power_mode: Option<bool>,
+    pub power_source: Option<String>, // e.g. "AC Power", "Battery Power"
+}
+
+pub fn thermal_pressure() -> Result<ThermalPressure> {
+    let level = notify_state("com.apple.system.thermalpressurelevel")?;
+    ThermalPressure::from_level(level).ok_or_else(|| anyhow!("unknown thermal level {}", level))
+}
+
+#[cfg(target_os = "macos")]
+fn notify_state(name: &str) -> Result<u64> {
+    let name = CString::new(name)?;
+    let mut token: c_int = 0;
+    let status = unsafe { notify_register_check(name.as_ptr(), &mut token) };
+    if status != 0 {
+        return Err(anyhow!("notify_register_check: {}", status));
+    }
+    let mut level: u64 = 0;
+    let status = unsafe { notify_get_state(token, &mut level) };
+    unsafe { notify_cancel(token) };
+    if status != 0 {
+        return Err(anyhow!("notify_get_state: {}", status));
+    }
+    Ok(level)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn notify_state(_name: &str) -> Result<u64> {
+    Err(anyhow!("notifyd is macOS only"))
+}
+
+/// Low Power Mode from `pmset -g` and the current source from `pmset -g ps`.
+pub fn run_pmset() -> Result<(Option<bool>, Option<String>)> {
+    let out = output_timeout(Command::new("pmset").arg("-g"), CMD_TIMEOUT)?;
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    // " lowpowermode         1", only listed for the active power source
+    let low_power_mode = stdout.lines().find_map(|l| {
+        let mut it = l.split_whitespace();
+        match (it.next(), it.next()) {
+            (Some("lowpowermode"), Some(v)) => Some(v == "1"),
+            _ => None,
+        }
+    });
+
+    let out = output_timeout(Command::new("pmset").args(["-g", "ps"]), CMD_TIMEOUT)?;
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    // "Now drawing from 'AC Power'"
+    let power_source = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("Now drawing from '"))
+        .and_then(|rest| rest.split('\'').next())
+        .map(|s| s.to_string());
+
+    Ok((low_power_mode, power_source))
+}
+
+pub fn read_thermal_state() -> ThermalState {
+    let (low_power_mode, power_source) = run_pmset().unwrap_or((None, None));
+    ThermalState {
+        pressure: thermal_pressure().ok(),
+        low_power_mode,
+        power_source,
+    }
+}
+
+/*
+ * nvidia-smi "Clocks Event Reasons" mapped onto what macOS tells us:
+ * - Idle: GPU utilization (powermetrics, ioreg or Metal) below 1%
+ * - SW Power Cap: Low Power Mode
+ * - SW Thermal Slowdown: thermal pressure Moderate or worse
+ * - HW Thermal Slowdown: thermal pressure Heavy or worse
+ * None means the source wasn't available.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct EventReasons {
+    pub gpu_idle: Option<bool>,
+    pub sw_power_cap: Option<bool>,
+    pub sw_thermal_slowdown: Option<bool>,
+    pub hw_thermal_slowdown: Option<bool>,
+}
+
+impl EventReasons {
+    pub fn from_state(state: &ThermalState, gpu_util: Option<f64>) -> Self {
+        Self {
+            gpu_idle: gpu_util.map(|r| r < 1.0),
+            sw_power_cap: state.low_power_mode,
+            sw_thermal_slowdown: state.pressure.map(|p| p >= ThermalPressure::Moderate),
+            hw_thermal_slowdown: state.pressure.map(|p| p >= ThermalPressure::Heavy),
+        }
+    }
+
+    /// Same bit layout as nvidia-smi's clocks_event_reasons.active.
+    pub fn active_mask(&self) -> u64 {
+        let bit = |v: Option<bool>, b: u64| if v == Some(true) { b } else { 0 };
+        bit(self.gpu_idle, 0x1)
+            | bit(self.sw_power_cap, 0x4)
+            | bit(self.sw_thermal_slowdown, 0x20)
+            | bit(self.hw_thermal_slowdown, 0x40)
+    }
+}
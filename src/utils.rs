@@ -5,6 +5,15 @@
  * utils.rs
  * Some magics.
  */
+use crate::error::CmdError;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 pub fn project_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
@@ -12,3 +21,268 @@ pub fn project_version() -> &'static str {
 pub fn is_root() -> bool {
     unsafe { libc::geteuid() == 0 }
 }
+
+/// Read a string sysctl such as "hw.model" or "kern.osversion".
+#[cfg(target_os = "macos")]
+pub fn sysctl_string(name: &str) -> Option<String> {
+    let cname = std::ffi::CString::new(name).ok()?;
+    let mut len: libc::size_t = 0;
+    let kr = unsafe {
+        libc::sysctlbyname(
+            cname.as_ptr(),
+            std::ptr::null_mut(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if kr != 0 || len == 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; len];
+    let kr = unsafe {
+        libc::sysctlbyname(
+            cname.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if kr != 0 {
+        return None;
+    }
+    buf.truncate(len);
+    Some(
+        String::from_utf8_lossy(&buf)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn sysctl_string(_name: &str) -> Option<String> {
+    None
+}
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_stop_signal(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Route SIGINT/SIGTERM/SIGHUP into a flag so callers can clean up instead of dying.
+pub fn install_stop_handler() {
+    let handler = on_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+}
+
+/// Stop loops polling `stop_requested`, as if a signal came in.
+pub fn request_stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+// No child process gets longer than this, whoever is waiting for it
+pub const CMD_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Default)]
+struct Children {
+    pids: Vec<u32>,
+    // Killed already, whatever starts now is killed right away
+    closed: bool,
+}
+
+/// Children started by one collection's source threads, so it can kill what it
+/// abandoned without touching anyone else's.
+#[derive(Clone, Default)]
+pub struct ChildGroup(Arc<Mutex<Children>>);
+
+thread_local! {
+    // The group output_timeout registers its child in, if any
+    static GROUP: RefCell<Option<ChildGroup>> = const { RefCell::new(None) };
+}
+
+impl ChildGroup {
+    /// Run `f` with the children output_timeout starts on this thread in this group.
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        GROUP.with(|g| *g.borrow_mut() = Some(self.clone()));
+        let out = f();
+        GROUP.with(|g| *g.borrow_mut() = None);
+        out
+    }
+
+    /// Kill the children still running, their waiters were abandoned.
+    pub fn kill_all(&self) {
+        if let Ok(mut c) = self.0.lock() {
+            c.closed = true;
+            for pid in c.pids.drain(..) {
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            }
+        }
+    }
+}
+
+fn track_child(pid: u32, running: bool) {
+    GROUP.with(|g| {
+        let Some(group) = &*g.borrow() else {
+            return;
+        };
+        let Ok(mut c) = group.0.lock() else {
+            return;
+        };
+        if !running {
+            c.pids.retain(|&p| p != pid);
+        } else if c.closed {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        } else {
+            c.pids.push(pid);
+        }
+    });
+}
+
+/// `Command::output` that kills the child when it runs past `timeout`.
+pub fn output_timeout(cmd: &mut Command, timeout: Duration) -> Result<Output, CmdError> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let mut child = match cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(source) => return Err(CmdError::Spawn { program, source }),
+    };
+    track_child(child.id(), true);
+    // Drain stdout on the side, a full pipe would block the child forever
+    let mut pipe = child.stdout.take();
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(p) = pipe.as_mut() {
+            let _ = p.read_to_end(&mut buf);
+        }
+        buf
+    });
+
+    let start = Instant::now();
+    loop {
+        let status = match child.try_wait() {
+            Ok(s) => s,
+            Err(source) => {
+                track_child(child.id(), false);
+                return Err(CmdError::Spawn { program, source });
+            }
+        };
+        if let Some(status) = status {
+            track_child(child.id(), false);
+            return Ok(Output {
+                status,
+                stdout: reader.join().unwrap_or_default(),
+                stderr: Vec::new(),
+            });
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            track_child(child.id(), false);
+            return Err(CmdError::Timeout { program, timeout });
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Shell-style glob match supporting `*` and `?`, e.g. `T*` or `F?Ac`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last '*' and the text index it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn kills_only_its_own_children() {
+        let run = |group: ChildGroup| {
+            thread::spawn(move || {
+                group.enter(|| {
+                    output_timeout(Command::new("sleep").arg("5"), Duration::from_secs(10))
+                })
+            })
+        };
+        let (a, b) = (ChildGroup::default(), ChildGroup::default());
+        let start = Instant::now();
+        let (ta, tb) = (run(a.clone()), run(b.clone()));
+        thread::sleep(Duration::from_millis(300));
+
+        b.kill_all();
+        assert!(!tb.join().unwrap().unwrap().status.success());
+        thread::sleep(Duration::from_millis(100));
+        assert!(!ta.is_finished(), "a's child died with b's");
+
+        a.kill_all();
+        assert!(!ta.join().unwrap().unwrap().status.success());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn glob_matches_smc_keys() {
+        let matches = |p: &str, keys: &[&str]| -> Vec<String> {
+            keys.iter()
+                .filter(|k| glob_match(p, k))
+                .map(|k| k.to_string())
+                .collect()
+        };
+        let keys = ["F0Ac", "F1Ac", "F0Mn", "TG0P", "Tp09", "#KEY"];
+        assert_eq!(matches("T*", &keys), ["TG0P", "Tp09"]);
+        assert_eq!(matches("F?Ac", &keys), ["F0Ac", "F1Ac"]);
+        assert_eq!(matches("*Ac", &keys), ["F0Ac", "F1Ac"]);
+        assert_eq!(matches("F*A*", &keys), ["F0Ac", "F1Ac"]);
+        assert_eq!(matches("*", &keys).len(), keys.len());
+        // Anchored at both ends, and case matters like it does for the SMC
+        assert!(glob_match("F0Ac", "F0Ac"));
+        assert!(!glob_match("F0A", "F0Ac"));
+        assert!(!glob_match("0Ac", "F0Ac"));
+        assert!(!glob_match("f0ac", "F0Ac"));
+        // '?' is exactly one character
+        assert!(!glob_match("F?Ac", "FAc"));
+        assert!(!glob_match("????", "F0Acx"));
+        // Backtracking past an early match of the suffix
+        assert!(glob_match("*ab", "aabab"));
+        assert!(!glob_match("*ab", "abba"));
+        // The empty pattern only matches the empty key, '*' matches it too
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "F0Ac"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", ""));
+        assert!(!glob_match("?", ""));
+    }
+}